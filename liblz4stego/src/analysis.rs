@@ -0,0 +1,189 @@
+//! Steganalysis of LZ4 frames: checks how the matches choose between equally
+//! long previous occurrences. Regular compressors mostly take the nearest
+//! one, while data hidden by the default mode makes every choice equally
+//! likely.
+
+use std::io::Read;
+
+use crate::candidate_policy::CandidatePolicy;
+use crate::decompressor::Decompressor;
+use crate::errors::DecompressResult;
+use crate::occurrence_map::OccurrenceMap;
+use crate::offset_distribution::{OffsetDistribution, MAX_RANKS};
+
+/// Statistics of a single block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockAnalysis {
+    pub compressed: bool,
+    /// Decompressed size in bytes
+    pub size: usize,
+    pub match_count: usize,
+    /// Matches with several equally long candidates
+    pub choice_count: usize,
+    /// Choices of a candidate other than the nearest one
+    pub non_nearest_count: usize,
+    /// Matches shorter than the longest candidate at their position
+    pub non_longest_count: usize,
+    /// Number of choices per recency rank, the last entry counting all the
+    /// higher ranks as well
+    pub rank_histogram: Vec<usize>,
+    /// Bits the choices could carry if they were all made uniformly
+    pub capacity_bits: f64,
+    /// Log-likelihood ratio of the choices being uniform rather than following
+    /// the distribution of the reference `lz4` tool
+    pub log_likelihood_ratio: f64,
+}
+
+impl BlockAnalysis {
+    pub(crate) fn new_uncompressed(size: usize) -> Self {
+        Self {
+            compressed: false,
+            size,
+            match_count: 0,
+            choice_count: 0,
+            non_nearest_count: 0,
+            non_longest_count: 0,
+            rank_histogram: vec![0; MAX_RANKS],
+            capacity_bits: 0.0,
+            log_likelihood_ratio: 0.0,
+        }
+    }
+
+    pub fn non_nearest_fraction(&self) -> f64 {
+        get_fraction(self.non_nearest_count, self.choice_count)
+    }
+
+    /// Probability, between 0 and 1, that the choices carry hidden data; 0 if
+    /// there are none.
+    pub fn suspicion(&self) -> f64 {
+        get_suspicion(self.log_likelihood_ratio, self.choice_count)
+    }
+
+    /// Expected number of hidden bits in the block.
+    pub fn estimated_bits(&self) -> f64 {
+        self.suspicion() * self.capacity_bits
+    }
+}
+
+/// Statistics of a whole frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Analysis {
+    /// Whether the frame is preceded by an lz4stego settings frame
+    pub stego_header: bool,
+    pub blocks: Vec<BlockAnalysis>,
+}
+
+impl Analysis {
+    pub fn choice_count(&self) -> usize {
+        self.blocks.iter().map(|block| block.choice_count).sum()
+    }
+
+    pub fn non_nearest_count(&self) -> usize {
+        self.blocks
+            .iter()
+            .map(|block| block.non_nearest_count)
+            .sum()
+    }
+
+    pub fn non_nearest_fraction(&self) -> f64 {
+        get_fraction(self.non_nearest_count(), self.choice_count())
+    }
+
+    pub fn rank_histogram(&self) -> Vec<usize> {
+        let mut histogram = vec![0; MAX_RANKS];
+        for block in &self.blocks {
+            for (total, count) in histogram.iter_mut().zip(&block.rank_histogram) {
+                *total += count;
+            }
+        }
+
+        histogram
+    }
+
+    pub fn estimated_bits(&self) -> f64 {
+        self.blocks.iter().map(|block| block.estimated_bits()).sum()
+    }
+
+    /// Probability that the frame carries hidden data; certain if it has an
+    /// lz4stego settings frame.
+    pub fn suspicion(&self) -> f64 {
+        if self.stego_header {
+            return 1.0;
+        }
+
+        get_suspicion(
+            self.blocks
+                .iter()
+                .map(|block| block.log_likelihood_ratio)
+                .sum(),
+            self.choice_count(),
+        )
+    }
+}
+
+/// Analyzes an LZ4 frame, checking its content checksum along the way.
+pub fn analyze<R: Read>(input_read: R) -> DecompressResult<Analysis> {
+    let mut decompressor = Decompressor::new_analyzing(input_read);
+    std::io::copy(&mut decompressor, &mut std::io::sink())?;
+
+    Ok(decompressor.finish_analysis())
+}
+
+/// Rebuilds the candidate sets of the matches of a block, given as position,
+/// source position and length.
+pub(crate) fn analyze_block(data: &[u8], matches: &[(u32, u32, u32)]) -> BlockAnalysis {
+    let reference = OffsetDistribution::reference();
+    let mut analysis = BlockAnalysis::new_uncompressed(data.len());
+    analysis.compressed = true;
+    analysis.match_count = matches.len();
+    let mut occur = OccurrenceMap::new(data, CandidatePolicy::Longest);
+
+    for (index, match_index, _) in matches {
+        let index = *index as usize;
+        occur.index_until(index);
+
+        let occurrences = occur.get_occurrences(index);
+        let value = match occurrences.get_occurrence_index(*match_index as usize) {
+            Some(value) => value,
+            None => {
+                analysis.non_longest_count += 1;
+                continue;
+            }
+        };
+        let count = occurrences.len();
+        if count < 2 {
+            continue;
+        }
+
+        let rank = count - 1 - value;
+        analysis.choice_count += 1;
+        analysis.rank_histogram[rank.min(MAX_RANKS - 1)] += 1;
+        if rank > 0 {
+            analysis.non_nearest_count += 1;
+        }
+        analysis.capacity_bits += (count as f64).log2();
+
+        let weights = reference.get_candidate_weights(count);
+        let total_weight: u64 = weights.iter().map(|weight| *weight as u64).sum();
+        let natural_probability = weights[value] as f64 / total_weight as f64;
+        analysis.log_likelihood_ratio += (1.0 / count as f64 / natural_probability).ln();
+    }
+
+    analysis
+}
+
+fn get_fraction(count: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.0
+    } else {
+        count as f64 / total as f64
+    }
+}
+
+fn get_suspicion(log_likelihood_ratio: f64, choice_count: usize) -> f64 {
+    if choice_count == 0 {
+        return 0.0;
+    }
+
+    1.0 / (1.0 + (-log_likelihood_ratio).exp())
+}
@@ -0,0 +1,319 @@
+//! Tokio adapters of the compressor and the decompressor. The hidden data is
+//! embedded and extracted exactly as with `Compressor` and `Decompressor`;
+//! the compression runs on the blocking thread pool, as a block may take long
+//! to compress.
+
+use std::future::Future;
+use std::io::Write;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::task::JoinHandle;
+
+use crate::builder::CompressorBuilder;
+use crate::compressor::{OwnedCompressor, Progress};
+use crate::errors::OptionsError;
+use crate::push_decoder::PushDecoder;
+use crate::report::Report;
+use crate::HiddenDataMode;
+
+const INPUT_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Compressor writing to an `AsyncWrite`. The written data is compressed on
+/// the blocking thread pool in chunks of up to 64 KiB, so it has to be used
+/// within a Tokio runtime. The frame is ended by `poll_shutdown`, after which
+/// the report is available.
+pub struct AsyncCompressor<W: AsyncWrite + Unpin> {
+    writer: W,
+    state: CompressorState,
+    /// Data written but not passed to the compressor yet
+    input: Vec<u8>,
+    progress: Progress,
+    compressed: SharedBuffer,
+    pending: Vec<u8>,
+    pending_start: usize,
+    report: Option<Report>,
+}
+
+enum CompressorState {
+    Idle(Box<OwnedCompressor<SharedBuffer>>),
+    Writing(JoinHandle<(Box<OwnedCompressor<SharedBuffer>>, std::io::Result<()>)>),
+    Finishing(JoinHandle<std::io::Result<Report>>),
+    Finished,
+}
+
+impl<W: AsyncWrite + Unpin> AsyncCompressor<W> {
+    /// The hidden data is copied, so that the compressor can be moved to the
+    /// blocking thread pool; see `new_owned`.
+    pub fn new(
+        writer: W,
+        options: &CompressorBuilder,
+        hidden_data: &[u8],
+    ) -> Result<Self, OptionsError> {
+        Self::new_owned(writer, options, hidden_data.to_vec())
+    }
+
+    /// Like `new`, taking the ownership of the hidden data.
+    pub fn new_owned(
+        writer: W,
+        options: &CompressorBuilder,
+        hidden_data: Vec<u8>,
+    ) -> Result<Self, OptionsError> {
+        let compressed = SharedBuffer::default();
+        let compressor = options.build_owned(compressed.clone(), hidden_data)?;
+        Ok(Self {
+            writer,
+            progress: compressor.get_progress(),
+            state: CompressorState::Idle(Box::new(compressor)),
+            input: Vec::new(),
+            compressed,
+            pending: Vec::new(),
+            pending_start: 0,
+            report: None,
+        })
+    }
+
+    /// The progress of the data passed to the compressor so far, `None` once
+    /// the frame is finished.
+    pub fn get_progress(&self) -> Option<Progress> {
+        match self.state {
+            CompressorState::Finishing(_) | CompressorState::Finished => None,
+            _ => Some(self.progress),
+        }
+    }
+
+    /// The statistics of the stream, once `poll_shutdown` completed.
+    pub fn get_report(&self) -> Option<&Report> {
+        self.report.as_ref()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+
+    /// Waits until the compressor is done with the data passed to it.
+    fn poll_compressor(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        match &mut self.state {
+            CompressorState::Writing(handle) => {
+                let (compressor, result) = ready!(Pin::new(handle).poll(cx))?;
+                self.progress = compressor.get_progress();
+                self.state = CompressorState::Idle(compressor);
+                Poll::Ready(result)
+            }
+            CompressorState::Finishing(handle) => {
+                let result = ready!(Pin::new(handle).poll(cx));
+                self.state = CompressorState::Finished;
+                self.report = Some(result??);
+                Poll::Ready(Ok(()))
+            }
+            _ => Poll::Ready(Ok(())),
+        }
+    }
+
+    /// Passes the buffered input to the compressor, ending the frame as well
+    /// if `finish` is set.
+    fn start_compressor(&mut self, finish: bool) -> std::io::Result<()> {
+        let mut compressor = match std::mem::replace(&mut self.state, CompressorState::Finished) {
+            CompressorState::Idle(compressor) => compressor,
+            CompressorState::Finished => return Err(get_finished_error()),
+            _ => unreachable!("the compressor is still busy"),
+        };
+        let input = std::mem::take(&mut self.input);
+
+        self.state = if finish {
+            CompressorState::Finishing(tokio::task::spawn_blocking(move || {
+                compressor.write_all(&input)?;
+                compressor.finish()
+            }))
+        } else {
+            CompressorState::Writing(tokio::task::spawn_blocking(move || {
+                let result = compressor.write_all(&input);
+                (compressor, result)
+            }))
+        };
+
+        Ok(())
+    }
+
+    /// Writes out the data compressed so far.
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        loop {
+            if self.pending_start == self.pending.len() {
+                self.pending.clear();
+                self.pending_start = 0;
+                std::mem::swap(&mut self.pending, &mut self.compressed.0.lock().unwrap());
+                if self.pending.is_empty() {
+                    return Poll::Ready(Ok(()));
+                }
+            }
+
+            let count = ready!(
+                Pin::new(&mut self.writer).poll_write(cx, &self.pending[self.pending_start..])
+            )?;
+            if count == 0 {
+                return Poll::Ready(Err(std::io::ErrorKind::WriteZero.into()));
+            }
+            self.pending_start += count;
+        }
+    }
+}
+
+impl<W: AsyncWrite + Unpin> AsyncWrite for AsyncCompressor<W> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        if this.input.len() == INPUT_BUFFER_SIZE {
+            ready!(this.poll_compressor(cx))?;
+            this.start_compressor(false)?;
+        }
+        if matches!(
+            this.state,
+            CompressorState::Finishing(_) | CompressorState::Finished
+        ) {
+            return Poll::Ready(Err(get_finished_error()));
+        }
+        ready!(this.poll_pending(cx))?;
+
+        let count = buf.len().min(INPUT_BUFFER_SIZE - this.input.len());
+        this.input.extend_from_slice(&buf[..count]);
+
+        Poll::Ready(Ok(count))
+    }
+
+    /// Compresses the data written so far and writes out the blocks done.
+    /// Unlike `Compressor::flush`, the current block is not ended, as that
+    /// would change the output and its capacity depending on how the writes
+    /// are flushed.
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_compressor(cx))?;
+        if !this.input.is_empty() {
+            this.start_compressor(false)?;
+            ready!(this.poll_compressor(cx))?;
+        }
+        ready!(this.poll_pending(cx))?;
+
+        Pin::new(&mut this.writer).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_compressor(cx))?;
+        if let CompressorState::Idle(_) = this.state {
+            this.start_compressor(true)?;
+            ready!(this.poll_compressor(cx))?;
+        }
+        ready!(this.poll_pending(cx))?;
+
+        Pin::new(&mut this.writer).poll_shutdown(cx)
+    }
+}
+
+fn get_finished_error() -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::BrokenPipe,
+        "Compressor is already finished",
+    )
+}
+
+/// Output of the synchronous compressor, moved to the async writer.
+#[derive(Clone, Default)]
+struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Decompressor reading from an `AsyncRead`; see `PushDecoder`.
+pub struct AsyncDecompressor<R: AsyncRead + Unpin> {
+    reader: R,
+    decoder: PushDecoder,
+    input: Vec<u8>,
+    output: Vec<u8>,
+    output_start: usize,
+    /// Hidden data extracted and not read yet
+    hidden_data: Vec<u8>,
+}
+
+impl<R: AsyncRead + Unpin> AsyncDecompressor<R> {
+    pub fn new(reader: R, mode: HiddenDataMode) -> Self {
+        Self {
+            reader,
+            decoder: PushDecoder::new(mode),
+            input: vec![0; INPUT_BUFFER_SIZE],
+            output: Vec::new(),
+            output_start: 0,
+            hidden_data: Vec::new(),
+        }
+    }
+
+    /// The hidden data extracted and not read with `read_hidden_data` yet.
+    pub fn get_hidden_data(&self) -> &[u8] {
+        &self.hidden_data
+    }
+
+    /// Moves the hidden data extracted so far to `buf`, returning the number
+    /// of bytes moved, like `Decompressor::read_hidden_data`. The hidden data
+    /// is kept until it is read, so reading it along with the decompressed
+    /// data keeps the memory used bounded.
+    pub fn read_hidden_data(&mut self, buf: &mut [u8]) -> usize {
+        let count = buf.len().min(self.hidden_data.len());
+        buf[..count].copy_from_slice(&self.hidden_data[..count]);
+        self.hidden_data.drain(..count);
+
+        count
+    }
+
+    /// Returns the hidden data not read with `read_hidden_data` yet; it is
+    /// complete once the decompressed data was read to the end.
+    pub fn finish(self) -> Vec<u8> {
+        self.hidden_data
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for AsyncDecompressor<R> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        while this.output_start == this.output.len() {
+            if this.decoder.is_finished() {
+                return Poll::Ready(Ok(()));
+            }
+
+            let mut input = ReadBuf::new(&mut this.input);
+            ready!(Pin::new(&mut this.reader).poll_read(cx, &mut input))?;
+            if input.filled().is_empty() {
+                return Poll::Ready(Err(std::io::Error::new(
+                    std::io::ErrorKind::UnexpectedEof,
+                    "Compressed data is incomplete",
+                )));
+            }
+
+            let decoded = this.decoder.feed(input.filled())?;
+            this.output = decoded.output;
+            this.output_start = 0;
+            this.hidden_data.extend_from_slice(&decoded.hidden_data);
+        }
+
+        let count = buf.remaining().min(this.output.len() - this.output_start);
+        buf.put_slice(&this.output[this.output_start..this.output_start + count]);
+        this.output_start += count;
+
+        Poll::Ready(Ok(()))
+    }
+}
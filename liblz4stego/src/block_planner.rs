@@ -0,0 +1,82 @@
+use crate::candidate_policy::CandidatePolicy;
+
+/// Candidate policies tried by the planner, ordered from the best compression
+/// ratio to the highest hidden data capacity.
+pub const PLANNER_POLICIES: [CandidatePolicy; 7] = [
+    CandidatePolicy::Longest,
+    CandidatePolicy::WithinBytes(1),
+    CandidatePolicy::WithinBytes(2),
+    CandidatePolicy::WithinBytes(4),
+    CandidatePolicy::WithinBytes(8),
+    CandidatePolicy::WithinPercent(50),
+    CandidatePolicy::Any,
+];
+
+/// Index of the candidate policy used by a block, transmitted as mixed-radix
+/// digits in the first matches of the block, which always use the longest
+/// match policy. Blocks that end before the index is complete carry no hidden
+/// data.
+#[derive(Debug, Clone, Copy)]
+pub struct PolicySignal {
+    value: usize,
+    product: usize,
+    policy_count: usize,
+}
+
+impl PolicySignal {
+    pub fn new(value: usize, policy_count: usize) -> Self {
+        Self {
+            value,
+            product: 1,
+            policy_count,
+        }
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.product >= self.policy_count
+    }
+
+    pub fn encode_digit(&mut self, max_value: usize) -> u16 {
+        let digit = (self.value / self.product) % max_value;
+        self.product = self.product.saturating_mul(max_value);
+
+        digit as u16
+    }
+
+    pub fn add_digit(&mut self, value: usize, max_value: usize) {
+        self.value += value * self.product;
+        self.product = self.product.saturating_mul(max_value);
+    }
+
+    pub fn value(&self) -> usize {
+        self.value
+    }
+}
+
+pub struct Planner {
+    pub policies: Vec<CandidatePolicy>,
+    pub hidden_bits: f64,
+    pub input_remaining: usize,
+}
+
+impl Planner {
+    pub fn new(hidden_data_len: usize, input_size: usize) -> Self {
+        Self {
+            policies: PLANNER_POLICIES.to_vec(),
+            hidden_bits: hidden_data_len as f64 * 8.0,
+            input_remaining: input_size,
+        }
+    }
+
+    /// Number of hidden bits the next block should carry for the hidden data to
+    /// be spread evenly over the remaining input.
+    pub fn block_quota(&self, block_len: usize, available_bits: f64) -> f64 {
+        let remaining_bits = self.hidden_bits - available_bits;
+        if remaining_bits <= 0.0 {
+            return 0.0;
+        }
+
+        let input_remaining = self.input_remaining.max(block_len);
+        remaining_bits * block_len as f64 / input_remaining as f64
+    }
+}
@@ -0,0 +1,311 @@
+//! Configuration of every compressor setting at once, checked before any
+//! data is written.
+
+use std::io::{Read, Write};
+
+use crate::candidate_policy::CandidatePolicy;
+use crate::compressor::OwnedCompressor;
+use crate::compressor::{get_block_max_size, BlockOptions, Compressor, FrameOptions};
+use crate::constants::MAX_BLOCK_SIZE;
+use crate::errors::OptionsError;
+use crate::hidden_source::HiddenSource;
+use crate::offset_distribution::OffsetDistribution;
+
+/// Block maximum size declared in the frame descriptor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockMaxSize {
+    Max64KB,
+    Max256KB,
+    Max1MB,
+    Max4MB,
+}
+
+impl BlockMaxSize {
+    fn get_id(self) -> u8 {
+        match self {
+            BlockMaxSize::Max64KB => 4,
+            BlockMaxSize::Max256KB => 5,
+            BlockMaxSize::Max1MB => 6,
+            BlockMaxSize::Max4MB => 7,
+        }
+    }
+
+    pub fn get_size(self) -> usize {
+        get_block_max_size(self.get_id())
+    }
+}
+
+/// How the input is parsed into sequences, matching the `Compressor`
+/// constructors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseMode {
+    /// Greedy or lazy parse hiding data in every candidate choice; see
+    /// `Compressor::new_with_candidate_policy`
+    Default,
+    /// Parse of the reference `lz4` tool with the given acceleration, without
+    /// hidden data; see `Compressor::new_fast`
+    Fast(u32),
+    /// Reference mimicry with the given acceleration; see
+    /// `Compressor::new_reference_with_hidden_data`
+    Reference(u32),
+    /// Syndrome-trellis coding with the given ratio; see
+    /// `Compressor::new_trellis_with_hidden_data`
+    Trellis(usize),
+    /// Guaranteed capacity mode for an input of the given size; see
+    /// `Compressor::new_planned_with_hidden_data`
+    Planned(usize),
+}
+
+impl ParseMode {
+    fn get_name(self) -> &'static str {
+        match self {
+            ParseMode::Default => "default mode",
+            ParseMode::Fast(_) => "fast mode",
+            ParseMode::Reference(_) => "reference mode",
+            ParseMode::Trellis(_) => "trellis mode",
+            ParseMode::Planned(_) => "planned mode",
+        }
+    }
+
+    fn is_greedy(self) -> bool {
+        matches!(self, ParseMode::Default | ParseMode::Planned(_))
+    }
+}
+
+/// Builds a `Compressor` from any combination of frame and hidden data
+/// settings, returning an `OptionsError` for invalid ones.
+#[derive(Clone)]
+pub struct CompressorBuilder {
+    frame: FrameOptions,
+    mode: ParseMode,
+    candidate_policy: CandidatePolicy,
+    search_depth: usize,
+    literal_channel: Option<usize>,
+    match_truncation: Option<usize>,
+    block_channel: Option<(usize, usize)>,
+    offset_distribution: Option<OffsetDistribution>,
+}
+
+impl Default for CompressorBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl CompressorBuilder {
+    /// The settings of `Compressor::new_with_hidden_data` without
+    /// `prefer_hidden`.
+    pub fn new() -> Self {
+        Self {
+            frame: FrameOptions::default(),
+            mode: ParseMode::Default,
+            candidate_policy: CandidatePolicy::Longest,
+            search_depth: 0,
+            literal_channel: None,
+            match_truncation: None,
+            block_channel: None,
+            offset_distribution: None,
+        }
+    }
+
+    /// By default, 4 MiB, or as small as the input allows in the fast and
+    /// reference modes.
+    pub fn block_size(mut self, block_size: BlockMaxSize) -> Self {
+        self.frame.block_size_id = Some(block_size.get_id());
+        self
+    }
+
+    /// Only declares whether blocks may refer to the previous ones; they never
+    /// do.
+    pub fn block_independent(mut self, block_independent: bool) -> Self {
+        self.frame.block_independent = block_independent;
+        self
+    }
+
+    pub fn block_checksum(mut self, block_checksum: bool) -> Self {
+        self.frame.block_checksum = block_checksum;
+        self
+    }
+
+    pub fn content_checksum(mut self, content_checksum: bool) -> Self {
+        self.frame.content_checksum = content_checksum;
+        self
+    }
+
+    /// Declares the size of the input in the frame descriptor; `finish` fails
+    /// if a different number of bytes is written.
+    pub fn content_size(mut self, content_size: u64) -> Self {
+        self.frame.content_size = Some(content_size);
+        self
+    }
+
+    pub fn mode(mut self, mode: ParseMode) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    /// Only in the default mode.
+    pub fn candidate_policy(mut self, candidate_policy: CandidatePolicy) -> Self {
+        self.candidate_policy = candidate_policy;
+        self
+    }
+
+    /// Number of following positions checked for a better match before one is
+    /// emitted, as in `Compressor::new_hc_with_hidden_data`; 0 for the greedy
+    /// parse.
+    pub fn search_depth(mut self, search_depth: usize) -> Self {
+        self.search_depth = search_depth;
+        self
+    }
+
+    /// See `Compressor::with_literal_channel`.
+    pub fn literal_channel(mut self, max_match_length: usize) -> Self {
+        self.literal_channel = Some(max_match_length);
+        self
+    }
+
+    /// See `Compressor::with_match_truncation`.
+    pub fn match_truncation(mut self, max_truncation: usize) -> Self {
+        self.match_truncation = Some(max_truncation);
+        self
+    }
+
+    /// See `Compressor::with_block_size_channel`.
+    pub fn block_size_channel(mut self, block_size: usize, range: usize) -> Self {
+        self.block_channel = Some((block_size, range));
+        self
+    }
+
+    /// See `Compressor::with_offset_distribution`.
+    pub fn offset_distribution(mut self, offset_distribution: OffsetDistribution) -> Self {
+        self.offset_distribution = Some(offset_distribution);
+        self
+    }
+
+    pub fn validate(&self) -> Result<(), OptionsError> {
+        let mode = self.mode.get_name();
+
+        match self.mode {
+            ParseMode::Fast(0) | ParseMode::Reference(0) => {
+                return Err(OptionsError::InvalidValue("acceleration"));
+            }
+            ParseMode::Trellis(0) => return Err(OptionsError::InvalidValue("trellis ratio")),
+            _ => {}
+        }
+
+        if self.candidate_policy != CandidatePolicy::Longest && self.mode != ParseMode::Default {
+            return Err(OptionsError::Conflict("candidate policy", mode));
+        }
+        if self.search_depth > 0 && !self.mode.is_greedy() {
+            return Err(OptionsError::Conflict("search depth", mode));
+        }
+        if self.literal_channel.is_some() && self.search_depth > 0 {
+            return Err(OptionsError::Conflict("literal channel", "search depth"));
+        }
+
+        let channels = [
+            ("literal channel", self.literal_channel.is_some()),
+            ("match truncation", self.match_truncation.is_some()),
+            ("block size channel", self.block_channel.is_some()),
+            ("offset distribution", self.offset_distribution.is_some()),
+        ];
+        if let Some((channel, _)) = channels
+            .iter()
+            .find(|(_, enabled)| *enabled && !self.mode.is_greedy())
+        {
+            return Err(OptionsError::Conflict(channel, mode));
+        }
+
+        if let Some(offset_distribution) = self.offset_distribution {
+            if !offset_distribution.is_valid() {
+                return Err(OptionsError::InvalidValue("offset distribution"));
+            }
+        }
+        if let Some((block_size, range)) = self.block_channel {
+            let max_block_size = match self.frame.block_size_id {
+                Some(block_size_id) => get_block_max_size(block_size_id),
+                None => MAX_BLOCK_SIZE,
+            };
+            if block_size > max_block_size
+                || !(2..=block_size.min(u16::MAX as usize)).contains(&range)
+            {
+                return Err(OptionsError::InvalidValue("block size channel"));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Validates the settings and creates the compressor. There cannot be any
+    /// hidden data in the fast mode.
+    pub fn build<'a, W: Write>(
+        &self,
+        writer: W,
+        hidden_data: &'a [u8],
+    ) -> Result<Compressor<'a, W>, OptionsError> {
+        self.build_from_source(writer, HiddenSource::new(hidden_data))
+    }
+
+    /// Like `build`, taking the ownership of the hidden data, so that the
+    /// compressor does not borrow anything.
+    pub fn build_owned<W: Write>(
+        &self,
+        writer: W,
+        hidden_data: Vec<u8>,
+    ) -> Result<OwnedCompressor<W>, OptionsError> {
+        self.build_from_source(writer, HiddenSource::from_vec(hidden_data))
+    }
+
+    /// Like `build`, reading the hidden data lazily from `reader`; see
+    /// `Compressor::new_with_hidden_reader`. The trellis and planned modes need
+    /// the length of the data in advance, so they cannot be used.
+    pub fn build_with_reader<'a, W: Write, R: Read + Send + 'a>(
+        &self,
+        writer: W,
+        reader: R,
+    ) -> Result<Compressor<'a, W>, OptionsError> {
+        match self.mode {
+            ParseMode::Fast(_) | ParseMode::Trellis(_) | ParseMode::Planned(_) => Err(
+                OptionsError::Conflict("hidden data reader", self.mode.get_name()),
+            ),
+            _ => self.build_from_source(writer, HiddenSource::from_reader(reader)),
+        }
+    }
+
+    fn build_from_source<'a, W: Write>(
+        &self,
+        writer: W,
+        hidden_source: HiddenSource<'a>,
+    ) -> Result<Compressor<'a, W>, OptionsError> {
+        self.validate()?;
+        if matches!(self.mode, ParseMode::Fast(_)) && hidden_source.get_len() != Some(0) {
+            return Err(OptionsError::Conflict("hidden data", self.mode.get_name()));
+        }
+
+        let mut options = BlockOptions::new(self.candidate_policy);
+        options.lookahead = self.search_depth;
+        options.literal_channel = self.literal_channel;
+        options.match_truncation = self.match_truncation;
+        options.block_channel = self.block_channel;
+        options.offset_distribution = self.offset_distribution;
+        let mut input_size = None;
+        match self.mode {
+            ParseMode::Default => {}
+            ParseMode::Fast(acceleration) => options.acceleration = Some(acceleration),
+            ParseMode::Reference(acceleration) => {
+                options.acceleration = Some(acceleration);
+                options.reference_parse = true;
+            }
+            ParseMode::Trellis(ratio) => options.trellis_ratio = Some(ratio),
+            ParseMode::Planned(size) => input_size = Some(size),
+        }
+
+        Ok(Compressor::from_options(
+            writer,
+            hidden_source,
+            options,
+            self.frame,
+            input_size,
+        ))
+    }
+}
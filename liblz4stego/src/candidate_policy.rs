@@ -0,0 +1,59 @@
+use crate::constants::MATCH_LENGTH_OFFSET;
+
+/// Decides which of the previous occurrences at a position are offered as
+/// match candidates, trading compression ratio for hidden data capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CandidatePolicy {
+    /// Only the longest matches; the default mode.
+    Longest,
+    /// Matches at most the given number of bytes shorter than the longest one.
+    WithinBytes(u32),
+    /// Matches at most the given percentage shorter than the longest one.
+    WithinPercent(u32),
+    /// Any match, regardless of its length; the prefer-hidden mode.
+    Any,
+}
+
+impl CandidatePolicy {
+    pub fn from_prefer_hidden(prefer_hidden: bool) -> Self {
+        if prefer_hidden {
+            CandidatePolicy::Any
+        } else {
+            CandidatePolicy::Longest
+        }
+    }
+
+    pub fn accepts(&self, match_length: usize, max_match_length: usize) -> bool {
+        match *self {
+            CandidatePolicy::Longest => match_length == max_match_length,
+            CandidatePolicy::WithinBytes(bytes) => {
+                match_length >= MATCH_LENGTH_OFFSET as usize
+                    && match_length + bytes as usize >= max_match_length
+            }
+            CandidatePolicy::WithinPercent(percent) => {
+                match_length >= MATCH_LENGTH_OFFSET as usize
+                    && match_length * 100 >= max_match_length * (100 - percent.min(100) as usize)
+            }
+            CandidatePolicy::Any => true,
+        }
+    }
+
+    pub(crate) fn to_raw(self) -> (u8, u32) {
+        match self {
+            CandidatePolicy::Longest => (0, 0),
+            CandidatePolicy::WithinBytes(bytes) => (1, bytes),
+            CandidatePolicy::WithinPercent(percent) => (2, percent),
+            CandidatePolicy::Any => (3, 0),
+        }
+    }
+
+    pub(crate) fn from_raw(kind: u8, param: u32) -> Option<Self> {
+        match kind {
+            0 => Some(CandidatePolicy::Longest),
+            1 => Some(CandidatePolicy::WithinBytes(param)),
+            2 if param <= 100 => Some(CandidatePolicy::WithinPercent(param)),
+            3 => Some(CandidatePolicy::Any),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! Hidden data capacity of a cover, found by compressing it without writing
+//! the output, with the same parser as the compressor.
+
+use std::io::Write;
+
+use crate::candidate_policy::CandidatePolicy;
+use crate::compressor::Compressor;
+use crate::constants::MAX_BLOCK_SIZE;
+
+/// Capacity of a single block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockCapacity {
+    /// Uncompressed size in bytes
+    pub size: usize,
+    pub bits: f64,
+}
+
+/// Capacity of a cover with one candidate policy.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PolicyCapacity {
+    pub policy: CandidatePolicy,
+    pub blocks: Vec<BlockCapacity>,
+    pub bits: f64,
+    /// Bytes of hidden data that fit, as reported by `Compressor::finish`
+    pub bytes: usize,
+    /// Whether the capacity holds for any hidden data; otherwise it is an
+    /// estimate
+    pub exact: bool,
+}
+
+/// Capacity of `data` with each of `policies`, compressed with the default
+/// settings. Except in the `Longest` mode, the matches depend on the hidden
+/// data: the choices decide which positions the following matches start at,
+/// and so which candidates they have. The capacity is then an estimate,
+/// measured with hidden data of zeros, and the actual one may be lower or
+/// higher.
+pub fn get_capacity(data: &[u8], policies: &[CandidatePolicy]) -> Vec<PolicyCapacity> {
+    policies
+        .iter()
+        .map(|policy| get_policy_capacity(data, *policy))
+        .collect()
+}
+
+fn get_policy_capacity(data: &[u8], policy: CandidatePolicy) -> PolicyCapacity {
+    let mut compressor =
+        Compressor::new_with_candidate_policy(std::io::sink(), &[], policy).unwrap();
+    let mut blocks = Vec::new();
+    let mut bits = 0.0;
+
+    for block in data.chunks(MAX_BLOCK_SIZE) {
+        // Writing a whole block, or flushing the last one, compresses it
+        compressor.write_all(block).unwrap();
+        compressor.flush().unwrap();
+
+        let total_bits = compressor.get_available_bits();
+        blocks.push(BlockCapacity {
+            size: block.len(),
+            bits: total_bits - bits,
+        });
+        bits = total_bits;
+    }
+
+    PolicyCapacity {
+        policy,
+        blocks,
+        bits,
+        bytes: compressor.finish().unwrap().hidden_bytes,
+        exact: policy == CandidatePolicy::Longest,
+    }
+}
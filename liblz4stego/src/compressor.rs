@@ -3,23 +3,128 @@ use std::cmp::min;
 use log::debug;
 use xxhash_rust::xxh32::{xxh32, Xxh32};
 
+use crate::block_planner::{Planner, PolicySignal};
+use crate::candidate_policy::CandidatePolicy;
 use crate::constants::{
     END_LITERAL_NUM, LZ4_MAGIC_NUMBER, MATCH_LENGTH_OFFSET, MAX_BLOCK_SIZE, MIN_COMPRESS_LENGTH,
-    TOKEN_MAX_VAL,
+    REFERENCE_BLOCK_SIZE, TOKEN_MAX_VAL,
 };
 use crate::descriptors::{BdByte, BlockSize, FlgByte, Token};
+use crate::errors::OptionsError;
+use crate::fast_compressor;
+use crate::hidden_source::HiddenSource;
 use crate::numeral_coding;
-use crate::occurrence_map::OccurrenceMap;
+use crate::occurrence_map::{get_truncation_count, OccurrenceMap, Occurrences};
+use crate::offset_distribution::{CandidateModels, OffsetDistribution};
+use crate::report::{BlockReport, Report};
+use crate::stego_header::StegoHeader;
+use crate::trellis_coding;
 use byteorder::{WriteBytesExt, LE};
 use std::collections::VecDeque;
-use std::io::Write;
+use std::io::{Read, Write};
+use std::ops::ControlFlow;
 
 pub struct Compressor<'a, W: Write> {
-    output_write: W,
+    output_write: CountingWriter<W>,
     buffer: VecDeque<u8>,
     hash: Xxh32,
+    hidden_source: HiddenSource<'a>,
     hidden_data_encoder: numeral_coding::Decoder<'a>,
-    prefer_hidden: bool,
+    trellis_encoder: Option<trellis_coding::Decoder<'a>>,
+    options: BlockOptions,
+    frame: FrameOptions,
+    planner: Option<Planner>,
+    /// The headers are written with the first output rather than by the
+    /// constructors, as they record the `with_*` settings and, in the fast
+    /// mode, a block size depending on the input length
+    header_written: bool,
+    content_len: u64,
+    block_reports: Vec<BlockReport>,
+    progress_callback: Option<ProgressCallback<'a>>,
+    cancelled: bool,
+}
+
+/// Called with the progress after every block written; `Break` cancels the
+/// compression.
+type ProgressCallback<'a> = Box<dyn FnMut(&Progress) -> ControlFlow<()> + Send + 'a>;
+
+/// Compressor owning its hidden data, which can be moved to another thread.
+pub type OwnedCompressor<W> = Compressor<'static, W>;
+
+#[derive(Clone, Copy)]
+pub(crate) struct BlockOptions {
+    pub candidate_policy: CandidatePolicy,
+    pub acceleration: Option<u32>,
+    pub lookahead: usize,
+    pub literal_channel: Option<usize>,
+    pub match_truncation: Option<usize>,
+    pub block_channel: Option<(usize, usize)>,
+    pub offset_distribution: Option<OffsetDistribution>,
+    pub reference_parse: bool,
+    pub trellis_ratio: Option<usize>,
+}
+
+impl BlockOptions {
+    pub fn new(candidate_policy: CandidatePolicy) -> Self {
+        Self {
+            candidate_policy,
+            acceleration: None,
+            lookahead: 0,
+            literal_channel: None,
+            match_truncation: None,
+            block_channel: None,
+            offset_distribution: None,
+            reference_parse: false,
+            trellis_ratio: None,
+        }
+    }
+}
+
+/// Settings of the LZ4 frame itself, which do not affect the hidden data.
+#[derive(Clone, Copy)]
+pub(crate) struct FrameOptions {
+    /// Block maximum size id of the frame descriptor; chosen by the mode if
+    /// not set
+    pub block_size_id: Option<u8>,
+    pub block_independent: bool,
+    pub block_checksum: bool,
+    pub content_checksum: bool,
+    pub content_size: Option<u64>,
+}
+
+impl FrameOptions {
+    /// Largest compressed block allowed; larger ones are stored uncompressed.
+    fn get_max_compressed_size(&self) -> usize {
+        match self.block_size_id {
+            Some(block_size_id) => get_block_max_size(block_size_id),
+            None => MAX_BLOCK_SIZE,
+        }
+    }
+}
+
+impl Default for FrameOptions {
+    fn default() -> Self {
+        Self {
+            block_size_id: None,
+            block_independent: true,
+            block_checksum: false,
+            content_checksum: true,
+            content_size: None,
+        }
+    }
+}
+
+/// State of a compression in progress; see `Compressor::get_progress`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Progress {
+    pub blocks_written: usize,
+    /// Bytes written to the output, including the headers
+    pub compressed_bytes: u64,
+    /// Bits of the hidden data carried by the blocks written so far
+    pub hidden_bits_used: u64,
+    /// Bits of hidden data the blocks written so far can carry; the hidden
+    /// data fits as long as this keeps up with its length
+    pub hidden_bits_available: u64,
 }
 
 impl<'a, W: Write> Compressor<'a, W> {
@@ -28,44 +133,376 @@ impl<'a, W: Write> Compressor<'a, W> {
         hidden_data: &'a [u8],
         prefer_hidden: bool,
     ) -> Result<Self, std::io::Error> {
-        let mut compressor = Self {
-            output_write: writer,
+        Self::new_with_candidate_policy(
+            writer,
+            hidden_data,
+            CandidatePolicy::from_prefer_hidden(prefer_hidden),
+        )
+    }
+
+    /// Policies other than `Longest` and `Any` are recorded in the stream, so
+    /// the decompressor does not need to be told about them.
+    pub fn new_with_candidate_policy(
+        writer: W,
+        hidden_data: &'a [u8],
+        candidate_policy: CandidatePolicy,
+    ) -> Result<Self, std::io::Error> {
+        Ok(Self::from_options(
+            writer,
+            HiddenSource::new(hidden_data),
+            BlockOptions::new(candidate_policy),
+            FrameOptions::default(),
+            None,
+        ))
+    }
+
+    /// Like `new_with_hidden_data`, reading the hidden data from `reader` only
+    /// as the matches need it, so that it does not have to be held in memory.
+    /// Read errors are returned by the following `write`, `flush` or `finish`.
+    pub fn new_with_hidden_reader<R: Read + Send + 'a>(
+        writer: W,
+        reader: R,
+        prefer_hidden: bool,
+    ) -> Result<Self, std::io::Error> {
+        Ok(Self::from_options(
+            writer,
+            HiddenSource::from_reader(reader),
+            BlockOptions::new(CandidatePolicy::from_prefer_hidden(prefer_hidden)),
+            FrameOptions::default(),
+            None,
+        ))
+    }
+
+    /// Compressor for already validated options; see `CompressorBuilder`.
+    /// `input_size` enables the guaranteed capacity mode, which, like the
+    /// trellis mode, needs the length of the hidden data up front.
+    pub(crate) fn from_options(
+        writer: W,
+        hidden_source: HiddenSource<'a>,
+        options: BlockOptions,
+        frame: FrameOptions,
+        input_size: Option<usize>,
+    ) -> Self {
+        let offset_source = if options.trellis_ratio.is_some()
+            || (options.acceleration.is_some() && !options.reference_parse)
+        {
+            HiddenSource::new(b"")
+        } else {
+            hidden_source.clone()
+        };
+        let mut hidden_data_encoder = numeral_coding::Decoder::from_source(offset_source);
+        if options.offset_distribution.is_some() {
+            hidden_data_encoder = hidden_data_encoder.into_shaped();
+        }
+        if options.reference_parse {
+            hidden_data_encoder = hidden_data_encoder.into_legacy();
+        }
+        let hidden_data_len = hidden_source.get_len();
+
+        Self {
+            output_write: CountingWriter::new(writer),
             buffer: VecDeque::new(),
             hash: Xxh32::new(0),
-            hidden_data_encoder: numeral_coding::Decoder::new(hidden_data),
-            prefer_hidden,
-        };
+            trellis_encoder: options
+                .trellis_ratio
+                .map(|ratio| trellis_coding::Decoder::from_source(hidden_source.clone(), ratio)),
+            hidden_source,
+            hidden_data_encoder,
+            options,
+            frame,
+            planner: input_size.map(|input_size| {
+                let hidden_data_len = hidden_data_len.expect("Hidden data length is unknown");
+                Planner::new(hidden_data_len, input_size)
+            }),
+            header_written: false,
+            content_len: 0,
+            block_reports: Vec::new(),
+            progress_callback: None,
+            cancelled: false,
+        }
+    }
 
-        compressor.init()?;
+    /// High compression mode using lazy matching, checking up to `level`
+    /// following positions for a better match before emitting one.
+    pub fn new_hc_with_hidden_data(
+        writer: W,
+        hidden_data: &'a [u8],
+        level: usize,
+    ) -> Result<Self, std::io::Error> {
+        let mut compressor = Self::new_with_hidden_data(writer, hidden_data, false)?;
+        compressor.options.lookahead = level;
 
         Ok(compressor)
     }
 
+    /// Guaranteed capacity mode: the candidate policy is chosen separately for
+    /// each block, so that the hidden data is spread over the whole input with
+    /// the smallest compression ratio loss. `input_size` is the total number of
+    /// bytes that will be written to the compressor.
+    pub fn new_planned_with_hidden_data(
+        writer: W,
+        hidden_data: &'a [u8],
+        input_size: usize,
+    ) -> Result<Self, std::io::Error> {
+        Ok(Self::from_options(
+            writer,
+            HiddenSource::new(hidden_data),
+            BlockOptions::new(CandidatePolicy::Longest),
+            FrameOptions::default(),
+            Some(input_size),
+        ))
+    }
+
     pub fn new(writer: W) -> Result<Self, std::io::Error> {
-        let mut compressor = Self {
-            output_write: writer,
-            buffer: VecDeque::new(),
-            hash: Xxh32::new(0),
-            hidden_data_encoder: numeral_coding::Decoder::new(b""),
-            prefer_hidden: false,
-        };
+        Self::new_fast(writer, 1)
+    }
+
+    /// Plain LZ4 compression without hidden data, producing the same output as
+    /// the reference `lz4` tool with the given acceleration factor (1 for
+    /// `lz4 -1`, N + 1 for `lz4 --fast=N`).
+    pub fn new_fast(writer: W, acceleration: u32) -> Result<Self, std::io::Error> {
+        Ok(Self::from_options(
+            writer,
+            HiddenSource::new(b""),
+            BlockOptions {
+                acceleration: Some(acceleration.max(1)),
+                ..BlockOptions::new(CandidatePolicy::Longest)
+            },
+            FrameOptions::default(),
+            None,
+        ))
+    }
 
-        compressor.init()?;
+    /// Reference mimicry mode: the output has the same structure and frame
+    /// flags as that of `new_fast`, hiding data only in the choice between
+    /// previous occurrences the reference parser would encode identically.
+    /// Nothing is recorded in the stream, so the decompressor has to be created
+    /// with `Decompressor::new_reference`.
+    pub fn new_reference_with_hidden_data(
+        writer: W,
+        hidden_data: &'a [u8],
+        acceleration: u32,
+    ) -> Result<Self, std::io::Error> {
+        Ok(Self::from_options(
+            writer,
+            HiddenSource::new(hidden_data),
+            BlockOptions {
+                acceleration: Some(acceleration.max(1)),
+                reference_parse: true,
+                ..BlockOptions::new(CandidatePolicy::Longest)
+            },
+            FrameOptions::default(),
+            None,
+        ))
+    }
+
+    /// Syndrome-trellis mode: instead of every candidate choice carrying hidden
+    /// digits, each block embeds one hidden bit per `ratio` matches with
+    /// several candidates, taking the nearest candidate wherever possible and
+    /// the second nearest one elsewhere, so that the total extra distance is
+    /// as low as possible. The setting is stored in the stream.
+    pub fn new_trellis_with_hidden_data(
+        writer: W,
+        hidden_data: &'a [u8],
+        ratio: usize,
+    ) -> Result<Self, std::io::Error> {
+        if ratio == 0 {
+            return Err(OptionsError::InvalidValue("trellis ratio").into());
+        }
+        let compressor = Self::from_options(
+            writer,
+            HiddenSource::new(hidden_data),
+            BlockOptions {
+                trellis_ratio: Some(ratio),
+                ..BlockOptions::new(CandidatePolicy::Longest)
+            },
+            FrameOptions::default(),
+            None,
+        );
 
         Ok(compressor)
     }
 
-    fn init(&mut self) -> Result<(), std::io::Error> {
-        self.write_header()?;
+    /// Hides additional digits in whether matches of at most
+    /// `max_match_length` bytes are taken or emitted as literals. The setting
+    /// is stored in the stream. Not compatible with the high compression mode.
+    pub fn with_literal_channel(mut self, max_match_length: usize) -> Result<Self, OptionsError> {
+        self.check_default_parser("literal channel")?;
+        if self.options.lookahead > 0 {
+            return Err(OptionsError::Conflict("literal channel", "search depth"));
+        }
+        self.options.literal_channel = Some(max_match_length);
+        Ok(self)
+    }
 
-        Ok(())
+    /// Fails if the hidden data channel named `channel` cannot be used with
+    /// the parser.
+    fn check_default_parser(&self, channel: &'static str) -> Result<(), OptionsError> {
+        if self.options.trellis_ratio.is_some() {
+            Err(OptionsError::Conflict(channel, "trellis mode"))
+        } else if self.options.reference_parse {
+            Err(OptionsError::Conflict(channel, "reference mode"))
+        } else if self.options.acceleration.is_some() {
+            Err(OptionsError::Conflict(channel, "fast mode"))
+        } else {
+            Ok(())
+        }
     }
 
     fn get_available_bytes(&self) -> usize {
-        self.hidden_data_encoder.get_available_bytes()
+        match &self.trellis_encoder {
+            Some(trellis_encoder) => trellis_encoder.get_available_bytes(),
+            None => self.hidden_data_encoder.get_available_bytes(),
+        }
+    }
+
+    /// Bits the choices made so far carry, before the framing of the hidden
+    /// data.
+    pub(crate) fn get_available_bits(&self) -> f64 {
+        match &self.trellis_encoder {
+            Some(trellis_encoder) => trellis_encoder.get_available_bits() as f64,
+            None => self.hidden_data_encoder.get_available_bits(),
+        }
+    }
+
+    fn get_consumed_bytes(&self) -> usize {
+        match &self.trellis_encoder {
+            Some(trellis_encoder) => trellis_encoder.get_consumed_bytes(),
+            None => self.hidden_data_encoder.get_consumed_bytes(),
+        }
     }
 
-    pub fn finish(mut self) -> Result<usize, std::io::Error> {
+    /// Bits of the hidden data the blocks written so far carry, unlike
+    /// `get_consumed_bytes`, which counts the bytes read ahead by the coder.
+    fn get_used_bits(&self) -> u64 {
+        match &self.trellis_encoder {
+            Some(trellis_encoder) => trellis_encoder.get_used_bits() as u64,
+            None => self.hidden_data_encoder.get_used_bytes() as u64 * 8,
+        }
+    }
+
+    /// Calls `callback` with the progress after every block written, for
+    /// example to display it. If it returns `ControlFlow::Break`, the
+    /// compression is cancelled: the call that wrote the block and every later
+    /// one fail, and the frame is left unfinished.
+    pub fn with_progress_callback<F>(mut self, callback: F) -> Self
+    where
+        F: FnMut(&Progress) -> ControlFlow<()> + Send + 'a,
+    {
+        self.progress_callback = Some(Box::new(callback));
+        self
+    }
+
+    pub fn get_progress(&self) -> Progress {
+        let hidden_bits_available = self.get_available_bytes() as u64 * 8;
+        Progress {
+            blocks_written: self.block_reports.len(),
+            compressed_bytes: self.output_write.count,
+            hidden_bits_used: self.get_used_bits().min(hidden_bits_available),
+            hidden_bits_available,
+        }
+    }
+
+    /// Hides additional digits in how many bytes, up to `max_truncation`, are
+    /// cut off the end of each match. The setting is stored in the stream.
+    pub fn with_match_truncation(mut self, max_truncation: usize) -> Result<Self, OptionsError> {
+        self.check_default_parser("match truncation")?;
+        self.options.match_truncation = Some(max_truncation);
+        Ok(self)
+    }
+
+    /// Hides additional digits in the block sizes: every block except the last
+    /// one is between `block_size - range + 1` and `block_size` bytes long. The
+    /// setting is stored in the stream. Works with incompressible data as well.
+    pub fn with_block_size_channel(
+        mut self,
+        block_size: usize,
+        range: usize,
+    ) -> Result<Self, OptionsError> {
+        self.check_default_parser("block size channel")?;
+        if block_size > self.frame.get_max_compressed_size()
+            || !(2..=block_size.min(u16::MAX as usize)).contains(&range)
+        {
+            return Err(OptionsError::InvalidValue("block size channel"));
+        }
+        self.options.block_channel = Some((block_size, range));
+        Ok(self)
+    }
+
+    /// Shapes the candidate choices to the given distribution of recency
+    /// ranks, so that the offsets are statistically closer to those of a
+    /// regular LZ4 compressor. Lowers the capacity; the cost is returned by
+    /// `finish_with_shaping_cost`. The distribution is stored in the stream.
+    pub fn with_offset_distribution(
+        mut self,
+        offset_distribution: OffsetDistribution,
+    ) -> Result<Self, OptionsError> {
+        self.check_default_parser("offset distribution")?;
+        if !offset_distribution.is_valid() {
+            return Err(OptionsError::InvalidValue("offset distribution"));
+        }
+        self.hidden_data_encoder = self.hidden_data_encoder.into_shaped();
+        self.options.offset_distribution = Some(offset_distribution);
+        Ok(self)
+    }
+
+    fn get_block_size(&self) -> usize {
+        match (
+            self.options.block_channel,
+            self.frame.block_size_id,
+            self.options.acceleration,
+        ) {
+            (Some((block_size, _)), _, _) => block_size,
+            (None, Some(block_size_id), _) => get_block_max_size(block_size_id),
+            (None, None, Some(_)) => REFERENCE_BLOCK_SIZE,
+            (None, None, None) => MAX_BLOCK_SIZE,
+        }
+    }
+
+    /// Like the reference `lz4` tool, the fast modes declare the smallest block
+    /// size holding the whole input if it fits in a single block.
+    fn get_block_size_id(&self, content_size: Option<usize>) -> u8 {
+        if let Some(block_size_id) = self.frame.block_size_id {
+            return block_size_id;
+        }
+
+        match content_size.filter(|_| self.options.acceleration.is_some()) {
+            Some(content_size) => (4..7)
+                .find(|id| content_size <= 1 << (2 * id + 8))
+                .unwrap_or(7),
+            None => 7,
+        }
+    }
+
+    /// Ends the frame, returning the statistics of the stream written. Its
+    /// `hidden_bytes` tells how many bytes of hidden data fit.
+    pub fn finish(self) -> Result<Report, std::io::Error> {
+        Ok(self.finish_frame()?.0)
+    }
+
+    /// Like `finish`, additionally returning how many bytes of capacity were
+    /// lost to the offset distribution shaping.
+    pub fn finish_with_shaping_cost(self) -> Result<(Report, usize), std::io::Error> {
+        self.finish_frame()
+    }
+
+    fn finish_frame(mut self) -> Result<(Report, usize), std::io::Error> {
+        if self.cancelled {
+            return Err(get_cancelled_error());
+        }
+        if self
+            .frame
+            .content_size
+            .is_some_and(|content_size| content_size != self.content_len)
+        {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "Content size does not match the declared one",
+            ));
+        }
+
+        // Until the header is written, the buffer holds the whole input
+        self.write_header(Some(self.buffer.len()))?;
         if !self.buffer.is_empty() {
             self.output_block(true)?;
         }
@@ -73,31 +510,59 @@ impl<'a, W: Write> Compressor<'a, W> {
         self.write_footer()?;
         self.output_write.flush()?;
 
-        Ok(self.get_available_bytes())
+        let shaping_cost_bytes = (self.hidden_data_encoder.get_shaping_cost_bits() / 8.0) as usize;
+        let available_bytes = self.get_available_bytes();
+        let report = Report::new(self.block_reports, self.output_write.count, available_bytes);
+        Ok((report, shaping_cost_bytes))
     }
 
-    fn write_header(&mut self) -> Result<(), std::io::Error> {
+    fn write_header(&mut self, content_size: Option<usize>) -> Result<(), std::io::Error> {
+        if self.header_written {
+            return Ok(());
+        }
+        self.header_written = true;
+
+        let mut stego_header = StegoHeader::new(self.options.candidate_policy);
+        if let Some(planner) = &self.planner {
+            stego_header.block_policies = planner.policies.clone();
+        }
+        stego_header.literal_channel = self.options.literal_channel;
+        stego_header.match_truncation = self.options.match_truncation;
+        stego_header.block_channel = self.options.block_channel;
+        stego_header.offset_distribution = self.options.offset_distribution;
+        stego_header.trellis_ratio = self.options.trellis_ratio;
+        // The fast modes write what the reference `lz4` tool does
+        if self.options.acceleration.is_none() {
+            stego_header.write(&mut self.output_write)?;
+        }
+
         self.output_write.write_u32::<LE>(LZ4_MAGIC_NUMBER)?;
 
-        let frame_descriptor = self.build_frame_descriptor();
-        self.output_write.write(&frame_descriptor)?;
+        let frame_descriptor = self.build_frame_descriptor(content_size);
+        self.output_write.write_all(&frame_descriptor)?;
 
         Ok(())
     }
 
-    fn build_frame_descriptor(&self) -> Vec<u8> {
+    fn build_frame_descriptor(&self, content_size: Option<usize>) -> Vec<u8> {
         let mut output = Vec::new();
 
         let mut flag = FlgByte(0);
         flag.set_version(1);
-        flag.set_block_independent(true);
-        flag.set_content_checksum_added(true);
+        flag.set_block_independent(self.frame.block_independent);
+        flag.set_block_checksum_added(self.frame.block_checksum);
+        flag.set_content_size_added(self.frame.content_size.is_some());
+        flag.set_content_checksum_added(self.frame.content_checksum);
         output.write_u8(flag.0).unwrap();
 
         let mut bd = BdByte(0);
-        bd.set_block_max_size(7);
+        bd.set_block_max_size(self.get_block_size_id(content_size));
         output.write_u8(bd.0).unwrap();
 
+        if let Some(content_size) = self.frame.content_size {
+            output.write_u64::<LE>(content_size).unwrap();
+        }
+
         let hc = ((xxh32(&output, 0) >> 8) & 0xFF) as u8;
         output.write_u8(hc).unwrap();
 
@@ -106,65 +571,133 @@ impl<'a, W: Write> Compressor<'a, W> {
 
     fn write_footer(&mut self) -> Result<(), std::io::Error> {
         self.output_write.write_u32::<LE>(0)?;
-        self.output_write.write_u32::<LE>(self.hash.digest())?;
+        if self.frame.content_checksum {
+            self.output_write.write_u32::<LE>(self.hash.digest())?;
+        }
 
         Ok(())
     }
 
     fn output_block(&mut self, force_write: bool) -> Result<(), std::io::Error> {
-        let mut data = self.buffer.make_contiguous();
-        let mut to_shrink = 0;
-
-        while !data.is_empty() && (data.len() >= MAX_BLOCK_SIZE || force_write) {
-            if data.len() < MIN_COMPRESS_LENGTH {
-                output_uncompressed_block(&mut self.output_write, data)?;
-                to_shrink += data.len();
-                break;
-            }
+        if self.cancelled {
+            return Err(get_cancelled_error());
+        }
+        self.write_header(None)?;
+        let max_block_size = self.get_block_size();
+        let block_checksum = self.frame.block_checksum;
 
-            let block_size = min(data.len(), MAX_BLOCK_SIZE);
-            output_compressed_block(
-                &mut self.output_write,
-                &data[..block_size],
-                &mut self.hidden_data_encoder,
-                self.prefer_hidden,
-            )?;
+        loop {
+            let start_bits = self.get_available_bits();
+            let data = self.buffer.make_contiguous();
+            let block_size = match self.options.block_channel {
+                // Only blocks followed by more data carry a digit, so that the
+                // last block can be of any size
+                Some((_, range)) if data.len() > max_block_size => {
+                    max_block_size - self.hidden_data_encoder.decode_value(range as u16) as usize
+                }
+                None if data.len() >= max_block_size => max_block_size,
+                _ if force_write && !data.is_empty() => data.len(),
+                _ => break,
+            };
 
-            to_shrink += block_size;
-            data = &mut data[block_size..];
-        }
+            let block = &data[..block_size];
+            let mut block_report = if block.len() < MIN_COMPRESS_LENGTH {
+                output_uncompressed_block(&mut self.output_write, block, block_checksum)?;
+                BlockReport::new_uncompressed(block.len())
+            } else {
+                output_compressed_block(
+                    &mut self.output_write,
+                    block,
+                    &mut self.hidden_data_encoder,
+                    self.trellis_encoder.as_mut(),
+                    self.options,
+                    self.planner.as_mut(),
+                    self.frame,
+                )?
+            };
 
-        self.buffer.drain(..to_shrink);
+            self.buffer.drain(..block_size);
+            block_report.hidden_bits = self.get_available_bits() - start_bits;
+            self.block_reports.push(block_report);
+            // The coder states of the blocks written are never restored
+            self.hidden_source.release(self.get_consumed_bytes());
+            if let Some(e) = self.hidden_source.take_error() {
+                return Err(e);
+            }
+
+            let progress = self.get_progress();
+            if let Some(progress_callback) = &mut self.progress_callback {
+                if progress_callback(&progress).is_break() {
+                    self.cancelled = true;
+                    return Err(get_cancelled_error());
+                }
+            }
+        }
 
         Ok(())
     }
 }
 
+fn get_cancelled_error() -> std::io::Error {
+    std::io::Error::other("Compression was cancelled")
+}
+
+/// Counts the bytes written through it.
+struct CountingWriter<W: Write> {
+    inner: W,
+    count: u64,
+}
+
+impl<W: Write> CountingWriter<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        let count = self.inner.write(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+
+    fn flush(&mut self) -> Result<(), std::io::Error> {
+        self.inner.flush()
+    }
+}
+
 impl<'a, W: Write> Write for Compressor<'a, W> {
     fn write(&mut self, buf: &[u8]) -> Result<usize, std::io::Error> {
+        if self.cancelled {
+            return Err(get_cancelled_error());
+        }
         self.buffer.extend(buf);
         self.hash.update(buf);
+        self.content_len += buf.len() as u64;
 
-        if self.buffer.len() >= MAX_BLOCK_SIZE {
+        if self.buffer.len() >= self.get_block_size() {
             self.output_block(false)?;
         }
 
         Ok(buf.len())
     }
 
+    /// Ends the current block and flushes the writer. With the block size
+    /// channel, the sizes of all blocks but the last one carry hidden data, so
+    /// the block is only ended once it is complete.
     fn flush(&mut self) -> Result<(), std::io::Error> {
-        if !self.buffer.is_empty() {
+        if self.options.block_channel.is_none() && !self.buffer.is_empty() {
             self.output_block(true)?;
-            self.output_write.flush()?;
         }
 
-        Ok(())
+        self.output_write.flush()
     }
 }
 
 fn output_uncompressed_block<W: Write>(
     mut output_write: W,
     data: &[u8],
+    block_checksum: bool,
 ) -> Result<(), std::io::Error> {
     debug!("Outputting uncompressed block with length: {}", data.len());
 
@@ -173,7 +706,10 @@ fn output_uncompressed_block<W: Write>(
     block_size.set_block_size(data.len() as u32);
 
     output_write.write_u32::<LE>(block_size.0)?;
-    output_write.write(data)?;
+    output_write.write_all(data)?;
+    if block_checksum {
+        output_write.write_u32::<LE>(xxh32(data, 0))?;
+    }
 
     Ok(())
 }
@@ -182,23 +718,304 @@ fn output_compressed_block<W: Write>(
     mut output_write: W,
     data: &[u8],
     hidden_data_encoder: &mut numeral_coding::Decoder,
-    prefer_hidden: bool,
-) -> Result<(), std::io::Error> {
+    mut trellis_encoder: Option<&mut trellis_coding::Decoder>,
+    options: BlockOptions,
+    planner: Option<&mut Planner>,
+    frame: FrameOptions,
+) -> Result<BlockReport, std::io::Error> {
+    let max_compressed_size = frame.get_max_compressed_size();
+    let encoder_state = hidden_data_encoder.clone();
+    let trellis_state = trellis_encoder.as_deref().cloned();
     let mut output = Vec::new();
 
     // Reserve space for BlockSize
     output.write_u32::<LE>(0).unwrap();
 
-    let mut occur = OccurrenceMap::new(data, prefer_hidden);
+    let (fits, choice_count) = if let Some(acceleration) = options.acceleration {
+        compress_reference_block(
+            data,
+            acceleration,
+            hidden_data_encoder,
+            options.reference_parse,
+            &mut output,
+        )
+    } else if let Some(trellis_encoder) = trellis_encoder.as_deref_mut() {
+        let choice_count = compress_trellis_block(data, trellis_encoder, &mut output);
+        (output.len() - 4 <= max_compressed_size, choice_count)
+    } else if let Some(planner) = planner {
+        let choice_count =
+            plan_block_data(data, hidden_data_encoder, options, planner, &mut output);
+        planner.input_remaining = planner.input_remaining.saturating_sub(data.len());
+        (output.len() - 4 <= max_compressed_size, choice_count)
+    } else {
+        let choice_count =
+            compress_block_data(data, hidden_data_encoder, options, None, &mut output);
+        (output.len() - 4 <= max_compressed_size, choice_count)
+    };
+
+    let mut block_size = BlockSize(0);
+    block_size.set_block_uncompressed(false);
+    let block_size_num = output.len() - 4;
+    block_size.set_block_size(block_size_num as u32);
+    output.splice(0..4, block_size.0.to_le_bytes());
+
+    debug!("Block size: {}, data size: {}", block_size_num, data.len());
+
+    if fits {
+        output_write.write_all(&output)?;
+        if frame.block_checksum {
+            output_write.write_u32::<LE>(xxh32(&output[4..], 0))?;
+        }
+
+        Ok(BlockReport {
+            choice_count: Some(choice_count),
+            ..BlockReport::new_compressed(data.len(), &output[4..])
+        })
+    } else {
+        // Uncompressed blocks carry no hidden data
+        *hidden_data_encoder = encoder_state;
+        if let (Some(trellis_encoder), Some(trellis_state)) = (trellis_encoder, trellis_state) {
+            *trellis_encoder = trellis_state;
+        }
+        output_uncompressed_block(output_write, data, frame.block_checksum)?;
+
+        Ok(BlockReport::new_uncompressed(data.len()))
+    }
+}
+
+/// Compresses the block with the reference parser, which like the reference
+/// `lz4` tool gives up unless the block shrinks. In the mimicry mode, each
+/// match refers to one of the occurrences equivalent to the one found.
+/// Returns whether the block shrinks and the number of matches with a choice.
+fn compress_reference_block(
+    data: &[u8],
+    acceleration: u32,
+    hidden_data_encoder: &mut numeral_coding::Decoder,
+    reference_parse: bool,
+    output: &mut Vec<u8>,
+) -> (bool, usize) {
+    let max_output = Some(data.len() - 1);
+    if !reference_parse {
+        let fits = fast_compressor::compress_block(
+            data,
+            acceleration,
+            max_output,
+            |found| found.match_index,
+            output,
+        );
+        return (fits, 0);
+    }
+
+    let mut occur = OccurrenceMap::new(data, CandidatePolicy::Longest);
+    let mut choice_count = 0;
+    let fits = fast_compressor::compress_block(
+        data,
+        acceleration,
+        max_output,
+        |found| {
+            occur.index_until(found.index);
+            let occurrences =
+                occur.get_equivalent_occurrences(found.index, found.match_length, found.anchor);
+            debug_assert!(occurrences
+                .get_occurrence_index(found.match_index)
+                .is_some());
+            let chosen_index = hidden_data_encoder.decode_value(occurrences.len() as u16);
+            if occurrences.len() > 1 {
+                choice_count += 1;
+            }
+
+            occurrences.choose_occurrence(chosen_index as usize).0
+        },
+        output,
+    );
+
+    (fits, choice_count)
+}
+
+/// Greedy parse taking the longest matches, with the candidate choices of the
+/// whole block made at once by the syndrome-trellis code. Taking the second
+/// nearest candidate instead of the nearest one flips a cover bit, at a cost
+/// growing with the extra distance.
+fn compress_trellis_block(
+    data: &[u8],
+    trellis_encoder: &mut trellis_coding::Decoder,
+    output: &mut Vec<u8>,
+) -> usize {
+    let mut occur = OccurrenceMap::new(data, CandidatePolicy::Longest);
+    // Literals start, match position, nearest and second nearest candidates
+    // and match length of each sequence
+    let mut sequences = Vec::new();
+    let mut costs = Vec::new();
+    let mut anchor = 0;
+    let mut i = 0;
+    let end = data.len() - END_LITERAL_NUM;
+
+    while i < end {
+        occur.index_until(i);
+        let occurrences = occur.get_occurrences(i);
+        if occurrences.is_empty() {
+            i += 1;
+            continue;
+        }
+
+        let (nearest, match_length) = occurrences.choose_occurrence(occurrences.len() - 1);
+        let second_nearest = if occurrences.len() > 1 {
+            let (second_nearest, _) = occurrences.choose_occurrence(occurrences.len() - 2);
+            costs.push(((i - second_nearest) as f64 / (i - nearest) as f64).log2());
+            Some(second_nearest)
+        } else {
+            None
+        };
+        sequences.push((anchor, i, nearest, second_nearest, match_length));
+        i += match_length;
+        anchor = i;
+    }
+
+    let choice_count = costs.len();
+    let mut cover = trellis_encoder.embed_block(&costs).into_iter();
+    for (anchor, index, nearest, second_nearest, match_length) in sequences {
+        let chosen_index = match second_nearest {
+            Some(second_nearest) if cover.next() == Some(true) => second_nearest,
+            _ => nearest,
+        };
+        output_sequence(
+            &data[anchor..index],
+            (index - chosen_index) as u16,
+            match_length as u32,
+            output,
+        );
+    }
+
+    output_sequence(&data[anchor..], 0, MATCH_LENGTH_OFFSET, output);
+    choice_count
+}
+
+/// Compresses the block with the planner policy of the best compression ratio
+/// that carries its share of the hidden data. As the capacity generally grows
+/// with the policy index, the policy is found by a binary search, so a block
+/// is compressed at most `log2(policy count) + 1` times. If no policy carries
+/// the share, the one with the highest capacity is used.
+fn plan_block_data(
+    data: &[u8],
+    hidden_data_encoder: &mut numeral_coding::Decoder,
+    options: BlockOptions,
+    planner: &Planner,
+    output: &mut Vec<u8>,
+) -> usize {
+    let start_bits = hidden_data_encoder.get_available_bits();
+    let quota = planner.block_quota(data.len(), start_bits);
+    let trial = |policy_index: usize| {
+        let mut trial_encoder = hidden_data_encoder.clone();
+        let mut trial_output = Vec::new();
+        let signal = PolicySignal::new(policy_index, planner.policies.len());
+        let trial_options = BlockOptions {
+            candidate_policy: planner.policies[policy_index],
+            ..options
+        };
+        let choice_count = compress_block_data(
+            data,
+            &mut trial_encoder,
+            trial_options,
+            Some(signal),
+            &mut trial_output,
+        );
+        let bits = trial_encoder.get_available_bits() - start_bits;
+
+        (
+            policy_index,
+            trial_output,
+            trial_encoder,
+            bits,
+            choice_count,
+        )
+    };
+
+    let mut low = 0;
+    let mut high = if quota > 0.0 {
+        planner.policies.len() - 1
+    } else {
+        0
+    };
+    let mut best = None;
+    while low < high {
+        let middle = (low + high) / 2;
+        let result = trial(middle);
+        if result.3 >= quota {
+            high = middle;
+            best = Some(result);
+        } else {
+            low = middle + 1;
+        }
+    }
+    let best = match best {
+        Some(best) if best.0 == low => best,
+        _ => trial(low),
+    };
+
+    let (policy_index, best_output, best_encoder, bits, choice_count) = best;
+    debug!(
+        "Planned policy {:?} with {} bits",
+        planner.policies[policy_index], bits
+    );
+    *hidden_data_encoder = best_encoder;
+    output.extend_from_slice(&best_output);
+    choice_count
+}
+
+fn compress_block_data(
+    data: &[u8],
+    hidden_data_encoder: &mut numeral_coding::Decoder,
+    options: BlockOptions,
+    mut policy_signal: Option<PolicySignal>,
+    output: &mut Vec<u8>,
+) -> usize {
+    let initial_policy = match policy_signal {
+        Some(signal) if !signal.is_complete() => CandidatePolicy::Longest,
+        _ => options.candidate_policy,
+    };
+    let mut occur = OccurrenceMap::new(data, initial_policy);
+    let mut candidate_models = options.offset_distribution.map(CandidateModels::new);
     let mut literals = Vec::new();
+    let mut choice_count = 0;
     let mut i = 0;
+    let end = data.len() - END_LITERAL_NUM;
 
-    while i < data.len() - END_LITERAL_NUM {
+    while i < end {
+        occur.index_until(i);
         let occurrences = occur.get_occurrences(i);
 
-        if occurrences.len() > 0 {
-            let chosen_index = hidden_data_encoder.decode_value(occurrences.len() as u16);
-            let (index, match_length) = occurrences.choose_occurrence(chosen_index as usize);
+        if !occurrences.is_empty()
+            && !is_match_deferred(&mut occur, &occurrences, i, options.lookahead)
+        {
+            let signal_pending = policy_signal.is_some_and(|s| !s.is_complete());
+            if !signal_pending {
+                if let Some(literal_length) =
+                    occurrences.literal_channel_length(options.literal_channel)
+                {
+                    if hidden_data_encoder.decode_value(2) == 1 {
+                        literals.extend_from_slice(&data[i..i + literal_length]);
+                        i += literal_length;
+                        continue;
+                    }
+                }
+            }
+
+            let chosen_index = match policy_signal.as_mut().filter(|s| !s.is_complete()) {
+                Some(signal) => {
+                    let digit = signal.encode_digit(occurrences.len());
+                    if signal.is_complete() {
+                        occur.set_candidate_policy(options.candidate_policy);
+                    }
+                    digit
+                }
+                None => match &mut candidate_models {
+                    Some(candidate_models) => {
+                        hidden_data_encoder.decode_weighted(candidate_models.get(occurrences.len()))
+                    }
+                    None => hidden_data_encoder.decode_value(occurrences.len() as u16),
+                },
+            };
+            let (index, mut match_length) = occurrences.choose_occurrence(chosen_index as usize);
             if match_length < 4 {
                 // End of block
                 literals.push(data[i]);
@@ -206,41 +1023,73 @@ fn output_compressed_block<W: Write>(
                 continue;
             }
 
+            if let Some(max_truncation) = options.match_truncation.filter(|_| !signal_pending) {
+                let truncation_count = get_truncation_count(match_length, max_truncation);
+                if truncation_count > 1 {
+                    match_length -= hidden_data_encoder.decode_value(truncation_count) as usize;
+                }
+            }
+
             let offset = (i - index) as u16;
+            if occurrences.len() > 1 {
+                choice_count += 1;
+            }
 
-            output_sequence(&literals, offset, match_length as u32, &mut output);
+            output_sequence(&literals, offset, match_length as u32, output);
 
             literals.clear();
-            occur.add_occurrences(i, match_length);
             i += match_length;
         } else {
             literals.push(data[i]);
-            occur.add_occurrences(i, 1);
             i += 1;
         }
     }
 
     literals.extend_from_slice(&data[data.len() - END_LITERAL_NUM..]);
-    output_sequence(&literals, 0, MATCH_LENGTH_OFFSET, &mut output);
+    output_sequence(&literals, 0, MATCH_LENGTH_OFFSET, output);
+    choice_count
+}
 
-    let mut block_size = BlockSize(0);
-    block_size.set_block_uncompressed(false);
-    let block_size_num = output.len() - 4;
-    block_size.set_block_size(block_size_num as u32);
-    output.splice(0..4, block_size.0.to_le_bytes());
+/// Lazy matching: checks if emitting a literal and taking a match that starts
+/// up to `lookahead` bytes later covers more data. Only the longest match at
+/// a position is ever emitted, so the decompressor derives the same candidate
+/// sets as in greedy mode without knowing the lookahead used.
+fn is_match_deferred(
+    occur: &mut OccurrenceMap,
+    occurrences: &Occurrences,
+    index: usize,
+    lookahead: usize,
+) -> bool {
+    let match_length = match occurrences.match_length() {
+        Some(match_length) if lookahead > 0 => match_length,
+        _ => return false,
+    };
+    let end = occur.data_len() - END_LITERAL_NUM;
 
-    debug!("Block size: {}, data size: {}", block_size_num, data.len());
+    (1..=lookahead)
+        .take_while(|distance| index + distance < end)
+        .any(|distance| {
+            occur.index_until(index + distance);
+            let next_length = occur
+                .peek_occurrences(index + distance)
+                .match_length()
+                .unwrap_or(0);
 
-    if block_size_num <= MAX_BLOCK_SIZE {
-        output_write.write(&output)?;
-    } else {
-        output_uncompressed_block(output_write, data)?;
-    }
+            next_length >= match_length + distance
+        })
+}
 
-    Ok(())
+/// Block size declared by the given block maximum size id.
+pub(crate) fn get_block_max_size(block_size_id: u8) -> usize {
+    1 << (2 * block_size_id as usize + 8)
 }
 
-fn output_sequence(literals: &Vec<u8>, offset: u16, match_length: u32, output: &mut Vec<u8>) {
+pub(crate) fn output_sequence(
+    literals: &[u8],
+    offset: u16,
+    match_length: u32,
+    output: &mut Vec<u8>,
+) {
     debug!(
         "Outputting sequence: literals {:?}, offset={}, match_length={}",
         literals, offset, match_length
@@ -255,7 +1104,7 @@ fn output_sequence(literals: &Vec<u8>, offset: u16, match_length: u32, output: &
     output.write_u8(token.0).unwrap();
 
     output_lsic_int(literals_len, TOKEN_MAX_VAL, output);
-    output.extend_from_slice(literals.as_slice());
+    output.extend_from_slice(literals);
 
     if offset != 0 {
         // Last sequence
@@ -271,7 +1120,7 @@ fn output_lsic_int(val: u32, max_val: u8, output: &mut Vec<u8>) {
 
     let mut new_val = val - max_val as u32;
 
-    while new_val > 255 {
+    while new_val >= 255 {
         output.write_u8(255).unwrap();
         new_val -= 255;
     }
@@ -1,152 +1,634 @@
 use xxhash_rust::xxh32::Xxh32;
 
-use crate::constants::{LZ4_MAGIC_NUMBER, MATCH_LENGTH_OFFSET, TOKEN_MAX_VAL};
-use crate::descriptors::{BdByte, BlockSize, FlgByte, Token};
+use crate::analysis::{self, Analysis, BlockAnalysis};
+use crate::block_planner::PolicySignal;
+use crate::candidate_policy::CandidatePolicy;
+use crate::compressor;
+use crate::constants::{
+    END_LITERAL_NUM, MATCH_LENGTH_OFFSET, MAX_OFFSET, REFERENCE_BLOCK_SIZE, TOKEN_MAX_VAL,
+};
+use crate::descriptors::{BlockSize, Token};
 use crate::errors::{DecompressResult, Lz4DecompressError};
+use crate::frame_parser::{Event, FrameParser};
 
 use crate::numeral_coding;
-use crate::occurrence_map::OccurrenceMap;
+use crate::occurrence_map::{get_truncation_count, OccurrenceMap};
+use crate::offset_distribution::CandidateModels;
+use crate::report::{BlockReport, Report};
+use crate::stego_header::StegoHeader;
+use crate::trellis_coding;
+use crate::HiddenDataMode;
 use byteorder::{ReadBytesExt, LE};
 use std::cmp::min;
-use std::io::Read;
+use std::io::{ErrorKind, Read, Write};
+
+/// Position, source position and length of a match within a block
+type Match = (u32, u32, u32);
 
 pub struct Decompressor<R: Read> {
-    input_read: R,
+    input_read: CountingReader<R>,
+    parser: FrameParser,
     input_buffer: Vec<u8>,
     buffer: Vec<u8>,
     hash: Xxh32,
     hidden_data_decoder: numeral_coding::Encoder,
+    trellis_decoder: Option<trellis_coding::Encoder>,
 
-    header_read: bool,
-    content_checksum_added: bool,
+    frame_options: compressor::FrameOptions,
+    frame_ended: bool,
     buffer_start: usize,
-    prefer_hidden: bool,
+    stego_header: StegoHeader,
+    extract_hidden: bool,
+    reference_parse: bool,
+    /// Step read ahead for the block size channel
+    next_event: Option<Event>,
+    stego_header_found: bool,
+    block_analyses: Option<Vec<BlockAnalysis>>,
+    block_reports: Vec<BlockReport>,
+    /// Hidden data moved out by `read_hidden_data`
+    hidden_bytes_read: usize,
 }
 
 impl<R: Read> Decompressor<R> {
     pub fn new(input_read: R, prefer_hidden: bool) -> Self {
-        const INPUT_BUFFER_SIZE: usize = 4 * 1024 * 1024;
+        Self::with_extraction(input_read, prefer_hidden, true)
+    }
+
+    /// Plain LZ4 decompression; `finish` returns no hidden data.
+    pub fn new_without_hidden_data(input_read: R) -> Self {
+        Self::with_extraction(input_read, false, false)
+    }
+
+    /// Extracts hidden data from streams created by
+    /// `Compressor::new_reference_with_hidden_data`, which cannot be told apart
+    /// from regular LZ4 streams.
+    pub fn new_reference(input_read: R) -> Self {
+        let mut decompressor = Self::with_extraction(input_read, false, true);
+        decompressor.reference_parse = true;
+        decompressor
+    }
 
-        let mut input_buffer = Vec::with_capacity(INPUT_BUFFER_SIZE);
-        unsafe {
-            input_buffer.set_len(INPUT_BUFFER_SIZE);
+    pub fn new_with_mode(input_read: R, mode: HiddenDataMode) -> Self {
+        match mode {
+            HiddenDataMode::Default => Self::new(input_read, false),
+            HiddenDataMode::PreferHidden => Self::new(input_read, true),
+            HiddenDataMode::Reference => Self::new_reference(input_read),
+            HiddenDataMode::None => Self::new_without_hidden_data(input_read),
         }
+    }
 
+    /// Collects the statistics of every block instead of extracting hidden
+    /// data; see `analysis::analyze`.
+    pub(crate) fn new_analyzing(input_read: R) -> Self {
+        let mut decompressor = Self::with_extraction(input_read, false, false);
+        decompressor.block_analyses = Some(Vec::new());
+        decompressor
+    }
+
+    fn with_extraction(input_read: R, prefer_hidden: bool, extract_hidden: bool) -> Self {
         Self {
-            input_read,
-            input_buffer,
+            input_read: CountingReader {
+                inner: input_read,
+                count: 0,
+            },
+            parser: FrameParser::new(),
+            input_buffer: Vec::new(),
             buffer: Vec::new(),
             hash: Xxh32::new(0),
             hidden_data_decoder: numeral_coding::Encoder::new(),
+            trellis_decoder: None,
 
-            header_read: false,
-            content_checksum_added: false,
+            frame_options: compressor::FrameOptions::default(),
+            frame_ended: false,
             buffer_start: 0,
-            prefer_hidden,
+            stego_header: StegoHeader::new(CandidatePolicy::from_prefer_hidden(prefer_hidden)),
+            extract_hidden,
+            reference_parse: false,
+            next_event: None,
+            stego_header_found: false,
+            block_analyses: None,
+            block_reports: Vec::new(),
+            hidden_bytes_read: 0,
         }
     }
 
-    fn read_header(&mut self) -> DecompressResult<()> {
-        if self.input_read.read_u32::<LE>()? != LZ4_MAGIC_NUMBER {
-            return Err(Lz4DecompressError::from_static_str("Invalid header"));
+    /// Sets up the decoders once the frame header, and so the steganography
+    /// header before it, was read.
+    fn start_frame(&mut self, frame_options: compressor::FrameOptions) {
+        self.frame_options = frame_options;
+        if let Some(block_size_id) = frame_options.block_size_id {
+            self.buffer
+                .reserve(compressor::get_block_max_size(block_size_id));
         }
 
-        // TODO check all releveant flags
-        let flg = FlgByte(self.input_read.read_u8()?);
-        if flg.get_version() != 1 {
-            return Err(Lz4DecompressError::from_static_str("Version is not 1"));
+        if !self.stego_header_found {
+            self.hidden_data_decoder = numeral_coding::Encoder::new_legacy();
+        } else if self.stego_header.offset_distribution.is_some() {
+            self.hidden_data_decoder = numeral_coding::Encoder::new_shaped();
         }
+        self.trellis_decoder = self
+            .stego_header
+            .trellis_ratio
+            .map(trellis_coding::Encoder::new);
+    }
 
-        if flg.is_dictionary_id_set() {
-            return Err(Lz4DecompressError::from_static_str(
-                "Dictionary ID is not supported",
-            ));
+    /// Reads the next step of the stream, unless it was already read ahead.
+    fn read_event(&mut self) -> DecompressResult<Event> {
+        if let Some(event) = self.next_event.take() {
+            return Ok(event);
         }
 
-        if !flg.is_block_independent() {
-            return Err(Lz4DecompressError::from_static_str(
-                "Blocks must be independent",
-            ));
+        match self
+            .parser
+            .read_event(&mut self.input_read, &mut self.input_buffer)?
+        {
+            Some(event) => Ok(event),
+            None => Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
         }
-
-        self.content_checksum_added = flg.is_content_checksum_added();
-
-        let byte = BdByte(self.input_read.read_u8()?);
-        let block_max_size = get_block_max_size(byte.get_block_max_size())?;
-        self.buffer.reserve(block_max_size);
-
-        // TODO check HC
-        self.input_read.read_u8()?;
-
-        self.header_read = true;
-
-        Ok(())
     }
 
+    /// Reads up to the end of the next block, or of the frame. Returns the size
+    /// of the block, 0 at the end of the frame.
     fn read_block(&mut self) -> DecompressResult<usize> {
-        let block_size_val = self.input_read.read_u32::<LE>()?;
-        if block_size_val == 0 {
-            self.check_checksum()?;
-            return Ok(0);
+        loop {
+            if let Some(size) = self.read_step()? {
+                return Ok(size);
+            }
+        }
+    }
+
+    /// Reads the next step of the stream. Returns the size of the block it
+    /// completed, 0 if it ended the frame, and `None` otherwise.
+    pub(crate) fn read_step(&mut self) -> DecompressResult<Option<usize>> {
+        match self.read_event()? {
+            Event::SkippableFrame(size) => {
+                if self.stego_header.read(&self.input_buffer[..size])? {
+                    self.stego_header_found = true;
+                }
+            }
+            Event::FrameHeader(frame_options) => self.start_frame(frame_options),
+            Event::Block(block_size) => return self.decompress_block(block_size).map(Some),
+            Event::FrameEnd(checksum) => {
+                if checksum.is_some_and(|checksum| checksum != self.hash.digest()) {
+                    return Err(Lz4DecompressError::from_static_str("Checksum is invalid"));
+                }
+                self.frame_ended = true;
+                return Ok(Some(0));
+            }
+            Event::Partial | Event::BlockSize => {}
         }
 
-        let block_size_desc = BlockSize(block_size_val);
+        Ok(None)
+    }
+
+    /// Decompresses the block in the input buffer and extracts its hidden data.
+    fn decompress_block(&mut self, block_size_desc: BlockSize) -> DecompressResult<usize> {
         let start_index = self.buffer.len();
+        let start_bits = self.get_available_bits();
         let block_size = block_size_desc.get_block_size() as usize;
 
-        self.input_read.read(&mut self.input_buffer[..block_size])?;
         if block_size_desc.is_uncompressed() {
             let new_data = &self.input_buffer[..block_size];
             self.hash.update(new_data);
             self.buffer.extend_from_slice(new_data);
+            self.analyze_block_size(block_size)?;
+            if let Some(block_analyses) = &mut self.block_analyses {
+                block_analyses.push(BlockAnalysis::new_uncompressed(block_size));
+            }
+            self.push_block_report(BlockReport::new_uncompressed(block_size), start_bits);
 
             Ok(block_size)
         } else {
+            let max_len = self.get_block_max_size();
             let (bytes_read, matches) = decompress_block_data(
                 &mut self.buffer,
                 &mut self.hash,
                 &self.input_buffer[..block_size],
+                max_len,
+                self.extract_hidden || self.block_analyses.is_some(),
             )?;
-            self.analyze_matches(start_index, matches);
+            // Before the next block size is read ahead into the input buffer
+            let block_report =
+                BlockReport::new_compressed(bytes_read, &self.input_buffer[..block_size]);
+            self.analyze_block_size(bytes_read)?;
+            if let Some(block_analyses) = &mut self.block_analyses {
+                block_analyses.push(analysis::analyze_block(
+                    &self.buffer[start_index..],
+                    &matches,
+                ));
+            }
+            let choice_count = if !self.extract_hidden {
+                None
+            } else if self.reference_parse {
+                Some(self.analyze_reference_matches(start_index, matches))
+            } else if self.trellis_decoder.is_some() {
+                Some(self.analyze_trellis_matches(start_index, matches))
+            } else {
+                Some(self.analyze_matches(start_index, matches))
+            };
+            let block_report = BlockReport {
+                choice_count,
+                ..block_report
+            };
+            self.push_block_report(block_report, start_bits);
 
             Ok(bytes_read)
         }
     }
 
-    fn check_checksum(&mut self) -> DecompressResult<()> {
-        if self.content_checksum_added {
-            let file_checksum = self.input_read.read_u32::<LE>()?;
-            let computed_checksum = self.hash.digest();
-            if file_checksum != computed_checksum {
-                return Err(Lz4DecompressError::from_static_str("Checksum is invalid"));
-            }
+    /// Largest size of the decompressed blocks of the frame.
+    fn get_block_max_size(&self) -> usize {
+        self.frame_options
+            .block_size_id
+            .map_or(REFERENCE_BLOCK_SIZE, compressor::get_block_max_size)
+    }
+
+    /// Reads the frame header if it was not read yet and the next block, whose
+    /// data is then in `buffer` after `buffer_start`. Returns the size of the
+    /// block, 0 at the end of the frame.
+    fn read_next_block(&mut self) -> DecompressResult<usize> {
+        if self.frame_ended {
+            return Ok(0);
         }
 
+        // Linked blocks may refer to the data of the previous ones
+        let history_len = if self.frame_options.block_independent {
+            0
+        } else {
+            min(self.buffer.len(), MAX_OFFSET)
+        };
+        self.buffer.drain(..self.buffer.len() - history_len);
+        self.buffer_start = history_len;
+
+        self.read_block()
+    }
+
+    /// Like `read_next_block`, appending the data of the block to `output`.
+    pub(crate) fn decompress_next_block(&mut self, output: &mut Vec<u8>) -> DecompressResult<()> {
+        self.read_next_block()?;
+        output.extend_from_slice(&self.buffer[self.buffer_start..]);
+        self.buffer_start = self.buffer.len();
+
         Ok(())
     }
 
+    pub(crate) fn get_input_mut(&mut self) -> &mut R {
+        &mut self.input_read.inner
+    }
+
+    /// Recovers the rest of the hidden data once the stream has ended.
+    pub(crate) fn end_hidden_data(&mut self) {
+        self.hidden_data_decoder.end();
+    }
+
+    /// Reads the block size channel digit, which is only present if another
+    /// block follows, so the next block header has to be read ahead.
+    fn analyze_block_size(&mut self, bytes_read: usize) -> DecompressResult<()> {
+        let (block_size, range) = match self.stego_header.block_channel {
+            Some(block_channel) if self.extract_hidden => block_channel,
+            _ => return Ok(()),
+        };
+
+        let next_event = self.read_event()?;
+        self.next_event = Some(next_event);
+        if !matches!(next_event, Event::BlockSize) {
+            return Ok(());
+        }
+
+        match block_size.checked_sub(bytes_read).filter(|d| *d < range) {
+            Some(digit) => {
+                self.hidden_data_decoder
+                    .add_value(digit as u16, range as u16);
+                Ok(())
+            }
+            None => Err(Lz4DecompressError::from_static_str(
+                "Block size does not match the block size channel",
+            )),
+        }
+    }
+
+    fn get_available_bits(&self) -> f64 {
+        match &self.trellis_decoder {
+            Some(trellis_decoder) => trellis_decoder.get_available_bits() as f64,
+            None => self.hidden_data_decoder.get_available_bits(),
+        }
+    }
+
+    fn push_block_report(&mut self, mut block_report: BlockReport, start_bits: f64) {
+        block_report.hidden_bits = self.get_available_bits() - start_bits;
+        self.block_reports.push(block_report);
+    }
+
+    pub(crate) fn finish_analysis(self) -> Analysis {
+        Analysis {
+            stego_header: self.stego_header_found,
+            blocks: self.block_analyses.unwrap_or_default(),
+        }
+    }
+
+    /// Moves the hidden data extracted so far to `buf`, returning the number
+    /// of bytes moved. The hidden data is extracted along with every block,
+    /// so it can be read while the decompression goes on, except in streams
+    /// without a steganography header, where it is only known at the end.
+    pub fn read_hidden_data(&mut self, buf: &mut [u8]) -> usize {
+        let count = match &mut self.trellis_decoder {
+            Some(trellis_decoder) => trellis_decoder.read(buf),
+            None => self.hidden_data_decoder.read(buf),
+        };
+        self.hidden_bytes_read += count;
+
+        count
+    }
+
+    /// Whether the end of the hidden data was reached, so that no more of it
+    /// will be extracted.
+    pub fn is_hidden_data_complete(&self) -> bool {
+        match &self.trellis_decoder {
+            Some(trellis_decoder) => trellis_decoder.is_complete(),
+            None => self.hidden_data_decoder.is_complete(),
+        }
+    }
+
+    /// Returns the hidden data not read with `read_hidden_data` yet.
     pub fn finish(self) -> Vec<u8> {
-        self.hidden_data_decoder.finish()
+        match self.trellis_decoder {
+            Some(trellis_decoder) => trellis_decoder.finish(),
+            None => self.hidden_data_decoder.finish(),
+        }
+    }
+
+    /// Like `finish`, additionally returning the statistics of the stream read.
+    /// The hidden bits are only counted if the hidden data is extracted.
+    pub fn finish_with_report(mut self) -> (Vec<u8>, Report) {
+        let block_reports = std::mem::take(&mut self.block_reports);
+        let compressed_size = self.input_read.count;
+        let hidden_bytes_read = self.hidden_bytes_read;
+        let hidden_data = self.finish();
+        let hidden_bytes = hidden_bytes_read + hidden_data.len();
+
+        (
+            hidden_data,
+            Report::new(block_reports, compressed_size, hidden_bytes),
+        )
+    }
+
+    /// Turns the decompressor into a reader of the hidden data, which
+    /// decompresses the stream as the hidden data is read, writing the
+    /// decompressed data to `output_write`.
+    pub fn into_hidden_reader<W: Write>(self, output_write: W) -> HiddenReader<R, W> {
+        HiddenReader {
+            decompressor: self,
+            output_write,
+            buffer: vec![0; HIDDEN_READER_BUFFER_SIZE],
+            ended: false,
+        }
     }
 
-    fn analyze_matches(&mut self, start_index: usize, matches: Vec<(u32, u32)>) {
+    /// In the reference mimicry mode, each match carries a choice between the
+    /// occurrences the reference parser would encode identically. Returns the
+    /// number of matches with a choice, like the other `analyze_*` methods.
+    fn analyze_reference_matches(&mut self, start_index: usize, matches: Vec<Match>) -> usize {
         let data = &self.buffer[start_index..];
-        let mut occur = OccurrenceMap::new(data, self.prefer_hidden);
+        let mut occur = OccurrenceMap::new(data, CandidatePolicy::Longest);
+        let mut anchor = 0;
+        let mut choice_count = 0;
 
-        let mut last_index: u32 = 0;
+        for (index, match_index, match_length) in matches {
+            let index = index as usize;
+            occur.index_until(index);
 
-        for (index, match_index) in matches {
-            occur.add_occurrences(last_index as usize, index as usize - last_index as usize);
-            last_index = index;
+            let occurrences =
+                occur.get_equivalent_occurrences(index, match_length as usize, anchor);
+            if let Some(value) = occurrences.get_occurrence_index(match_index as usize) {
+                self.hidden_data_decoder
+                    .add_value(value as u16, occurrences.len() as u16);
+                if occurrences.len() > 1 {
+                    choice_count += 1;
+                }
+            }
+            anchor = index + match_length as usize;
+        }
+
+        choice_count
+    }
 
-            let occurrences = occur.get_occurrences(index as usize);
+    /// In the syndrome-trellis mode, every match with several candidates
+    /// carries the parity of the recency rank of the chosen one.
+    fn analyze_trellis_matches(&mut self, start_index: usize, matches: Vec<Match>) -> usize {
+        let data = &self.buffer[start_index..];
+        let mut occur = OccurrenceMap::new(data, CandidatePolicy::Longest);
+        let mut cover = Vec::new();
+
+        for (index, match_index, _) in matches {
+            let index = index as usize;
+            occur.index_until(index);
+
+            let occurrences = occur.get_occurrences(index);
+            if occurrences.len() > 1 {
+                let rank = occurrences
+                    .get_occurrence_index(match_index as usize)
+                    .map(|value| occurrences.len() - 1 - value)
+                    .unwrap_or(0);
+                cover.push(rank % 2 == 1);
+            }
+        }
+
+        if let Some(trellis_decoder) = &mut self.trellis_decoder {
+            trellis_decoder.add_block(&cover);
+        }
+
+        cover.len()
+    }
+
+    fn analyze_matches(&mut self, start_index: usize, matches: Vec<Match>) -> usize {
+        let data = &self.buffer[start_index..];
+        let block_policies = &self.stego_header.block_policies;
+        let literal_channel = self.stego_header.literal_channel;
+        let match_truncation = self.stego_header.match_truncation;
+        let mut candidate_models = self
+            .stego_header
+            .offset_distribution
+            .map(CandidateModels::new);
+        let mut policy_signal = if block_policies.is_empty() {
+            None
+        } else {
+            Some(PolicySignal::new(0, block_policies.len()))
+        };
+        let initial_policy = match policy_signal {
+            Some(signal) if !signal.is_complete() => CandidatePolicy::Longest,
+            _ => self.stego_header.candidate_policy,
+        };
+        let mut occur = OccurrenceMap::new(data, initial_policy);
+        let mut next_index = 0;
+        let mut choice_count = 0;
+
+        for (index, match_index, match_length) in matches {
+            let index = index as usize;
+            let signal_pending = policy_signal.is_some_and(|s| !s.is_complete());
+            if !signal_pending {
+                analyze_literals(
+                    &mut self.hidden_data_decoder,
+                    &mut occur,
+                    next_index,
+                    index,
+                    literal_channel,
+                );
+            }
+            next_index = index + match_length as usize;
+
+            occur.index_until(index);
+
+            let occurrences = occur.get_occurrences(index);
             let max_val = occurrences.len();
             let val = occurrences.get_occurrence_index(match_index as usize);
 
             if let Some(value) = val {
-                self.hidden_data_decoder
-                    .add_value(value as u16, max_val as u16);
+                if max_val > 1 {
+                    choice_count += 1;
+                }
+                match policy_signal.as_mut().filter(|s| !s.is_complete()) {
+                    Some(signal) => {
+                        signal.add_digit(value, max_val);
+                        if signal.is_complete() {
+                            let candidate_policy = block_policies
+                                .get(signal.value())
+                                .cloned()
+                                .unwrap_or(CandidatePolicy::Longest);
+                            occur.set_candidate_policy(candidate_policy);
+                        }
+                    }
+                    None => {
+                        if occurrences
+                            .literal_channel_length(literal_channel)
+                            .is_some()
+                        {
+                            self.hidden_data_decoder.add_value(0, 2);
+                        }
+                        match &mut candidate_models {
+                            Some(candidate_models) => {
+                                self.hidden_data_decoder.add_weighted_value(
+                                    value as u16,
+                                    candidate_models.get(max_val),
+                                );
+                            }
+                            None => self
+                                .hidden_data_decoder
+                                .add_value(value as u16, max_val as u16),
+                        }
+
+                        if let Some(max_truncation) = match_truncation {
+                            let (_, full_length) = occurrences.choose_occurrence(value);
+                            let truncation_count =
+                                get_truncation_count(full_length, max_truncation);
+                            let truncation = full_length.checked_sub(match_length as usize);
+                            if let Some(truncation) = truncation
+                                .filter(|t| truncation_count > 1 && *t < truncation_count as usize)
+                            {
+                                self.hidden_data_decoder
+                                    .add_value(truncation as u16, truncation_count);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        let signal_pending = policy_signal.is_some_and(|s| !s.is_complete());
+        if !signal_pending {
+            let end = data.len().saturating_sub(END_LITERAL_NUM);
+            analyze_literals(
+                &mut self.hidden_data_decoder,
+                &mut occur,
+                next_index,
+                end,
+                literal_channel,
+            );
+        }
+
+        choice_count
+    }
+}
+
+/// Counts the bytes read through it.
+struct CountingReader<R: Read> {
+    inner: R,
+    count: u64,
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.inner.read(buf)?;
+        self.count += count as u64;
+        Ok(count)
+    }
+}
+
+const HIDDEN_READER_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Hidden data of a stream; see `Decompressor::into_hidden_reader`. Only the
+/// current block and the hidden data not read yet are held in memory.
+pub struct HiddenReader<R: Read, W: Write> {
+    decompressor: Decompressor<R>,
+    output_write: W,
+    buffer: Vec<u8>,
+    ended: bool,
+}
+
+impl<R: Read, W: Write> HiddenReader<R, W> {
+    pub fn into_inner(self) -> W {
+        self.output_write
+    }
+
+    /// Returns the decompressor, for example to get its report once the
+    /// hidden data is read; the writer is dropped.
+    pub fn into_decompressor(self) -> Decompressor<R> {
+        self.decompressor
+    }
+}
+
+impl<R: Read, W: Write> Read for HiddenReader<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            let count = self.decompressor.read_hidden_data(buf);
+            if count > 0 || buf.is_empty() || self.ended {
+                return Ok(count);
+            }
+
+            let bytes_read = self.decompressor.read(&mut self.buffer)?;
+            if bytes_read == 0 {
+                self.decompressor.end_hidden_data();
+                self.output_write.flush()?;
+                self.ended = true;
             }
+            self.output_write.write_all(&self.buffer[..bytes_read])?;
+        }
+    }
+}
+
+/// Reads the literal channel digits of the matches emitted as literals
+/// between `start` and `end`.
+fn analyze_literals(
+    hidden_data_decoder: &mut numeral_coding::Encoder,
+    occur: &mut OccurrenceMap,
+    start: usize,
+    end: usize,
+    literal_channel: Option<usize>,
+) {
+    if literal_channel.is_none() {
+        return;
+    }
+
+    let mut i = start;
+    while i < end {
+        occur.index_until(i);
+        let occurrences = occur.get_occurrences(i);
+
+        match occurrences.literal_channel_length(literal_channel) {
+            Some(literal_length) => {
+                hidden_data_decoder.add_value(1, 2);
+                i += literal_length;
+            }
+            None => i += 1,
         }
     }
 }
@@ -155,9 +637,12 @@ fn decompress_block_data(
     buffer: &mut Vec<u8>,
     hash: &mut Xxh32,
     mut data: &[u8],
-) -> DecompressResult<(usize, Vec<(u32, u32)>)> {
+    max_len: usize,
+    collect_matches: bool,
+) -> DecompressResult<(usize, Vec<Match>)> {
     let start_len = buffer.len();
-    let mut matches: Vec<(u32, u32)> = Vec::new();
+    let too_large = || Lz4DecompressError::from_static_str("Decompressed block is too large");
+    let mut matches: Vec<Match> = Vec::new();
     let start_index = buffer.len();
 
     loop {
@@ -165,9 +650,15 @@ fn decompress_block_data(
 
         let literals_length_initial = token.get_literals_length();
         let literals_length =
-            get_lsic_int(&mut data, literals_length_initial, TOKEN_MAX_VAL) as usize;
-        buffer.extend_from_slice(&data[..literals_length]);
-        data = &data[literals_length as usize..];
+            get_lsic_int(&mut data, literals_length_initial, TOKEN_MAX_VAL)? as usize;
+        let literals = data
+            .get(..literals_length)
+            .ok_or_else(|| Lz4DecompressError::from_static_str("Literals are out of range"))?;
+        if buffer.len() - start_len + literals_length > max_len {
+            return Err(too_large());
+        }
+        buffer.extend_from_slice(literals);
+        data = &data[literals_length..];
 
         if data.is_empty() {
             // End of block
@@ -181,11 +672,26 @@ fn decompress_block_data(
         }
         let match_length_initial = token.get_match_length();
         let match_length =
-            get_lsic_int(&mut data, match_length_initial, TOKEN_MAX_VAL) + MATCH_LENGTH_OFFSET;
+            get_lsic_int(&mut data, match_length_initial, TOKEN_MAX_VAL)? + MATCH_LENGTH_OFFSET;
         let next_pos = buffer.len();
-        let next_pos_block = next_pos - start_index;
-        matches.push((next_pos_block as u32, next_pos_block as u32 - offset as u32));
+        // Matches referring to the previous blocks carry no hidden data
+        if collect_matches && next_pos - start_index >= offset {
+            let next_pos_block = next_pos - start_index;
+            matches.push((
+                next_pos_block as u32,
+                next_pos_block as u32 - offset as u32,
+                match_length,
+            ));
+        }
 
+        if offset > next_pos {
+            return Err(Lz4DecompressError::from_static_str(
+                "Offset is out of range",
+            ));
+        }
+        if next_pos - start_len + match_length as usize > max_len {
+            return Err(too_large());
+        }
         let match_pos = next_pos - offset;
         if offset == 1 {
             buffer.resize(buffer.len() + match_length as usize, buffer[match_pos]);
@@ -201,20 +707,75 @@ fn decompress_block_data(
     }
 }
 
-impl<R: Read> Read for Decompressor<R> {
-    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
-        if !self.header_read {
-            self.read_header()?;
+/// Decompresses the block `data`, of at most `max_len` bytes, into `output` at
+/// `position`, without allocating. Matches may refer to the data of `output`
+/// from `window_start`. Returns the position after the block, or an error as
+/// soon as a literal run or a match would go past the end of `output`.
+pub(crate) fn decompress_block_into(
+    output: &mut [u8],
+    window_start: usize,
+    mut position: usize,
+    mut data: &[u8],
+    max_len: usize,
+) -> DecompressResult<usize> {
+    let output_len = output.len();
+    let block_end = position.saturating_add(max_len);
+    let get_end = |position: usize, length: usize| {
+        let end = position.saturating_add(length);
+        if end > block_end {
+            Err(Lz4DecompressError::from_static_str(
+                "Decompressed block is too large",
+            ))
+        } else if end > output_len {
+            Err(Lz4DecompressError::buffer_too_small())
+        } else {
+            Ok(end)
         }
+    };
 
-        if self.buffer.is_empty() || self.buffer_start >= self.buffer.len() {
-            self.buffer.clear();
-            self.buffer_start = 0;
-            let bytes_read = self.read_block()?;
+    loop {
+        let token = Token(data.read_u8()?);
 
-            if bytes_read == 0 {
-                return Ok(0);
-            }
+        let literals_length =
+            get_lsic_int(&mut data, token.get_literals_length(), TOKEN_MAX_VAL)? as usize;
+        let literals = data
+            .get(..literals_length)
+            .ok_or_else(|| Lz4DecompressError::from_static_str("Literals are out of range"))?;
+        let literals_end = get_end(position, literals_length)?;
+        output[position..literals_end].copy_from_slice(literals);
+        position = literals_end;
+        data = &data[literals_length..];
+
+        if data.is_empty() {
+            return Ok(position);
+        }
+
+        let offset = data.read_u16::<LE>()? as usize;
+        if offset == 0 {
+            return Err(Lz4DecompressError::from_static_str("Offset is 0"));
+        }
+        let match_length = (get_lsic_int(&mut data, token.get_match_length(), TOKEN_MAX_VAL)?
+            + MATCH_LENGTH_OFFSET) as usize;
+        if offset > position - window_start {
+            return Err(Lz4DecompressError::from_static_str(
+                "Offset is out of range",
+            ));
+        }
+        let match_end = get_end(position, match_length)?;
+
+        let match_pos = position - offset;
+        while position < match_end {
+            let current_to_copy = min(match_end - position, position - match_pos);
+            output.copy_within(match_pos..match_pos + current_to_copy, position);
+            position += current_to_copy;
+        }
+    }
+}
+
+impl<R: Read> Read for Decompressor<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        if self.buffer_start >= self.buffer.len() && self.read_next_block()? == 0 {
+            return Ok(0);
         }
 
         let to_return = min(buf.len(), self.buffer.len() - self.buffer_start);
@@ -225,29 +786,33 @@ impl<R: Read> Read for Decompressor<R> {
     }
 }
 
-fn get_block_max_size(index: u8) -> DecompressResult<usize> {
-    if index < 3 || index > 7 {
+pub(crate) fn get_block_max_size(index: u8) -> DecompressResult<usize> {
+    if !(4..=7).contains(&index) {
         return Err(Lz4DecompressError::from_static_str(
             "Block max size is invalid",
         ));
     }
 
-    Ok((index as usize - 4).pow(4) * 65536)
+    Ok(compressor::get_block_max_size(index))
 }
 
-fn get_lsic_int(data: &mut &[u8], initial_val: u8, max_val: u8) -> u32 {
+pub(crate) fn get_lsic_int(
+    data: &mut &[u8],
+    initial_val: u8,
+    max_val: u8,
+) -> DecompressResult<u32> {
     let mut val = initial_val as u32;
 
     if initial_val < max_val {
-        return val;
+        return Ok(val);
     }
 
     loop {
-        let current_val = data.read_u8().unwrap();
+        let current_val = data.read_u8()?;
         val += current_val as u32;
 
         if current_val < 255 {
-            return val;
+            return Ok(val);
         }
     }
 }
@@ -20,6 +20,7 @@ bitfield! {
 }
 
 bitfield! {
+    #[derive(Clone, Copy)]
     pub struct BlockSize(u32);
     impl Debug;
 
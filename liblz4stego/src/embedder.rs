@@ -0,0 +1,82 @@
+//! Hides data in existing LZ4 files from any compressor, keeping their frame
+//! descriptors, block boundaries and sequences. Each match with several
+//! earlier occurrences of its bytes in range carries a digit: the recency
+//! rank of the one it refers to. As the files get no steganography header,
+//! the digits use the original coding of the hidden data.
+
+use std::io::{Read, Write};
+
+use crate::errors::DecompressResult;
+use crate::frame_rewriter::{rewrite_frames, BlockRewriter, Sequence, SourceFinder};
+use crate::numeral_coding;
+
+struct Embedder<'a> {
+    hidden_data_encoder: numeral_coding::Decoder<'a>,
+}
+
+impl<'a> BlockRewriter for Embedder<'a> {
+    fn rewrite_block(&mut self, window: &[u8], sequences: &mut [Sequence]) -> DecompressResult<()> {
+        let mut finder = SourceFinder::new(window);
+        for sequence in sequences {
+            let sources: Vec<usize> = finder
+                .get_sources(sequence.index, sequence.match_length)
+                .collect();
+            if sources.len() > 1 {
+                let rank = self.hidden_data_encoder.decode_value(sources.len() as u16);
+                sequence.match_index = sources[rank as usize];
+            }
+        }
+
+        Ok(())
+    }
+}
+
+struct Extractor {
+    hidden_data_decoder: numeral_coding::Encoder,
+}
+
+impl BlockRewriter for Extractor {
+    fn rewrite_block(&mut self, window: &[u8], sequences: &mut [Sequence]) -> DecompressResult<()> {
+        let mut finder = SourceFinder::new(window);
+        for sequence in sequences {
+            let sources: Vec<usize> = finder
+                .get_sources(sequence.index, sequence.match_length)
+                .collect();
+            let rank = sources
+                .iter()
+                .position(|source| *source == sequence.match_index);
+            if let Some(rank) = rank.filter(|_| sources.len() > 1) {
+                self.hidden_data_decoder
+                    .add_value(rank as u16, sources.len() as u16);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Copies the LZ4 frames of `input_read` to `output_write`, pointing the
+/// matches at the occurrences that encode `hidden_data`. Returns the number of
+/// bytes that could be hidden, like the report of `Compressor::finish`.
+pub fn embed<R: Read, W: Write>(
+    input_read: R,
+    output_write: W,
+    hidden_data: &[u8],
+) -> DecompressResult<usize> {
+    let mut embedder = Embedder {
+        hidden_data_encoder: numeral_coding::Decoder::new(hidden_data).into_legacy(),
+    };
+    rewrite_frames(input_read, output_write, &mut embedder)?;
+
+    Ok(embedder.hidden_data_encoder.get_available_bytes())
+}
+
+/// Recovers the data hidden by `embed`.
+pub fn extract<R: Read>(input_read: R) -> DecompressResult<Vec<u8>> {
+    let mut extractor = Extractor {
+        hidden_data_decoder: numeral_coding::Encoder::new_legacy(),
+    };
+    rewrite_frames(input_read, std::io::sink(), &mut extractor)?;
+
+    Ok(extractor.hidden_data_decoder.finish())
+}
@@ -1,20 +1,46 @@
 use std::{error, fmt};
 
+/// What a `Lz4DecompressError` is about, for callers that handle some of them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Lz4DecompressErrorKind {
+    /// The input is not valid, or was cut short
+    InvalidData,
+    /// The output buffer given to `decompress_into` is too small
+    BufferTooSmall,
+}
+
 #[derive(Debug, Clone)]
 pub struct Lz4DecompressError {
+    kind: Lz4DecompressErrorKind,
     message: String,
 }
 
 impl Lz4DecompressError {
     pub fn from_string(message: String) -> Self {
-        Lz4DecompressError { message }
+        Lz4DecompressError {
+            kind: Lz4DecompressErrorKind::InvalidData,
+            message,
+        }
     }
 
     pub fn from_static_str(message: &'static str) -> Self {
+        Self::from_string(message.to_owned())
+    }
+
+    pub fn buffer_too_small() -> Self {
         Lz4DecompressError {
-            message: message.to_owned(),
+            kind: Lz4DecompressErrorKind::BufferTooSmall,
+            message: "Output buffer is too small".to_owned(),
         }
     }
+
+    pub fn get_kind(&self) -> Lz4DecompressErrorKind {
+        self.kind
+    }
+
+    pub fn get_message(&self) -> &str {
+        &self.message
+    }
 }
 
 impl error::Error for Lz4DecompressError {}
@@ -42,3 +68,31 @@ impl fmt::Display for Lz4DecompressError {
 }
 
 pub type DecompressResult<T> = std::result::Result<T, Lz4DecompressError>;
+
+/// Invalid combination or value of `CompressorBuilder` settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptionsError {
+    /// The two named settings cannot be used together
+    Conflict(&'static str, &'static str),
+    /// The named setting is out of its range
+    InvalidValue(&'static str),
+}
+
+impl error::Error for OptionsError {}
+
+impl From<OptionsError> for std::io::Error {
+    fn from(e: OptionsError) -> Self {
+        Self::new(std::io::ErrorKind::InvalidInput, e)
+    }
+}
+
+impl fmt::Display for OptionsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OptionsError::Conflict(option, other) => {
+                write!(f, "{} cannot be used with {}", option, other)
+            }
+            OptionsError::InvalidValue(option) => write!(f, "invalid value of {}", option),
+        }
+    }
+}
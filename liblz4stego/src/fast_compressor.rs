@@ -0,0 +1,228 @@
+use byteorder::{ByteOrder, LE};
+
+use crate::compressor::output_sequence;
+use crate::constants::{
+    END_LITERAL_NUM, MATCH_LENGTH_OFFSET, MAX_OFFSET, MF_LIMIT, MIN_COMPRESS_LENGTH, TOKEN_MAX_VAL,
+};
+
+const HASH_LOG: u32 = 12;
+const SKIP_TRIGGER: u32 = 6;
+/// Blocks shorter than this use a larger table of 16-bit positions
+const LIMIT_64K: usize = 64 * 1024 + MF_LIMIT - 1;
+
+/// A match found by the parser: its position, the position of the previous
+/// occurrence, its length and the start of the literals preceding it.
+#[derive(Debug, Clone, Copy)]
+pub struct FoundMatch {
+    pub index: usize,
+    pub match_index: usize,
+    pub match_length: usize,
+    pub anchor: usize,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum TableType {
+    U16,
+    U32,
+}
+
+fn hash(data: &[u8], index: usize, table_type: TableType) -> usize {
+    match table_type {
+        TableType::U16 => {
+            (LE::read_u32(&data[index..]).wrapping_mul(2654435761) >> (32 - (HASH_LOG + 1)))
+                as usize
+        }
+        TableType::U32 => {
+            ((LE::read_u64(&data[index..]) << 24).wrapping_mul(889523592379) >> (64 - HASH_LOG))
+                as usize
+        }
+    }
+}
+
+fn read_u32(data: &[u8], index: usize) -> u32 {
+    LE::read_u32(&data[index..])
+}
+
+fn count_common(data: &[u8], mut index: usize, mut match_index: usize, limit: usize) -> usize {
+    let start = index;
+    while index < limit && data[index] == data[match_index] {
+        index += 1;
+        match_index += 1;
+    }
+
+    index - start
+}
+
+/// Whether the reference compressor gives up on a sequence with the given
+/// literal and match lengths when its output is limited to `max_output` bytes.
+/// The checks are conservative, so they are reproduced rather than compared
+/// with the actual size.
+fn exceeds_limit(
+    output_len: usize,
+    literals_length: usize,
+    match_length: usize,
+    check_literals: bool,
+    max_output: Option<usize>,
+) -> bool {
+    let max_output = match max_output {
+        Some(max_output) => max_output,
+        None => return false,
+    };
+
+    let output_len = output_len + 1;
+    if check_literals
+        && output_len + literals_length + 2 + 1 + END_LITERAL_NUM + literals_length / 255
+            > max_output
+    {
+        return true;
+    }
+
+    let literals_length_bytes = if literals_length >= TOKEN_MAX_VAL as usize {
+        (literals_length - TOKEN_MAX_VAL as usize) / 255 + 1
+    } else {
+        0
+    };
+    let output_len = output_len + literals_length_bytes + literals_length + 2;
+    let match_code = match_length - MATCH_LENGTH_OFFSET as usize;
+
+    output_len + 1 + END_LITERAL_NUM + (match_code + 240) / 255 > max_output
+}
+
+/// Single-probe hash table parser, following the reference LZ4 fast compressor
+/// byte for byte. `choose_match` picks the previous occurrence to refer to for
+/// each match found, which must give the same match length and not extend it
+/// backwards. Returns `false` if the output would not fit in `max_output`
+/// bytes, under the same conditions as the reference compressor.
+pub fn compress_block<F: FnMut(FoundMatch) -> usize>(
+    data: &[u8],
+    acceleration: u32,
+    max_output: Option<usize>,
+    mut choose_match: F,
+    output: &mut Vec<u8>,
+) -> bool {
+    let output_start = output.len();
+    let mut anchor = 0;
+
+    if data.len() >= MIN_COMPRESS_LENGTH {
+        let table_type = if data.len() < LIMIT_64K {
+            TableType::U16
+        } else {
+            TableType::U32
+        };
+        let table_log = match table_type {
+            TableType::U16 => HASH_LOG + 1,
+            TableType::U32 => HASH_LOG,
+        };
+        let mut table = vec![0u32; 1 << table_log];
+        let mf_limit_plus_one = data.len() - MF_LIMIT + 1;
+        let match_limit = data.len() - END_LITERAL_NUM;
+
+        table[hash(data, 0, table_type)] = 0;
+        let mut ip = 1;
+        let mut forward_hash = hash(data, ip, table_type);
+
+        'search: loop {
+            let mut forward_ip = ip;
+            let mut step = 1;
+            let mut search_match_nb = acceleration << SKIP_TRIGGER;
+            let mut match_index;
+
+            loop {
+                let current_hash = forward_hash;
+                ip = forward_ip;
+                forward_ip += step;
+                step = (search_match_nb >> SKIP_TRIGGER) as usize;
+                search_match_nb += 1;
+
+                if forward_ip > mf_limit_plus_one {
+                    break 'search;
+                }
+
+                match_index = table[current_hash] as usize;
+                forward_hash = hash(data, forward_ip, table_type);
+                table[current_hash] = ip as u32;
+
+                if match_index + MAX_OFFSET >= ip
+                    && read_u32(data, match_index) == read_u32(data, ip)
+                {
+                    break;
+                }
+            }
+
+            while ip > anchor && match_index > 0 && data[ip - 1] == data[match_index - 1] {
+                ip -= 1;
+                match_index -= 1;
+            }
+
+            let mut check_literals = true;
+            loop {
+                let match_length = MATCH_LENGTH_OFFSET as usize
+                    + count_common(
+                        data,
+                        ip + MATCH_LENGTH_OFFSET as usize,
+                        match_index + MATCH_LENGTH_OFFSET as usize,
+                        match_limit,
+                    );
+                if exceeds_limit(
+                    output.len() - output_start,
+                    ip - anchor,
+                    match_length,
+                    check_literals,
+                    max_output,
+                ) {
+                    return false;
+                }
+
+                let chosen_index = choose_match(FoundMatch {
+                    index: ip,
+                    match_index,
+                    match_length,
+                    anchor,
+                });
+                output_sequence(
+                    &data[anchor..ip],
+                    (ip - chosen_index) as u16,
+                    match_length as u32,
+                    output,
+                );
+                ip += match_length;
+                anchor = ip;
+
+                if ip >= mf_limit_plus_one {
+                    break 'search;
+                }
+
+                table[hash(data, ip - 2, table_type)] = (ip - 2) as u32;
+
+                let current_hash = hash(data, ip, table_type);
+                match_index = table[current_hash] as usize;
+                table[current_hash] = ip as u32;
+                if match_index + MAX_OFFSET < ip
+                    || read_u32(data, match_index) != read_u32(data, ip)
+                {
+                    break;
+                }
+                check_literals = false;
+            }
+
+            ip += 1;
+            forward_hash = hash(data, ip, table_type);
+        }
+    }
+
+    let last_run = data.len() - anchor;
+    if let Some(max_output) = max_output {
+        if output.len() - output_start
+            + last_run
+            + 1
+            + (last_run + 255 - TOKEN_MAX_VAL as usize) / 255
+            > max_output
+        {
+            return false;
+        }
+    }
+
+    output_sequence(&data[anchor..], 0, MATCH_LENGTH_OFFSET, output);
+
+    true
+}
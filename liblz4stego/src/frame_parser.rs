@@ -0,0 +1,231 @@
+//! The structure of LZ4 streams, shared by everything that reads them: any
+//! number of skippable frames and LZ4 frames, each LZ4 frame being a header,
+//! blocks and an end mark. The parser does no I/O; it is given the bytes of
+//! one step at a time, so that it can be driven by a blocking reader as well
+//! as by data pushed in chunks.
+
+use std::cmp::min;
+use std::io::{ErrorKind, Read};
+
+use byteorder::{ByteOrder, LE};
+use xxhash_rust::xxh32::xxh32;
+
+use crate::compressor::FrameOptions;
+use crate::constants::{LZ4_MAGIC_NUMBER, SKIPPABLE_MAGIC_MASK, SKIPPABLE_MAGIC_NUMBER};
+use crate::decompressor::get_block_max_size;
+use crate::descriptors::{BdByte, BlockSize, FlgByte};
+use crate::errors::{DecompressResult, Lz4DecompressError};
+use crate::stego_header::MAX_STEGO_HEADER_SIZE;
+
+/// Most bytes of a skipped skippable frame passed in a single step.
+const SKIPPED_CHUNK_SIZE: usize = 64 * 1024;
+
+/// What the bytes of a step completed. The data of blocks and skippable
+/// frames is at the start of the bytes passed to `FrameParser::advance`.
+#[derive(Clone, Copy)]
+pub(crate) enum Event {
+    /// Part of a header, nothing to act on yet
+    Partial,
+    /// Skippable frame that may be the lz4stego header, with the size of its
+    /// content. The content of the other skippable frames, whose size is set
+    /// by the input, is passed in `Partial` steps of bounded size instead.
+    SkippableFrame(usize),
+    /// Header of an LZ4 frame
+    FrameHeader(FrameOptions),
+    /// Size of a block, whose data comes next
+    BlockSize,
+    /// Block, whose checksum was already checked
+    Block(BlockSize),
+    /// End of the frame, with its content checksum if there is one
+    FrameEnd(Option<u32>),
+}
+
+#[derive(Clone, Copy)]
+enum Step {
+    MagicNumber,
+    /// Whether the content may be the lz4stego header
+    SkippableSize(bool),
+    SkippableContent(usize),
+    SkippedContent(usize),
+    Flg,
+    Descriptor(u8),
+    BlockSize,
+    BlockData(BlockSize),
+    ContentChecksum,
+}
+
+pub(crate) struct FrameParser {
+    step: Step,
+    frame_options: FrameOptions,
+    block_max_size: usize,
+}
+
+impl FrameParser {
+    pub fn new() -> Self {
+        Self {
+            step: Step::MagicNumber,
+            frame_options: FrameOptions::default(),
+            block_max_size: 0,
+        }
+    }
+
+    /// Number of bytes the next step needs.
+    pub fn get_needed_len(&self) -> usize {
+        match self.step {
+            Step::MagicNumber
+            | Step::SkippableSize(_)
+            | Step::BlockSize
+            | Step::ContentChecksum => 4,
+            Step::SkippableContent(size) => size,
+            Step::SkippedContent(remaining) => min(remaining, SKIPPED_CHUNK_SIZE),
+            Step::Flg => 1,
+            // BD byte, content size and header checksum
+            Step::Descriptor(flg) => {
+                if FlgByte(flg).is_content_size_added() {
+                    10
+                } else {
+                    2
+                }
+            }
+            Step::BlockData(block_size) => {
+                let checksum_size = if self.frame_options.block_checksum {
+                    4
+                } else {
+                    0
+                };
+                block_size.get_block_size() as usize + checksum_size
+            }
+        }
+    }
+
+    /// Whether the parser is between frames, where the stream may end.
+    pub fn is_between_frames(&self) -> bool {
+        matches!(self.step, Step::MagicNumber)
+    }
+
+    /// Parses the next step, `data` being exactly `get_needed_len` bytes long.
+    pub fn advance(&mut self, data: &[u8]) -> DecompressResult<Event> {
+        debug_assert_eq!(data.len(), self.get_needed_len());
+
+        let (next_step, event) = match self.step {
+            Step::MagicNumber => {
+                let magic_number = LE::read_u32(data);
+                if magic_number & SKIPPABLE_MAGIC_MASK == SKIPPABLE_MAGIC_NUMBER {
+                    let maybe_header = magic_number == SKIPPABLE_MAGIC_NUMBER;
+                    (Step::SkippableSize(maybe_header), Event::Partial)
+                } else if magic_number == LZ4_MAGIC_NUMBER {
+                    (Step::Flg, Event::Partial)
+                } else {
+                    return Err(Lz4DecompressError::from_static_str("Invalid header"));
+                }
+            }
+            Step::SkippableSize(maybe_header) => {
+                let size = LE::read_u32(data) as usize;
+                if maybe_header && size <= MAX_STEGO_HEADER_SIZE {
+                    (Step::SkippableContent(size), Event::Partial)
+                } else if size > 0 {
+                    (Step::SkippedContent(size), Event::Partial)
+                } else {
+                    (Step::MagicNumber, Event::Partial)
+                }
+            }
+            Step::SkippableContent(size) => (Step::MagicNumber, Event::SkippableFrame(size)),
+            Step::SkippedContent(remaining) => {
+                if remaining > data.len() {
+                    (Step::SkippedContent(remaining - data.len()), Event::Partial)
+                } else {
+                    (Step::MagicNumber, Event::Partial)
+                }
+            }
+            Step::Flg => {
+                // TODO check all releveant flags
+                let flg = FlgByte(data[0]);
+                if flg.get_version() != 1 {
+                    return Err(Lz4DecompressError::from_static_str("Version is not 1"));
+                }
+                if flg.is_dictionary_id_set() {
+                    return Err(Lz4DecompressError::from_static_str(
+                        "Dictionary ID is not supported",
+                    ));
+                }
+
+                (Step::Descriptor(data[0]), Event::Partial)
+            }
+            Step::Descriptor(flg) => {
+                let flg = FlgByte(flg);
+                let block_size_id = BdByte(data[0]).get_block_max_size();
+                self.block_max_size = get_block_max_size(block_size_id)?;
+                // TODO check HC
+                self.frame_options = FrameOptions {
+                    block_size_id: Some(block_size_id),
+                    block_independent: flg.is_block_independent(),
+                    block_checksum: flg.is_block_checksum_added(),
+                    content_checksum: flg.is_content_checksum_added(),
+                    content_size: if flg.is_content_size_added() {
+                        Some(LE::read_u64(&data[1..]))
+                    } else {
+                        None
+                    },
+                };
+
+                (Step::BlockSize, Event::FrameHeader(self.frame_options))
+            }
+            Step::BlockSize => {
+                let block_size = BlockSize(LE::read_u32(data));
+                if block_size.0 == 0 {
+                    if self.frame_options.content_checksum {
+                        (Step::ContentChecksum, Event::Partial)
+                    } else {
+                        (Step::MagicNumber, Event::FrameEnd(None))
+                    }
+                } else if block_size.get_block_size() as usize > self.block_max_size {
+                    return Err(Lz4DecompressError::from_static_str("Block is too large"));
+                } else {
+                    (Step::BlockData(block_size), Event::BlockSize)
+                }
+            }
+            Step::BlockData(block_size) => {
+                let size = block_size.get_block_size() as usize;
+                if self.frame_options.block_checksum
+                    && LE::read_u32(&data[size..]) != xxh32(&data[..size], 0)
+                {
+                    return Err(Lz4DecompressError::from_static_str(
+                        "Block checksum is invalid",
+                    ));
+                }
+
+                (Step::BlockSize, Event::Block(block_size))
+            }
+            Step::ContentChecksum => (Step::MagicNumber, Event::FrameEnd(Some(LE::read_u32(data)))),
+        };
+        self.step = next_step;
+
+        Ok(event)
+    }
+
+    /// Reads the bytes of the next step from `input_read` into `buffer` and
+    /// parses them. Returns `None` if the stream ends between frames.
+    pub fn read_event<R: Read>(
+        &mut self,
+        mut input_read: R,
+        buffer: &mut Vec<u8>,
+    ) -> DecompressResult<Option<Event>> {
+        let len = self.get_needed_len();
+        if buffer.len() < len {
+            buffer.resize(len, 0);
+        }
+
+        let mut filled = 0;
+        while filled < len {
+            match input_read.read(&mut buffer[filled..len]) {
+                Ok(0) if filled == 0 && self.is_between_frames() => return Ok(None),
+                Ok(0) => return Err(std::io::Error::from(ErrorKind::UnexpectedEof).into()),
+                Ok(count) => filled += count,
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        self.advance(&buffer[..len]).map(Some)
+    }
+}
@@ -0,0 +1,363 @@
+//! Copies LZ4 frames unchanged, except for the match offsets, which can be
+//! pointed at any other earlier occurrence of the same bytes. The sequence
+//! structure, and so the compressed size, stays the same; only the block
+//! checksums have to be computed again. This also makes it possible to
+//! rewrite the frames in place.
+
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+
+use byteorder::{ByteOrder, LE};
+use xxhash_rust::xxh32::{xxh32, Xxh32};
+
+use crate::compressor::FrameOptions;
+use crate::constants::{MATCH_LENGTH_OFFSET, MAX_OFFSET, REFERENCE_BLOCK_SIZE, TOKEN_MAX_VAL};
+use crate::descriptors::{BlockSize, Token};
+use crate::errors::{DecompressResult, Lz4DecompressError};
+use crate::frame_parser::{Event, FrameParser};
+
+/// A match of a block. Positions are relative to the window passed along,
+/// which starts with the data of the preceding blocks if they are linked.
+#[derive(Debug, Clone, Copy)]
+pub struct Sequence {
+    pub index: usize,
+    pub match_index: usize,
+    pub match_length: usize,
+    offset_position: usize,
+}
+
+/// Changes the `match_index` of the sequences of every compressed block. The
+/// new match indices must refer to the same bytes.
+pub trait BlockRewriter {
+    /// Called with the content of every skippable frame, in order.
+    fn read_skippable_frame(&mut self, _content: &[u8]) -> DecompressResult<()> {
+        Ok(())
+    }
+
+    fn rewrite_block(&mut self, window: &[u8], sequences: &mut [Sequence]) -> DecompressResult<()>;
+}
+
+/// Source of the frames, which also receives them back, either unchanged or
+/// rewritten, after they are read.
+trait FrameStream: Read {
+    fn keep(&mut self, data: &[u8]) -> std::io::Result<()>;
+    fn replace(&mut self, data: &[u8]) -> std::io::Result<()>;
+}
+
+struct CopyingStream<R: Read, W: Write> {
+    input_read: R,
+    output_write: W,
+}
+
+impl<R: Read, W: Write> Read for CopyingStream<R, W> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.input_read.read(buf)
+    }
+}
+
+impl<R: Read, W: Write> FrameStream for CopyingStream<R, W> {
+    fn keep(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.output_write.write_all(data)
+    }
+
+    fn replace(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.output_write.write_all(data)
+    }
+}
+
+/// Reads the file, keeping the rewritten blocks and their positions until
+/// all of it is read.
+struct InPlaceStream<F: Read> {
+    file: BufReader<F>,
+    position: u64,
+    replacements: Vec<(u64, Vec<u8>)>,
+}
+
+impl<F: Read> Read for InPlaceStream<F> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = self.file.read(buf)?;
+        self.position += count as u64;
+        Ok(count)
+    }
+}
+
+impl<F: Read> FrameStream for InPlaceStream<F> {
+    fn keep(&mut self, _data: &[u8]) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    fn replace(&mut self, data: &[u8]) -> std::io::Result<()> {
+        self.replacements
+            .push((self.position - data.len() as u64, data.to_vec()));
+        Ok(())
+    }
+}
+
+/// Copies all the frames of `input_read` to `output_write`, rewriting the
+/// compressed blocks with `rewriter`.
+pub fn rewrite_frames<R: Read, W: Write, B: BlockRewriter>(
+    input_read: R,
+    output_write: W,
+    rewriter: &mut B,
+) -> DecompressResult<()> {
+    rewrite_stream(
+        &mut CopyingStream {
+            input_read,
+            output_write,
+        },
+        rewriter,
+    )
+}
+
+/// Like `rewrite_frames`, but writes the rewritten blocks back to `file`,
+/// leaving all other bytes untouched. Nothing is written unless the whole
+/// file, starting at its current position, is read and rewritten without an
+/// error; the rewritten blocks are held in memory until then.
+pub fn rewrite_frames_in_place<F: Read + Write + Seek, B: BlockRewriter>(
+    mut file: F,
+    rewriter: &mut B,
+) -> DecompressResult<()> {
+    let mut stream = InPlaceStream {
+        position: file.stream_position()?,
+        file: BufReader::new(file),
+        replacements: Vec::new(),
+    };
+    rewrite_stream(&mut stream, rewriter)?;
+
+    let mut file = stream.file.into_inner();
+    for (position, data) in stream.replacements {
+        file.seek(SeekFrom::Start(position))?;
+        file.write_all(&data)?;
+    }
+    file.flush()?;
+
+    Ok(())
+}
+
+fn rewrite_stream<S: FrameStream, B: BlockRewriter>(
+    stream: &mut S,
+    rewriter: &mut B,
+) -> DecompressResult<()> {
+    let mut parser = FrameParser::new();
+    let mut data = Vec::new();
+    let mut frame_options = FrameOptions::default();
+    let mut window = Vec::new();
+    let mut hash = Xxh32::new(0);
+
+    loop {
+        let len = parser.get_needed_len();
+        let event = match parser.read_event(&mut *stream, &mut data)? {
+            Some(event) => event,
+            None => return Ok(()),
+        };
+        let step_data = &mut data[..len];
+
+        match event {
+            Event::SkippableFrame(_) => {
+                stream.keep(step_data)?;
+                rewriter.read_skippable_frame(step_data)?;
+            }
+            Event::FrameHeader(options) => {
+                // The header is copied as it is
+                stream.keep(step_data)?;
+                frame_options = options;
+                window.clear();
+                hash = Xxh32::new(0);
+            }
+            Event::Block(block_size_desc) => {
+                if frame_options.block_independent {
+                    window.clear();
+                } else if window.len() > MAX_OFFSET {
+                    window.drain(..window.len() - MAX_OFFSET);
+                }
+                let start_index = window.len();
+
+                rewrite_block(
+                    stream,
+                    rewriter,
+                    step_data,
+                    block_size_desc,
+                    frame_options.block_checksum,
+                    &mut window,
+                )?;
+                hash.update(&window[start_index..]);
+            }
+            Event::FrameEnd(checksum) => {
+                if checksum.is_some_and(|checksum| checksum != hash.digest()) {
+                    return Err(Lz4DecompressError::from_static_str("Checksum is invalid"));
+                }
+                stream.keep(step_data)?;
+            }
+            Event::Partial | Event::BlockSize => stream.keep(step_data)?,
+        }
+    }
+}
+
+/// Rewrites `block`, followed by its checksum if there is one, appending its
+/// content to `window`.
+fn rewrite_block<S: FrameStream, B: BlockRewriter>(
+    stream: &mut S,
+    rewriter: &mut B,
+    block: &mut [u8],
+    block_size_desc: BlockSize,
+    block_checksum: bool,
+    window: &mut Vec<u8>,
+) -> DecompressResult<()> {
+    let block_data_size = block_size_desc.get_block_size() as usize;
+    if block_size_desc.is_uncompressed() {
+        window.extend_from_slice(&block[..block_data_size]);
+        stream.keep(block)?;
+        return Ok(());
+    }
+
+    let mut sequences = decode_block(&block[..block_data_size], window)?;
+    rewriter.rewrite_block(window, &mut sequences)?;
+
+    let mut changed = false;
+    for sequence in sequences {
+        debug_assert_eq!(
+            window[sequence.match_index..sequence.match_index + sequence.match_length],
+            window[sequence.index..sequence.index + sequence.match_length]
+        );
+        let offset = (sequence.index - sequence.match_index) as u16;
+        if LE::read_u16(&block[sequence.offset_position..]) != offset {
+            LE::write_u16(&mut block[sequence.offset_position..], offset);
+            changed = true;
+        }
+    }
+
+    if changed && block_checksum {
+        let checksum = xxh32(&block[..block_data_size], 0);
+        LE::write_u32(&mut block[block_data_size..], checksum);
+    }
+    if changed {
+        stream.replace(block)?;
+    } else {
+        stream.keep(block)?;
+    }
+
+    Ok(())
+}
+
+/// Appends the content of a compressed block to `window`, checking every
+/// length and offset, as the input may come from anywhere.
+fn decode_block(block: &[u8], window: &mut Vec<u8>) -> DecompressResult<Vec<Sequence>> {
+    let invalid = || Lz4DecompressError::from_static_str("Block data is invalid");
+    let max_len = window.len() + REFERENCE_BLOCK_SIZE;
+    let mut sequences = Vec::new();
+    let mut position = 0;
+
+    loop {
+        let token = Token(*block.get(position).ok_or_else(invalid)?);
+        position += 1;
+
+        let literals_length = read_length(block, &mut position, token.get_literals_length())?;
+        let literals = position
+            .checked_add(literals_length)
+            .and_then(|end| block.get(position..end))
+            .ok_or_else(invalid)?;
+        if window.len() + literals_length > max_len {
+            return Err(invalid());
+        }
+        window.extend_from_slice(literals);
+        position += literals_length;
+
+        if position == block.len() {
+            return Ok(sequences);
+        }
+
+        let offset_position = position;
+        let offset = block
+            .get(position..position + 2)
+            .map(LE::read_u16)
+            .ok_or_else(invalid)? as usize;
+        position += 2;
+        let match_length = read_length(block, &mut position, token.get_match_length())?
+            .saturating_add(MATCH_LENGTH_OFFSET as usize);
+
+        let index = window.len();
+        if offset == 0 || offset > index || index + match_length > max_len {
+            return Err(invalid());
+        }
+        let match_index = index - offset;
+        for i in 0..match_length {
+            window.push(window[match_index + i]);
+        }
+
+        sequences.push(Sequence {
+            index,
+            match_index,
+            match_length,
+            offset_position,
+        });
+    }
+}
+
+fn read_length(block: &[u8], position: &mut usize, initial_val: u8) -> DecompressResult<usize> {
+    let mut val = initial_val as usize;
+    if initial_val < TOKEN_MAX_VAL {
+        return Ok(val);
+    }
+
+    loop {
+        let current_val = *block
+            .get(*position)
+            .ok_or_else(|| Lz4DecompressError::from_static_str("Block data is invalid"))?;
+        *position += 1;
+        val = val.saturating_add(current_val as usize);
+
+        if current_val < 255 {
+            return Ok(val);
+        }
+    }
+}
+
+/// Finds the earlier occurrences of the bytes of a match, using chains of
+/// positions starting with the same 4 bytes.
+pub struct SourceFinder<'a> {
+    window: &'a [u8],
+    heads: HashMap<u32, usize>,
+    previous: Vec<Option<usize>>,
+}
+
+impl<'a> SourceFinder<'a> {
+    pub fn new(window: &'a [u8]) -> Self {
+        Self {
+            window,
+            heads: HashMap::new(),
+            previous: Vec::new(),
+        }
+    }
+
+    fn index_until(&mut self, index: usize) {
+        while self.previous.len() < index {
+            let position = self.previous.len();
+            let prefix = LE::read_u32(&self.window[position..]);
+            self.previous.push(self.heads.insert(prefix, position));
+        }
+    }
+
+    /// Positions within the offset range, nearest first, holding the same
+    /// `match_length` bytes as `index`. Calls must not go backwards.
+    pub fn get_sources(
+        &mut self,
+        index: usize,
+        match_length: usize,
+    ) -> impl Iterator<Item = usize> + '_ {
+        self.index_until(index);
+
+        let window = self.window;
+        let previous = &self.previous;
+        let target = &window[index..index + match_length];
+        let min_index = index.saturating_sub(MAX_OFFSET);
+        let mut next = self.heads.get(&LE::read_u32(target)).copied();
+
+        std::iter::from_fn(move || loop {
+            let source = next.filter(|source| *source >= min_index)?;
+            next = previous[source];
+            if &window[source..source + match_length] == target {
+                return Some(source);
+            }
+        })
+    }
+}
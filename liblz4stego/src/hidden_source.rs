@@ -0,0 +1,125 @@
+//! Hidden data held in memory or pulled lazily from a reader. The coders read
+//! it by absolute position, so that their states can be saved and restored
+//! while a block is tried; bytes read from a reader are dropped once they are
+//! before the last `release` position.
+
+use std::collections::VecDeque;
+use std::io::{ErrorKind, Read};
+use std::sync::{Arc, Mutex};
+
+const READ_CHUNK_SIZE: usize = 4096;
+
+#[derive(Clone)]
+pub struct HiddenSource<'a> {
+    data: SourceData<'a>,
+    len: Option<usize>,
+}
+
+/// Data in memory is read without locking, as the coders read it byte by
+/// byte.
+#[derive(Clone)]
+enum SourceData<'a> {
+    Borrowed(&'a [u8]),
+    Owned(Arc<Vec<u8>>),
+    Reader(Arc<Mutex<SharedSource<'a>>>),
+}
+
+struct SharedSource<'a> {
+    reader: Box<dyn Read + Send + 'a>,
+    buffer: VecDeque<u8>,
+    /// Position of the first byte of `buffer`
+    start: usize,
+    ended: bool,
+    error: Option<std::io::Error>,
+}
+
+impl<'a> HiddenSource<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data: SourceData::Borrowed(data),
+            len: Some(data.len()),
+        }
+    }
+
+    /// Source of unknown length, ending when the reader does.
+    pub fn from_reader<R: Read + Send + 'a>(reader: R) -> Self {
+        Self {
+            data: SourceData::Reader(Arc::new(Mutex::new(SharedSource {
+                reader: Box::new(reader),
+                buffer: VecDeque::new(),
+                start: 0,
+                ended: false,
+                error: None,
+            }))),
+            len: None,
+        }
+    }
+
+    /// Total length, if known before the data is read.
+    pub fn get_len(&self) -> Option<usize> {
+        self.len
+    }
+
+    /// Byte at `position`, or `None` past the end of the data. A read error
+    /// ends the data as well; it is returned by `take_error`.
+    pub fn get_byte(&self, position: usize) -> Option<u8> {
+        let shared = match &self.data {
+            SourceData::Borrowed(data) => return data.get(position).copied(),
+            SourceData::Owned(data) => return data.get(position).copied(),
+            SourceData::Reader(shared) => shared,
+        };
+
+        let mut shared = shared.lock().unwrap();
+        let offset = position
+            .checked_sub(shared.start)
+            .expect("Byte was already released");
+        while offset >= shared.buffer.len() && !shared.ended {
+            shared.fill();
+        }
+
+        shared.buffer.get(offset).copied()
+    }
+
+    /// Drops the bytes before `position`, which are not read again.
+    pub fn release(&self, position: usize) {
+        if let SourceData::Reader(shared) = &self.data {
+            let mut shared = shared.lock().unwrap();
+            let count = position
+                .saturating_sub(shared.start)
+                .min(shared.buffer.len());
+            shared.buffer.drain(..count);
+            shared.start += count;
+        }
+    }
+
+    pub fn take_error(&self) -> Option<std::io::Error> {
+        match &self.data {
+            SourceData::Reader(shared) => shared.lock().unwrap().error.take(),
+            _ => None,
+        }
+    }
+}
+
+impl HiddenSource<'static> {
+    pub fn from_vec(data: Vec<u8>) -> Self {
+        Self {
+            len: Some(data.len()),
+            data: SourceData::Owned(Arc::new(data)),
+        }
+    }
+}
+
+impl<'a> SharedSource<'a> {
+    fn fill(&mut self) {
+        let mut chunk = [0; READ_CHUNK_SIZE];
+        match self.reader.read(&mut chunk) {
+            Ok(0) => self.ended = true,
+            Ok(count) => self.buffer.extend(&chunk[..count]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                self.error = Some(e);
+                self.ended = true;
+            }
+        }
+    }
+}
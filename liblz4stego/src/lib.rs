@@ -1,11 +1,34 @@
+mod block_planner;
 mod constants;
 mod descriptors;
+mod fast_compressor;
+mod frame_parser;
+mod frame_rewriter;
+mod hidden_source;
 mod numeral_coding;
 mod occurrence_map;
+mod oneshot;
+mod shaped_coding;
+mod stego_header;
+mod trellis_coding;
 
+pub mod analysis;
+#[cfg(feature = "tokio")]
+pub mod async_io;
+pub mod builder;
+pub mod candidate_policy;
+pub mod capacity;
 pub mod compressor;
 pub mod decompressor;
+pub mod embedder;
 pub mod errors;
+pub mod offset_distribution;
+pub mod patcher;
+pub mod push_decoder;
+pub mod report;
+pub mod sanitizer;
+
+pub use oneshot::{compress, decompress, decompress_into, HiddenDataMode};
 
 #[cfg(test)]
 mod tests;
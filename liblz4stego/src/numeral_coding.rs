@@ -1,77 +1,614 @@
-use byteorder::ReadBytesExt;
+//! Conversion between the hidden data and the digits carried by the choices of
+//! the compressor, using a range coder run in reverse: the compressor decodes
+//! the hidden data into digits, and the decompressor encodes the digits back
+//! into the hidden data. Both go forward, so the hidden data is recovered
+//! byte by byte as the digits are read.
+//!
+//! The hidden data is framed into chunks preceded by their length; a chunk
+//! shorter than `CHUNK_MAX_LEN` bytes is the last one, so that the end of the
+//! data is known without storing its length up front. Past its end, the data
+//! is followed by zero bytes.
+//!
+//! Streams without a steganography header, written by the reference mode or
+//! by versions before the header carried a version, use the original coding
+//! instead: the digits are the hidden data read as a mixed radix number, most
+//! significant digit first, without any framing. The decompressor can only
+//! fold them back into bytes once the last digit is known.
 
+use std::collections::{HashMap, VecDeque};
+
+use crate::hidden_source::HiddenSource;
+use crate::shaped_coding::{ShapedModel, TOTAL};
+
+const CHUNK_MAX_LEN: usize = 255;
+/// The range is kept between `RANGE_LOW` and `RANGE_HIGH`
+const RANGE_LOW: u64 = 1 << 24;
+const RANGE_HIGH: u64 = 1 << 32;
+/// Bytes of the code read before the first digit is decoded
+const STATE_BYTES: usize = 4;
+
+/// Cumulative frequencies of the values of a digit.
+#[derive(Clone, Copy)]
+enum Frequencies<'m> {
+    Uniform(u16),
+    Shaped(&'m ShapedModel),
+}
+
+impl<'m> Frequencies<'m> {
+    fn get_total(&self) -> u64 {
+        match self {
+            Frequencies::Uniform(max_value) => *max_value as u64,
+            Frequencies::Shaped(_) => TOTAL as u64,
+        }
+    }
+
+    fn get_start(&self, position: usize) -> u64 {
+        match self {
+            Frequencies::Uniform(_) => position as u64,
+            Frequencies::Shaped(model) => model.start(position) as u64,
+        }
+    }
+
+    /// Bounds of the part of `range` at `position` in slot order.
+    fn get_position_bounds(&self, position: usize, range: u64) -> (u64, u64) {
+        let total = self.get_total();
+        (
+            range * self.get_start(position) / total,
+            range * self.get_start(position + 1) / total,
+        )
+    }
+
+    /// Bounds of the part of `range` belonging to `value`.
+    fn get_bounds(&self, value: usize, range: u64) -> (u64, u64) {
+        let position = match self {
+            Frequencies::Uniform(_) => value,
+            Frequencies::Shaped(model) => model.get_position(value),
+        };
+
+        self.get_position_bounds(position, range)
+    }
+
+    /// Value whose part of `range` holds `code`.
+    fn find(&self, code: u64, range: u64) -> usize {
+        let slot = code * self.get_total() / range;
+        let mut position = match self {
+            Frequencies::Uniform(_) => slot as usize,
+            Frequencies::Shaped(model) => model.find(slot as u32),
+        };
+
+        // The bounds are rounded down, so the slot may be off by one
+        while self.get_position_bounds(position, range).0 > code {
+            position -= 1;
+        }
+        while self.get_position_bounds(position, range).1 <= code {
+            position += 1;
+        }
+
+        match self {
+            Frequencies::Uniform(_) => position,
+            Frequencies::Shaped(model) => model.get_symbol(position),
+        }
+    }
+}
+
+/// Position within the chunk framing of the hidden data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Framing {
+    /// The length of the next chunk comes next
+    ChunkStart,
+    /// Number of bytes left in the current chunk, and whether it is the last
+    Chunk(usize, bool),
+    End,
+}
+
+impl Framing {
+    fn new_chunk(chunk_len: usize) -> Self {
+        match chunk_len {
+            0 => Framing::End,
+            _ => Framing::Chunk(chunk_len, chunk_len < CHUNK_MAX_LEN),
+        }
+    }
+
+    fn after_byte(self) -> Self {
+        match self {
+            Framing::Chunk(1, true) => Framing::End,
+            Framing::Chunk(1, false) => Framing::ChunkStart,
+            Framing::Chunk(left, last) => Framing::Chunk(left - 1, last),
+            _ => self,
+        }
+    }
+
+    fn after(self, byte: u8) -> Self {
+        match self {
+            Framing::ChunkStart => Framing::new_chunk(byte as usize),
+            _ => self.after_byte(),
+        }
+    }
+}
+
+/// Adds 1 to a big endian number; `None` if it overflows.
+fn increment(mut number: Vec<u8>) -> Option<Vec<u8>> {
+    for byte in number.iter_mut().rev() {
+        let (sum, overflow) = byte.overflowing_add(1);
+        *byte = sum;
+        if !overflow {
+            return Some(number);
+        }
+    }
+
+    None
+}
+
+#[derive(Clone)]
 pub struct Encoder {
-    values: Vec<(u16, u16)>,
-    encoded: Vec<u8>,
-    x: u32,
+    low: u64,
+    range: u64,
+    /// Last byte shifted out of `low`, which a carry can still change, and the
+    /// number of 0xFF bytes following it
+    cache: Option<u8>,
+    pending_count: usize,
+    framing: Framing,
+    /// Hidden data recovered so far and not read yet
+    output: VecDeque<u8>,
+    ended: bool,
+    available_bits: f64,
+    shaped: bool,
+    /// Models of the uniform digits in the shaped mode
+    uniform_models: HashMap<u16, ShapedModel>,
+    /// Digits and their bases in the original coding, folded by `end`
+    legacy_values: Option<Vec<(u16, u16)>>,
 }
 
 impl Encoder {
     pub fn new() -> Self {
         Self {
-            values: Vec::new(),
-            encoded: Vec::new(),
-            x: 0,
+            low: 0,
+            range: RANGE_HIGH,
+            cache: None,
+            pending_count: 0,
+            framing: Framing::ChunkStart,
+            output: VecDeque::new(),
+            ended: false,
+            available_bits: 0.0,
+            shaped: false,
+            uniform_models: HashMap::new(),
+            legacy_values: None,
+        }
+    }
+
+    /// Encoder for the digits produced by a `Decoder` in the shaped mode.
+    pub fn new_shaped() -> Self {
+        Self {
+            shaped: true,
+            ..Self::new()
+        }
+    }
+
+    /// Encoder for the digits produced by a `Decoder` in the original coding.
+    /// Nothing is recovered before `end`.
+    pub fn new_legacy() -> Self {
+        Self {
+            legacy_values: Some(Vec::new()),
+            ..Self::new()
         }
     }
 
     pub fn add_value(&mut self, value: u16, max_value: u16) {
         assert!(value < max_value);
-        self.values.push((value, max_value));
+        if let Some(legacy_values) = &mut self.legacy_values {
+            legacy_values.push((value, max_value));
+            self.available_bits += (max_value as f64).log2();
+        } else if self.shaped {
+            let mut uniform_models = std::mem::take(&mut self.uniform_models);
+            let model = uniform_models
+                .entry(max_value)
+                .or_insert_with(|| ShapedModel::new_uniform(max_value));
+            self.add_weighted_value(value, model);
+            self.uniform_models = uniform_models;
+        } else {
+            self.encode(value as usize, Frequencies::Uniform(max_value));
+            self.available_bits += (max_value as f64).log2();
+        }
     }
 
-    pub fn finish(mut self) -> Vec<u8> {
-        for (val, max_val) in self.values.iter().rev() {
-            self.x = self.x * (*max_val as u32) + (*val as u32);
+    /// Adds a digit that was decoded with `Decoder::decode_weighted` using the
+    /// same model.
+    pub fn add_weighted_value(&mut self, value: u16, model: &ShapedModel) {
+        assert!((value as usize) < model.len());
+        if !self.shaped {
+            self.add_value(value, model.len() as u16);
+            return;
+        }
+        if model.len() <= 1 {
+            return;
+        }
+
+        self.encode(value as usize, Frequencies::Shaped(model));
+        self.available_bits += (TOTAL as f64 / model.frequency(value as usize) as f64).log2();
+    }
 
-            while self.x >= (1 << 16) {
-                self.encoded.push((self.x & 0xFF) as u8);
-                self.x >>= 8;
+    /// Bits the digits added so far carry, like `Decoder::get_available_bits`.
+    pub fn get_available_bits(&self) -> f64 {
+        self.available_bits
+    }
+
+    fn encode(&mut self, value: usize, frequencies: Frequencies) {
+        debug_assert!(!self.ended, "Digit added after the end");
+        let (low, high) = frequencies.get_bounds(value, self.range);
+        self.low += low;
+        self.range = high - low;
+
+        if self.low >= RANGE_HIGH {
+            // Once the carry is added, no later digit can change the bytes
+            // before the state
+            self.low -= RANGE_HIGH;
+            let cache = self.cache.take().expect("Carry past the first byte");
+            self.push_byte(cache.wrapping_add(1));
+            for _ in 0..std::mem::take(&mut self.pending_count) {
+                self.push_byte(0);
             }
         }
 
-        while self.x > 0 {
-            self.encoded.push((self.x & 0xFF) as u8);
-            self.x >>= 8;
+        while self.range < RANGE_LOW {
+            self.shift_low();
         }
+    }
+
+    fn shift_low(&mut self) {
+        let byte = (self.low >> 24) as u8;
+        self.low = (self.low << 8) & (RANGE_HIGH - 1);
+        self.range <<= 8;
 
-        self.encoded.reverse();
+        match self.cache {
+            Some(_) if byte == 0xFF => self.pending_count += 1,
+            Some(cache) => {
+                // A carry would stop at this byte
+                self.push_byte(cache);
+                for _ in 0..std::mem::take(&mut self.pending_count) {
+                    self.push_byte(0xFF);
+                }
+                self.cache = Some(byte);
+            }
+            None => self.cache = Some(byte),
+        }
+    }
 
-        self.encoded
+    /// Passes a byte of the framed hidden data, which no later digit can
+    /// change, through the framing.
+    fn push_byte(&mut self, byte: u8) {
+        if let Framing::Chunk(..) = self.framing {
+            self.output.push_back(byte);
+        }
+        self.framing = self.framing.after(byte);
+    }
+
+    /// Whether the whole hidden data was recovered.
+    pub fn is_complete(&self) -> bool {
+        self.framing == Framing::End
+    }
+
+    /// Moves the hidden data recovered so far to `buf`, returning the number
+    /// of bytes moved.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let count = buf.len().min(self.output.len());
+        for (target, byte) in buf.iter_mut().zip(self.output.drain(..count)) {
+            *target = byte;
+        }
+
+        count
+    }
+
+    /// Bytes not pushed yet of the lower and upper bound of the range.
+    fn get_bound_bytes(&self) -> (Vec<u8>, Vec<u8>) {
+        let mut low: Vec<u8> = self.cache.into_iter().collect();
+        low.extend(std::iter::repeat_n(0xFF, self.pending_count));
+        low.extend_from_slice(&(self.low as u32).to_be_bytes());
+
+        let mut high = low.clone();
+        let mut carry = self.range - 1;
+        for byte in high.iter_mut().rev() {
+            carry += *byte as u64;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+
+        (low, high)
+    }
+
+    /// Recovers the bytes that the remaining state determines; no more digits
+    /// can be added afterwards. As the hidden data is followed by zero bytes,
+    /// these include the ones up to its end if only one value of the range
+    /// both ends with zeros there and completes the framing. If there were
+    /// not enough digits for the whole hidden data, only its beginning is
+    /// recovered.
+    pub fn end(&mut self) {
+        if std::mem::replace(&mut self.ended, true) {
+            return;
+        }
+        if let Some(legacy_values) = self.legacy_values.take() {
+            self.end_legacy(&legacy_values);
+            return;
+        }
+
+        let (low, high) = self.get_bound_bytes();
+        for len in 0..=low.len() {
+            // Smallest value of the range ending with zeros after `len` bytes
+            let prefix = if low[len..].iter().all(|byte| *byte == 0) {
+                Some(low[..len].to_vec())
+            } else {
+                increment(low[..len].to_vec())
+            };
+            let prefix = match prefix {
+                Some(prefix) if prefix[..] <= high[..len] => prefix,
+                _ => continue,
+            };
+            if increment(prefix.clone()).is_some_and(|next| next[..] <= high[..len]) {
+                break;
+            }
+
+            let framing = prefix.iter().fold(self.framing, |f, byte| f.after(*byte));
+            if matches!(framing, Framing::ChunkStart | Framing::End) {
+                for byte in prefix {
+                    self.push_byte(byte);
+                }
+                return;
+            }
+        }
+
+        for (byte, high_byte) in low.into_iter().zip(high) {
+            if byte != high_byte {
+                break;
+            }
+            self.push_byte(byte);
+        }
+    }
+
+    /// Folds the digits of the original coding into the hidden data, the last
+    /// digit being the least significant one.
+    fn end_legacy(&mut self, legacy_values: &[(u16, u16)]) {
+        let mut encoded = Vec::new();
+        let mut x: u32 = 0;
+        for (value, max_value) in legacy_values.iter().rev() {
+            x = x * (*max_value as u32) + (*value as u32);
+
+            while x >= (1 << 16) {
+                encoded.push((x & 0xFF) as u8);
+                x >>= 8;
+            }
+        }
+
+        while x > 0 {
+            encoded.push((x & 0xFF) as u8);
+            x >>= 8;
+        }
+
+        self.output.extend(encoded.iter().rev());
+        self.framing = Framing::End;
+    }
+
+    /// Ends the coding, returning the hidden data not read yet.
+    pub fn finish(mut self) -> Vec<u8> {
+        self.end();
+        self.output.into()
     }
 }
 
+#[derive(Clone)]
 pub struct Decoder<'a> {
-    data: &'a [u8],
-    x: u32,
+    source: HiddenSource<'a>,
+    /// Position of the next byte of the source
+    position: usize,
+    framing: Framing,
+    code: u64,
+    /// 0 until the first digit is decoded
+    range: u64,
     available_bits: f64,
+    uniform_bits: f64,
+    shaped: bool,
+    /// Models of the uniform digits in the shaped mode
+    uniform_models: HashMap<u16, ShapedModel>,
+    /// Encoder of the digits decoded so far, telling how much of the hidden
+    /// data the decompressor recovers if it does not fit
+    mirror: Encoder,
+    /// Bytes recovered by `mirror`
+    recovered_bytes: usize,
+    legacy: bool,
 }
 
 impl<'a> Decoder<'a> {
     pub fn new(data: &'a [u8]) -> Self {
+        Self::from_source(HiddenSource::new(data))
+    }
+
+    pub fn from_source(source: HiddenSource<'a>) -> Self {
         Self {
-            data,
-            x: 0,
+            source,
+            position: 0,
+            framing: Framing::ChunkStart,
+            code: 0,
+            range: 0,
             available_bits: 0.0,
+            uniform_bits: 0.0,
+            shaped: false,
+            uniform_models: HashMap::new(),
+            mirror: Encoder::new(),
+            recovered_bytes: 0,
+            legacy: false,
         }
     }
 
+    /// Switches to the shaped mode, able to produce digits of a non-uniform
+    /// distribution with `decode_weighted`. Only before any digit is decoded.
+    pub fn into_shaped(self) -> Self {
+        debug_assert!(self.available_bits == 0.0);
+        Self {
+            shaped: true,
+            ..self
+        }
+    }
+
+    /// Switches to the original coding, for streams without a steganography
+    /// header. Only uniform digits, before any digit is decoded.
+    pub fn into_legacy(self) -> Self {
+        debug_assert!(self.available_bits == 0.0 && !self.shaped);
+        Self {
+            legacy: true,
+            ..self
+        }
+    }
+
+    /// Next byte of the framed hidden data.
+    fn read_byte(&mut self) -> u8 {
+        let byte = match self.framing {
+            Framing::ChunkStart => {
+                let chunk_len = (0..CHUNK_MAX_LEN)
+                    .find(|i| self.source.get_byte(self.position + i).is_none())
+                    .unwrap_or(CHUNK_MAX_LEN);
+                self.framing = Framing::new_chunk(chunk_len);
+                return chunk_len as u8;
+            }
+            Framing::Chunk(..) => self.source.get_byte(self.position).unwrap_or(0),
+            Framing::End => return 0,
+        };
+        self.position += 1;
+        self.framing = self.framing.after_byte();
+
+        byte
+    }
+
+    fn decode(&mut self, frequencies: Frequencies) -> usize {
+        if self.range == 0 {
+            for _ in 0..STATE_BYTES {
+                self.code = (self.code << 8) | self.read_byte() as u64;
+            }
+            self.range = RANGE_HIGH;
+        }
+
+        let value = frequencies.find(self.code, self.range);
+        let (low, high) = frequencies.get_bounds(value, self.range);
+        self.code -= low;
+        self.range = high - low;
+
+        self.mirror.encode(value, frequencies);
+        self.recovered_bytes += self.mirror.output.len();
+        self.mirror.output.clear();
+
+        while self.range < RANGE_LOW {
+            self.code = (self.code << 8) | self.read_byte() as u64;
+            self.range <<= 8;
+        }
+
+        value
+    }
+
     pub fn decode_value(&mut self, max_value: u16) -> u16 {
+        if self.shaped {
+            let mut uniform_models = std::mem::take(&mut self.uniform_models);
+            let model = uniform_models
+                .entry(max_value)
+                .or_insert_with(|| ShapedModel::new_uniform(max_value));
+            let value = self.decode_weighted(model);
+            self.uniform_models = uniform_models;
+            return value;
+        }
+
+        self.uniform_bits += (max_value as f64).log2();
         self.available_bits += (max_value as f64).log2();
-        let max_val = max_value as u32;
+        if self.legacy {
+            return self.decode_legacy(max_value);
+        }
 
-        while self.x < (max_val << 8) && !self.data.is_empty() {
-            self.x <<= 8;
-            self.x += self.data.read_u8().unwrap() as u32;
+        self.decode(Frequencies::Uniform(max_value)) as u16
+    }
+
+    /// Takes the next digit of the hidden data read as a mixed radix number,
+    /// keeping at least a byte more than the base in the state while the data
+    /// lasts.
+    fn decode_legacy(&mut self, max_value: u16) -> u16 {
+        let max_value = max_value as u64;
+        while self.code < (max_value << 8) {
+            match self.source.get_byte(self.position) {
+                Some(byte) => {
+                    self.code = (self.code << 8) + byte as u64;
+                    self.position += 1;
+                }
+                None => break,
+            }
         }
 
-        let result = (self.x % max_val) as u16;
-        self.x = self.x / max_val;
+        let value = (self.code % max_value) as u16;
+        self.code /= max_value;
 
-        result
+        value
     }
 
+    /// Decodes a digit whose value `i` is chosen with the probability of
+    /// symbol `i` of `model`. Without the shaped mode, the digit is uniform.
+    pub fn decode_weighted(&mut self, model: &ShapedModel) -> u16 {
+        if !self.shaped {
+            return self.decode_value(model.len() as u16);
+        }
+        if model.len() <= 1 {
+            return 0;
+        }
+
+        let symbol = self.decode(Frequencies::Shaped(model));
+
+        self.uniform_bits += (model.len() as f64).log2();
+        self.available_bits += (TOTAL as f64 / model.frequency(symbol) as f64).log2();
+
+        symbol as u16
+    }
+
+    pub fn get_available_bits(&self) -> f64 {
+        self.available_bits
+    }
+
+    /// Number of bytes of hidden data the digits decoded so far can carry,
+    /// without the framing and the bytes held by the state. If the hidden
+    /// data does not fit, the number of its bytes the decompressor recovers,
+    /// which may be lower.
     pub fn get_available_bytes(&self) -> usize {
-        (self.available_bits / 8.0) as usize
+        if self.legacy {
+            return (self.available_bits / 8.0) as usize;
+        }
+
+        let mut mirror = self.mirror.clone();
+        mirror.end();
+        if !mirror.is_complete() {
+            return self.recovered_bytes + mirror.output.len();
+        }
+
+        let framed_bytes = (self.available_bits / 8.0) as usize;
+        // Every full chunk takes one more byte, and the last one is followed
+        // by a shorter one, possibly empty
+        let bytes = framed_bytes.saturating_sub(1);
+        let full_chunks = bytes / (CHUNK_MAX_LEN + 1);
+        full_chunks * CHUNK_MAX_LEN + (bytes % (CHUNK_MAX_LEN + 1)).min(CHUNK_MAX_LEN - 1)
+    }
+
+    /// Number of bytes of the data read so far, including the ones held by
+    /// the state.
+    pub fn get_consumed_bytes(&self) -> usize {
+        self.position
+    }
+
+    /// Number of bytes of the data the digits decoded so far carry.
+    pub fn get_used_bytes(&self) -> usize {
+        let available_bytes = self.get_available_bytes();
+        if self.legacy {
+            return available_bytes.min(self.position);
+        }
+
+        match self.framing {
+            Framing::Chunk(left, true) => available_bytes.min(self.position + left),
+            Framing::End => available_bytes.min(self.position),
+            _ => available_bytes,
+        }
+    }
+
+    /// Capacity lost by shaping the digits, compared to uniform digits.
+    pub fn get_shaping_cost_bits(&self) -> f64 {
+        (self.uniform_bits - self.available_bits).max(0.0)
     }
 }
@@ -1,75 +1,149 @@
-use crate::constants::{END_LITERAL_NUM, MAP_PREF_SIZE, MAX_OFFSET};
+use crate::candidate_policy::CandidatePolicy;
+use crate::constants::{END_LITERAL_NUM, MAP_PREF_SIZE, MATCH_LENGTH_OFFSET, MAX_OFFSET, MF_LIMIT};
 use std::cmp::min;
-use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::{HashMap, VecDeque};
 
 pub struct OccurrenceMap<'a> {
     data: &'a [u8],
     occur: HashMap<&'a [u8], VecDeque<usize>>,
+    indexed_until: usize,
 
-    prefer_hidden: bool,
+    candidate_policy: CandidatePolicy,
 }
 
 impl<'a> OccurrenceMap<'a> {
-    pub fn new(data: &'a [u8], prefer_hidden: bool) -> Self {
+    pub fn new(data: &'a [u8], candidate_policy: CandidatePolicy) -> Self {
         Self {
             data,
             occur: HashMap::new(),
-            prefer_hidden,
+            indexed_until: 0,
+            candidate_policy,
         }
     }
 
-    pub fn add_occurrences(&mut self, index: usize, to_advance: usize) {
-        let (start_index, actual_to_advance) = if to_advance > MAX_OFFSET {
-            (index + to_advance - MAX_OFFSET, MAX_OFFSET)
-        } else {
-            (index, to_advance)
-        };
+    pub fn set_candidate_policy(&mut self, candidate_policy: CandidatePolicy) {
+        self.candidate_policy = candidate_policy;
+    }
+
+    pub fn data_len(&self) -> usize {
+        self.data.len()
+    }
+
+    /// Makes all positions before `end` available as match candidates.
+    pub fn index_until(&mut self, end: usize) {
+        let start_index = self.indexed_until.max(end.saturating_sub(MAX_OFFSET));
 
-        for i in start_index..start_index + actual_to_advance {
+        for i in start_index..end {
             let pref = &self.data[i..i + MAP_PREF_SIZE];
             self.occur.entry(pref).or_default().push_back(i);
         }
+
+        self.indexed_until = self.indexed_until.max(end);
     }
 
-    pub fn get_occurrences(&mut self, index: usize) -> Occurrences {
+    pub fn get_occurrences(&mut self, index: usize) -> Occurrences<'a> {
+        if index + MF_LIMIT > self.data.len() {
+            // The last match has to start at least `MF_LIMIT` bytes before the
+            // end of the block
+            return Occurrences::empty();
+        }
+
         let pref = &self.data[index..index + MAP_PREF_SIZE];
-        let entry = self.occur.entry(pref);
+        if let Some(occur_set) = self.occur.get_mut(pref) {
+            occur_set.retain(|x| x + MAX_OFFSET >= index);
+            debug_assert!(occur_set.len() <= u16::MAX as usize);
+        }
 
-        match entry {
-            Occupied(mut entry_val) => {
-                let occur_set = entry_val.get_mut();
-                occur_set.retain(|x| index - x <= MAX_OFFSET);
-                debug_assert!(occur_set.len() <= u16::MAX as usize);
+        self.peek_occurrences(index)
+    }
 
-                if occur_set.is_empty() {
-                    return Occurrences::empty();
-                }
-            }
-            Vacant(_) => {
-                return Occurrences::empty();
-            }
+    /// Like `get_occurrences`, but keeps the candidates that are too far from
+    /// `index`, as they may still be used by the positions before it. Used for
+    /// looking ahead.
+    pub fn peek_occurrences(&self, index: usize) -> Occurrences<'a> {
+        if index + MF_LIMIT > self.data.len() {
+            return Occurrences::empty();
+        }
+
+        let pref = &self.data[index..index + MAP_PREF_SIZE];
+        let candidates: Vec<usize> = match self.occur.get(pref) {
+            // Positions indexed ahead of time for lookahead are not usable
+            Some(occur_set) => occur_set
+                .iter()
+                .skip_while(|x| **x + MAX_OFFSET < index)
+                .take_while(|x| **x < index)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        };
+
+        if candidates.is_empty() {
+            return Occurrences::empty();
+        }
+
+        if self.candidate_policy == CandidatePolicy::Any {
+            return Occurrences::new(candidates, self.data, index);
         }
 
-        let occur_set = &self.occur[pref];
-        let match_lengths: Vec<usize> = occur_set
+        let match_lengths: Vec<usize> = candidates
             .iter()
             .map(|x| calc_match_length(self.data, index, *x))
             .collect();
-        if self.prefer_hidden {
-            Occurrences::new(occur_set.iter().cloned().collect(), self.data, index)
-        } else {
-            let max_match_length = *match_lengths.iter().max().unwrap();
-            let match_lengths_max: Vec<usize> = occur_set
-                .iter()
-                .zip(match_lengths)
-                .filter(|(_val, match_length)| *match_length == max_match_length)
-                .map(|(val, _match_length)| *val)
-                .collect();
+        let max_match_length = *match_lengths.iter().max().unwrap();
+        if max_match_length < MATCH_LENGTH_OFFSET as usize {
+            // No match fits before the end of the block, so the position is
+            // emitted as a literal. It must not offer a choice, which the
+            // decompressor would never see.
+            return Occurrences::empty();
+        }
+        let accepted: Vec<usize> = candidates
+            .into_iter()
+            .zip(match_lengths)
+            .filter(|(_val, match_length)| {
+                self.candidate_policy
+                    .accepts(*match_length, max_match_length)
+            })
+            .map(|(val, _match_length)| val)
+            .collect();
 
-            Occurrences::new_with_match_length(match_lengths_max, max_match_length)
+        if self.candidate_policy == CandidatePolicy::Longest {
+            Occurrences::new_with_match_length(accepted, max_match_length)
+        } else if accepted.is_empty() {
+            Occurrences::empty()
+        } else {
+            Occurrences::new(accepted, self.data, index)
         }
     }
+
+    /// Previous occurrences the reference parser would encode the same way as
+    /// the match of `match_length` bytes at `index`: ones that cannot be
+    /// extended forwards, nor backwards past `anchor`, where its literals
+    /// start.
+    pub fn get_equivalent_occurrences(
+        &mut self,
+        index: usize,
+        match_length: usize,
+        anchor: usize,
+    ) -> Occurrences<'a> {
+        let data = self.data;
+        let pref = &data[index..index + MAP_PREF_SIZE];
+
+        let candidates: Vec<usize> = match self.occur.get_mut(pref) {
+            Some(occur_set) => {
+                occur_set.retain(|x| x + MAX_OFFSET >= index);
+                occur_set
+                    .iter()
+                    .take_while(|x| **x < index)
+                    .filter(|x| calc_match_length(data, index, **x) == match_length)
+                    .filter(|x| index == anchor || **x == 0 || data[**x - 1] != data[index - 1])
+                    .cloned()
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+
+        Occurrences::new_with_match_length(candidates, match_length)
+    }
 }
 
 fn calc_match_length(data: &[u8], index: usize, occur_index: usize) -> usize {
@@ -82,6 +156,17 @@ fn calc_match_length(data: &[u8], index: usize, occur_index: usize) -> usize {
     )
 }
 
+/// Number of lengths a match can be truncated to in the match truncation
+/// channel, including its full length.
+pub fn get_truncation_count(match_length: usize, max_truncation: usize) -> u16 {
+    let max_truncation = max_truncation.min(u16::MAX as usize - 1);
+
+    (match_length
+        .saturating_sub(MATCH_LENGTH_OFFSET as usize)
+        .min(max_truncation)
+        + 1) as u16
+}
+
 fn get_common_prefix_len(a: &[u8], b: &[u8]) -> usize {
     a.iter().zip(b).take_while(|(x, y)| x == y).count()
 }
@@ -125,6 +210,44 @@ impl<'a> Occurrences<'a> {
         self.occur.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.occur.is_empty()
+    }
+
+    /// Length of the candidate matches; only known when all candidates share
+    /// the same length, i.e. with the longest match policy.
+    pub fn match_length(&self) -> Option<usize> {
+        self.match_length
+    }
+
+    pub fn max_match_length(&self) -> usize {
+        match (self.match_length, self.data) {
+            (Some(match_length), _) => match_length,
+            (None, Some(data)) => self
+                .occur
+                .iter()
+                .map(|x| calc_match_length(data, self.index, *x))
+                .max()
+                .unwrap_or(0),
+            (None, None) => 0,
+        }
+    }
+
+    /// If the match is short enough to carry a literal channel digit, returns
+    /// the number of bytes emitted as literals instead of it. The same number
+    /// of bytes is skipped either way, so that the decompressor visits the same
+    /// positions.
+    pub fn literal_channel_length(&self, literal_channel: Option<usize>) -> Option<usize> {
+        let max_length = literal_channel?;
+        let max_match_length = self.max_match_length();
+
+        if max_match_length >= MATCH_LENGTH_OFFSET as usize && max_match_length <= max_length {
+            Some(max_match_length)
+        } else {
+            None
+        }
+    }
+
     pub fn choose_occurrence(&self, index: usize) -> (usize, usize) {
         let occur_index = self.occur[index];
 
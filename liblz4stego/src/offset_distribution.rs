@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use crate::shaped_coding::ShapedModel;
+
+/// Maximum number of recency ranks with their own weight
+pub const MAX_RANKS: usize = 32;
+
+/// Relative frequencies of the recency ranks of the chosen match among equally
+/// long candidates in the output of the reference `lz4` tool, rank 0 being
+/// the nearest candidate. Measured by `scripts/measure_offset_weights.sh` on
+/// `lz4 -1` (v1.9.4) output of the sources of the libc 0.2.190 crate, and
+/// scaled to 10000 choices.
+const REFERENCE_WEIGHTS: [u32; 24] = [
+    6049, 2191, 712, 370, 194, 124, 81, 63, 42, 32, 24, 20, 17, 16, 13, 10, 7, 6, 6, 7, 5, 5, 4, 3,
+];
+
+/// Target distribution of the recency rank of the chosen match candidate.
+/// Shaping the candidate choices to it makes the offsets look like those of a
+/// regular LZ4 compressor, which mostly picks the nearest candidate, at the
+/// cost of hidden data capacity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OffsetDistribution {
+    weights: [u32; MAX_RANKS],
+    len: usize,
+}
+
+impl OffsetDistribution {
+    /// The distribution measured on the output of the reference `lz4` tool.
+    pub fn reference() -> Self {
+        Self::from_weights(&REFERENCE_WEIGHTS)
+    }
+
+    /// Creates a distribution from the weights of the consecutive recency
+    /// ranks; ranks past the end of the list use the last weight. The weights
+    /// are checked by `is_valid` when the distribution is used.
+    pub fn from_weights(weights: &[u32]) -> Self {
+        let copied_len = weights.len().min(MAX_RANKS);
+        let mut distribution = Self {
+            weights: [0; MAX_RANKS],
+            len: weights.len(),
+        };
+        distribution.weights[..copied_len].copy_from_slice(&weights[..copied_len]);
+
+        distribution
+    }
+
+    /// Whether there are between 1 and `MAX_RANKS` weights, all positive.
+    pub fn is_valid(&self) -> bool {
+        (1..=MAX_RANKS).contains(&self.len) && self.weights().iter().all(|weight| *weight > 0)
+    }
+
+    pub fn weights(&self) -> &[u32] {
+        &self.weights[..self.len.min(MAX_RANKS)]
+    }
+
+    fn get_rank_weight(&self, rank: usize) -> u32 {
+        self.weights[rank.min(self.len - 1)]
+    }
+
+    /// Weights of the candidates in the order of `Occurrences`, i.e. with the
+    /// nearest candidate last.
+    pub(crate) fn get_candidate_weights(&self, count: usize) -> Vec<u32> {
+        (0..count)
+            .rev()
+            .map(|rank| self.get_rank_weight(rank))
+            .collect()
+    }
+
+    /// Expected number of hidden bits carried by a choice among `count`
+    /// candidates.
+    pub fn bits_per_choice(&self, count: usize) -> f64 {
+        let weights: Vec<f64> = (0..count)
+            .map(|rank| self.get_rank_weight(rank) as f64)
+            .collect();
+        let total: f64 = weights.iter().sum();
+
+        weights
+            .iter()
+            .map(|weight| weight / total)
+            .map(|p| -p * p.log2())
+            .sum()
+    }
+
+    /// Number of hidden bits lost per choice among `count` candidates,
+    /// compared to choosing uniformly.
+    pub fn capacity_cost(&self, count: usize) -> f64 {
+        if count == 0 {
+            return 0.0;
+        }
+
+        (count as f64).log2() - self.bits_per_choice(count)
+    }
+}
+
+/// Models of the candidate choices shaped to a distribution, built once for
+/// every number of candidates met in a block rather than for every choice.
+pub(crate) struct CandidateModels {
+    distribution: OffsetDistribution,
+    models: HashMap<usize, ShapedModel>,
+}
+
+impl CandidateModels {
+    pub fn new(distribution: OffsetDistribution) -> Self {
+        Self {
+            distribution,
+            models: HashMap::new(),
+        }
+    }
+
+    pub fn get(&mut self, count: usize) -> &ShapedModel {
+        let distribution = self.distribution;
+        self.models
+            .entry(count)
+            .or_insert_with(|| ShapedModel::new(&distribution.get_candidate_weights(count)))
+    }
+}
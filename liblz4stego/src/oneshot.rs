@@ -0,0 +1,101 @@
+//! Compression and decompression of whole buffers in a single call.
+
+use std::io::{ErrorKind, Read, Write};
+
+use xxhash_rust::xxh32::Xxh32;
+
+use crate::builder::CompressorBuilder;
+use crate::compressor;
+use crate::decompressor::{decompress_block_into, Decompressor};
+use crate::errors::{DecompressResult, Lz4DecompressError};
+use crate::frame_parser::{Event, FrameParser};
+use crate::report::Report;
+
+/// How `decompress` extracts the hidden data; it has to match the settings
+/// the data was compressed with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum HiddenDataMode {
+    /// See `Decompressor::new`
+    #[default]
+    Default,
+    /// The prefer-hidden mode; see `Decompressor::new`
+    PreferHidden,
+    /// See `Decompressor::new_reference`
+    Reference,
+    /// Plain LZ4 decompression, without any hidden data
+    None,
+}
+
+/// Compresses `data` hiding `hidden_data`, with the settings of `options`.
+pub fn compress(
+    data: &[u8],
+    hidden_data: &[u8],
+    options: &CompressorBuilder,
+) -> std::io::Result<(Vec<u8>, Report)> {
+    let mut output = Vec::new();
+    let mut compressor = options.build(&mut output, hidden_data)?;
+    compressor.write_all(data)?;
+    let report = compressor.finish()?;
+
+    Ok((output, report))
+}
+
+/// Decompresses `data`, returning the decompressed data and the hidden data.
+pub fn decompress(data: &[u8], mode: HiddenDataMode) -> DecompressResult<(Vec<u8>, Vec<u8>)> {
+    let mut decompressor = Decompressor::new_with_mode(data, mode);
+    let mut output = Vec::new();
+    decompressor.read_to_end(&mut output)?;
+
+    Ok((output, decompressor.finish()))
+}
+
+/// Decompresses an LZ4 stream into `output`, returning the decompressed size.
+/// Fails if `output` is too small. The hidden data is not extracted, and
+/// nothing is allocated: the blocks are decoded directly into `output`.
+pub fn decompress_into(mut data: &[u8], output: &mut [u8]) -> DecompressResult<usize> {
+    let mut parser = FrameParser::new();
+    let mut hash = Xxh32::new(0);
+    let mut block_independent = true;
+    let mut block_max_size = 0;
+    let mut position = 0;
+
+    loop {
+        let len = parser.get_needed_len();
+        let step = data
+            .get(..len)
+            .ok_or_else(|| std::io::Error::from(ErrorKind::UnexpectedEof))?;
+        data = &data[len..];
+
+        match parser.advance(step)? {
+            Event::FrameHeader(frame_options) => {
+                block_independent = frame_options.block_independent;
+                block_max_size = frame_options
+                    .block_size_id
+                    .map_or(0, compressor::get_block_max_size);
+            }
+            Event::Block(block_size) => {
+                let block = &step[..block_size.get_block_size() as usize];
+                let start = position;
+                if block_size.is_uncompressed() {
+                    position = start + block.len();
+                    output
+                        .get_mut(start..position)
+                        .ok_or_else(Lz4DecompressError::buffer_too_small)?
+                        .copy_from_slice(block);
+                } else {
+                    let window_start = if block_independent { start } else { 0 };
+                    position =
+                        decompress_block_into(output, window_start, start, block, block_max_size)?;
+                }
+                hash.update(&output[start..position]);
+            }
+            Event::FrameEnd(checksum) => {
+                if checksum.is_some_and(|checksum| checksum != hash.digest()) {
+                    return Err(Lz4DecompressError::from_static_str("Checksum is invalid"));
+                }
+                return Ok(position);
+            }
+            Event::Partial | Event::SkippableFrame(_) | Event::BlockSize => {}
+        }
+    }
+}
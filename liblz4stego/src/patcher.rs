@@ -0,0 +1,104 @@
+//! Replaces the data hidden in a file created by the default mode of
+//! `Compressor` without compressing it again. Only the longest matches are
+//! candidates there, so the sequences do not depend on the hidden data and
+//! changing it only changes the match offsets.
+
+use std::io::{Read, Seek, Write};
+
+use crate::candidate_policy::CandidatePolicy;
+use crate::errors::{DecompressResult, Lz4DecompressError};
+use crate::frame_rewriter::{rewrite_frames_in_place, BlockRewriter, Sequence};
+use crate::numeral_coding;
+use crate::occurrence_map::OccurrenceMap;
+use crate::offset_distribution::CandidateModels;
+use crate::stego_header::StegoHeader;
+
+struct Patcher<'a> {
+    hidden_data: &'a [u8],
+    hidden_data_encoder: numeral_coding::Decoder<'a>,
+    stego_header: StegoHeader,
+}
+
+impl<'a> BlockRewriter for Patcher<'a> {
+    fn read_skippable_frame(&mut self, content: &[u8]) -> DecompressResult<()> {
+        if !self.stego_header.read(content)? {
+            return Ok(());
+        }
+
+        let header = &self.stego_header;
+        if header.candidate_policy != CandidatePolicy::Longest
+            || !header.block_policies.is_empty()
+            || header.literal_channel.is_some()
+            || header.match_truncation.is_some()
+            || header.block_channel.is_some()
+            || header.trellis_ratio.is_some()
+        {
+            return Err(Lz4DecompressError::from_static_str(
+                "Only data hidden in the offsets of the longest matches can be patched",
+            ));
+        }
+        let mut hidden_data_encoder = numeral_coding::Decoder::new(self.hidden_data);
+        if header.offset_distribution.is_some() {
+            hidden_data_encoder = hidden_data_encoder.into_shaped();
+        }
+        self.hidden_data_encoder = hidden_data_encoder;
+
+        Ok(())
+    }
+
+    fn rewrite_block(&mut self, window: &[u8], sequences: &mut [Sequence]) -> DecompressResult<()> {
+        let mut occur = OccurrenceMap::new(window, CandidatePolicy::Longest);
+        let mut candidate_models = self
+            .stego_header
+            .offset_distribution
+            .map(CandidateModels::new);
+
+        for sequence in sequences {
+            occur.index_until(sequence.index);
+
+            // Every match of the default mode is one of the longest candidates;
+            // the reference mode takes others
+            let occurrences = occur.get_occurrences(sequence.index);
+            if occurrences
+                .get_occurrence_index(sequence.match_index)
+                .is_none()
+            {
+                return Err(Lz4DecompressError::from_static_str(
+                    "Only files with the longest matches as candidates can be patched",
+                ));
+            }
+
+            let chosen_index = match &mut candidate_models {
+                Some(candidate_models) => self
+                    .hidden_data_encoder
+                    .decode_weighted(candidate_models.get(occurrences.len())),
+                None => self
+                    .hidden_data_encoder
+                    .decode_value(occurrences.len() as u16),
+            };
+            sequence.match_index = occurrences.choose_occurrence(chosen_index as usize).0;
+        }
+
+        Ok(())
+    }
+}
+
+/// Hides `hidden_data` in `file`, which must have been created by
+/// `Compressor::new_with_hidden_data` without `prefer_hidden`, optionally with
+/// an offset distribution. Only the match offsets are written; the file keeps
+/// its size and content. Returns the number of bytes that can be hidden, like
+/// the report of `Compressor::finish`.
+pub fn patch_hidden_data<F: Read + Write + Seek>(
+    file: F,
+    hidden_data: &[u8],
+) -> DecompressResult<usize> {
+    let mut patcher = Patcher {
+        hidden_data,
+        // Replaced if the file has a steganography header
+        hidden_data_encoder: numeral_coding::Decoder::new(hidden_data).into_legacy(),
+        stego_header: StegoHeader::new(CandidatePolicy::Longest),
+    };
+    rewrite_frames_in_place(file, &mut patcher)?;
+
+    Ok(patcher.hidden_data_encoder.get_available_bytes())
+}
@@ -0,0 +1,151 @@
+//! Push-based decompression, for callers that receive the compressed data in
+//! chunks and do not want to wrap it in a blocking `Read`.
+
+use std::io::Read;
+
+use crate::decompressor::Decompressor;
+use crate::errors::{DecompressResult, Lz4DecompressError};
+use crate::frame_parser::{Event, FrameParser};
+use crate::HiddenDataMode;
+
+const HIDDEN_DATA_BUFFER_SIZE: usize = 4096;
+
+/// What a call to `PushDecoder::feed` produced.
+#[derive(Debug, Default)]
+pub struct Decoded {
+    /// Decompressed data of the blocks completed by the call
+    pub output: Vec<u8>,
+    /// Hidden data that became available during the call
+    pub hidden_data: Vec<u8>,
+    /// Set once the end of the frame was reached and all the hidden data was
+    /// returned
+    pub finished: bool,
+}
+
+/// Decompressor that is fed the compressed data as it arrives. Incomplete
+/// headers and blocks are kept until the rest of them is fed.
+pub struct PushDecoder {
+    decompressor: Decompressor<PushInput>,
+    /// Finds where the blocks end in the buffered data, ahead of the
+    /// decompressor
+    scanner: FrameParser,
+    /// Bytes of the buffer the scanner went through
+    scanned_len: usize,
+    /// Whether the scanner went past the frame header
+    header_scanned: bool,
+    /// Whether the scanner went past the end of a block the decompressor did
+    /// not read yet
+    block_scanned: bool,
+    finished: bool,
+    error: Option<Lz4DecompressError>,
+}
+
+impl PushDecoder {
+    pub fn new(mode: HiddenDataMode) -> Self {
+        Self {
+            decompressor: Decompressor::new_with_mode(PushInput::default(), mode),
+            scanner: FrameParser::new(),
+            scanned_len: 0,
+            header_scanned: false,
+            block_scanned: false,
+            finished: false,
+            error: None,
+        }
+    }
+
+    /// Buffers `data` and decompresses every block that is complete. Once the
+    /// data turns out to be invalid, the same error is returned from then on.
+    pub fn feed(&mut self, data: &[u8]) -> DecompressResult<Decoded> {
+        if let Some(e) = &self.error {
+            return Err(e.clone());
+        }
+        let mut decoded = Decoded::default();
+        if self.finished {
+            decoded.finished = true;
+            return Ok(decoded);
+        }
+
+        let input = self.decompressor.get_input_mut();
+        input.buffer.drain(..input.start);
+        self.scanned_len -= input.start;
+        input.start = 0;
+        input.buffer.extend_from_slice(data);
+
+        if let Err(e) = self.decompress_scanned(&mut decoded.output) {
+            self.error = Some(e.clone());
+            return Err(e);
+        }
+
+        let mut buf = [0; HIDDEN_DATA_BUFFER_SIZE];
+        loop {
+            let count = self.decompressor.read_hidden_data(&mut buf);
+            if count == 0 {
+                break;
+            }
+            decoded.hidden_data.extend_from_slice(&buf[..count]);
+        }
+        decoded.finished = self.finished;
+
+        Ok(decoded)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.finished
+    }
+
+    /// Scans the buffered data, decompressing every block whose end was
+    /// reached. The decompressor may read the size of the next block ahead,
+    /// so a block is only decompressed once the step after it was scanned.
+    fn decompress_scanned(&mut self, output: &mut Vec<u8>) -> DecompressResult<()> {
+        loop {
+            let len = self.scanner.get_needed_len();
+            let buffer = &self.decompressor.get_input_mut().buffer;
+            if buffer.len() < self.scanned_len + len {
+                return Ok(());
+            }
+            let event = self
+                .scanner
+                .advance(&buffer[self.scanned_len..self.scanned_len + len])?;
+            self.scanned_len += len;
+
+            if !self.header_scanned {
+                // Up to the frame header, the decompressor reads every step
+                // as soon as it is scanned, so that the content of skippable
+                // frames is not kept in the buffer
+                self.decompressor.read_step()?;
+                self.header_scanned = matches!(event, Event::FrameHeader(_));
+                continue;
+            }
+            if self.block_scanned {
+                self.decompressor.decompress_next_block(output)?;
+                self.block_scanned = false;
+            }
+            match event {
+                Event::Block(_) => self.block_scanned = true,
+                Event::FrameEnd(_) => {
+                    self.decompressor.decompress_next_block(output)?;
+                    self.decompressor.end_hidden_data();
+                    self.finished = true;
+                    return Ok(());
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// The data fed so far, read by the decompressor from `start`.
+#[derive(Default)]
+struct PushInput {
+    buffer: Vec<u8>,
+    start: usize,
+}
+
+impl Read for PushInput {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = (&self.buffer[self.start..]).read(buf)?;
+        self.start += count;
+
+        Ok(count)
+    }
+}
@@ -0,0 +1,117 @@
+//! Statistics of a compressed or decompressed stream, returned by
+//! `Compressor::finish` and `Decompressor::finish_with_report`.
+
+use byteorder::{ReadBytesExt, LE};
+
+use crate::constants::TOKEN_MAX_VAL;
+use crate::decompressor::get_lsic_int;
+use crate::descriptors::Token;
+
+/// Statistics of a single block.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockReport {
+    pub compressed: bool,
+    pub uncompressed_size: usize,
+    /// Size of the block data, without its header and checksum
+    pub compressed_size: usize,
+    pub sequence_count: usize,
+    pub match_count: usize,
+    pub literal_bytes: usize,
+    /// Matches with more than one candidate; `None` if the decompressor did
+    /// not extract the hidden data, as the candidates depend on its mode
+    pub choice_count: Option<usize>,
+    /// Bits of hidden data the block can carry
+    pub hidden_bits: f64,
+}
+
+impl BlockReport {
+    pub(crate) fn new_uncompressed(size: usize) -> Self {
+        Self {
+            compressed: false,
+            uncompressed_size: size,
+            compressed_size: size,
+            literal_bytes: size,
+            choice_count: Some(0),
+            ..Default::default()
+        }
+    }
+
+    /// Counts the sequences of the data of a valid compressed block.
+    pub(crate) fn new_compressed(uncompressed_size: usize, mut data: &[u8]) -> Self {
+        let mut report = Self {
+            compressed: true,
+            uncompressed_size,
+            compressed_size: data.len(),
+            ..Default::default()
+        };
+
+        while let Ok(token) = data.read_u8() {
+            let token = Token(token);
+            let literals_length =
+                get_lsic_int(&mut data, token.get_literals_length(), TOKEN_MAX_VAL).unwrap()
+                    as usize;
+            report.sequence_count += 1;
+            report.literal_bytes += literals_length;
+            data = &data[literals_length..];
+
+            if data.read_u16::<LE>().is_err() {
+                break;
+            }
+            get_lsic_int(&mut data, token.get_match_length(), TOKEN_MAX_VAL).unwrap();
+            report.match_count += 1;
+        }
+
+        report
+    }
+}
+
+/// Statistics of a whole stream, with the totals of its blocks.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Report {
+    pub uncompressed_size: u64,
+    /// Size of the whole stream, including the headers
+    pub compressed_size: u64,
+    /// Uncompressed size divided by the compressed one
+    pub ratio: f64,
+    pub block_count: usize,
+    pub sequence_count: usize,
+    pub match_count: usize,
+    pub literal_bytes: usize,
+    /// Matches with more than one candidate, `None` if not known for some
+    /// block
+    pub choice_count: Option<usize>,
+    pub hidden_bits: f64,
+    /// Bytes of hidden data that fit in the stream when compressing, or that
+    /// were extracted when decompressing
+    pub hidden_bytes: usize,
+    pub blocks: Vec<BlockReport>,
+}
+
+impl Report {
+    pub(crate) fn new(blocks: Vec<BlockReport>, compressed_size: u64, hidden_bytes: usize) -> Self {
+        let uncompressed_size = blocks
+            .iter()
+            .map(|block| block.uncompressed_size as u64)
+            .sum();
+        let ratio = match compressed_size {
+            0 => 0.0,
+            _ => uncompressed_size as f64 / compressed_size as f64,
+        };
+
+        Self {
+            uncompressed_size,
+            compressed_size,
+            ratio,
+            block_count: blocks.len(),
+            sequence_count: blocks.iter().map(|block| block.sequence_count).sum(),
+            match_count: blocks.iter().map(|block| block.match_count).sum(),
+            literal_bytes: blocks.iter().map(|block| block.literal_bytes).sum(),
+            choice_count: blocks.iter().map(|block| block.choice_count).sum(),
+            hidden_bits: blocks.iter().map(|block| block.hidden_bits).sum(),
+            hidden_bytes,
+            blocks,
+        }
+    }
+}
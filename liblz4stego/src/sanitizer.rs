@@ -0,0 +1,41 @@
+//! Removes data hidden in the match offsets of LZ4 files by pointing every
+//! match at the nearest earlier occurrence of its bytes, as regular
+//! compressors do.
+
+use std::io::{Read, Write};
+
+use crate::errors::DecompressResult;
+use crate::frame_rewriter::{rewrite_frames, BlockRewriter, Sequence, SourceFinder};
+
+struct Sanitizer {
+    changed_count: usize,
+}
+
+impl BlockRewriter for Sanitizer {
+    fn rewrite_block(&mut self, window: &[u8], sequences: &mut [Sequence]) -> DecompressResult<()> {
+        let mut finder = SourceFinder::new(window);
+        for sequence in sequences {
+            let nearest = finder
+                .get_sources(sequence.index, sequence.match_length)
+                .next()
+                .unwrap_or(sequence.match_index);
+            if nearest != sequence.match_index {
+                sequence.match_index = nearest;
+                self.changed_count += 1;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Copies the LZ4 and skippable frames of `input_read` to `output_write` with
+/// every match offset made canonical. Everything else stays byte for byte the
+/// same, except for the block checksums, which are updated. Returns the number
+/// of offsets changed.
+pub fn sanitize<R: Read, W: Write>(input_read: R, output_write: W) -> DecompressResult<usize> {
+    let mut sanitizer = Sanitizer { changed_count: 0 };
+    rewrite_frames(input_read, output_write, &mut sanitizer)?;
+
+    Ok(sanitizer.changed_count)
+}
@@ -0,0 +1,112 @@
+//! Quantized digit distributions of the shaped mode of `numeral_coding`.
+
+/// Total of the quantized symbol frequencies
+pub const TOTAL_BITS: u32 = 16;
+pub const TOTAL: u32 = 1 << TOTAL_BITS;
+
+/// Symbol frequencies quantized to sum up to `TOTAL`. The slots of the most
+/// frequent symbol come first, so that the zero padding after the hidden data
+/// decodes to the most natural choice; the other symbols follow in order.
+#[derive(Clone)]
+pub struct ShapedModel {
+    frequencies: Vec<u32>,
+    /// Symbols in slot order
+    symbols: Vec<usize>,
+    /// Positions of the symbols in slot order
+    positions: Vec<usize>,
+    /// Starts of the slots, in slot order
+    starts: Vec<u32>,
+}
+
+impl ShapedModel {
+    pub fn new(weights: &[u32]) -> Self {
+        let frequencies = quantize(weights);
+        let first_symbol = (0..frequencies.len())
+            .max_by_key(|i| (frequencies[*i], std::cmp::Reverse(*i)))
+            .unwrap();
+        let symbols: Vec<usize> = std::iter::once(first_symbol)
+            .chain((0..frequencies.len()).filter(|i| *i != first_symbol))
+            .collect();
+
+        let mut positions = vec![0; symbols.len()];
+        for (position, symbol) in symbols.iter().enumerate() {
+            positions[*symbol] = position;
+        }
+
+        let starts = symbols
+            .iter()
+            .scan(0, |start, symbol| {
+                let current = *start;
+                *start += frequencies[*symbol];
+                Some(current)
+            })
+            .collect();
+
+        Self {
+            frequencies,
+            symbols,
+            positions,
+            starts,
+        }
+    }
+
+    /// Number of symbols.
+    pub fn len(&self) -> usize {
+        self.frequencies.len()
+    }
+
+    /// Model of `len` equally likely symbols.
+    pub fn new_uniform(len: u16) -> Self {
+        Self::new(&vec![1; len as usize])
+    }
+
+    pub fn frequency(&self, symbol: usize) -> u32 {
+        self.frequencies[symbol]
+    }
+
+    pub fn get_symbol(&self, position: usize) -> usize {
+        self.symbols[position]
+    }
+
+    pub fn get_position(&self, symbol: usize) -> usize {
+        self.positions[symbol]
+    }
+
+    /// Sum of the frequencies before `position` in slot order, which may be
+    /// one past the last position.
+    pub fn start(&self, position: usize) -> u32 {
+        self.starts.get(position).copied().unwrap_or(TOTAL)
+    }
+
+    /// Position whose slots include `slot`.
+    pub fn find(&self, slot: u32) -> usize {
+        self.starts.partition_point(|start| *start <= slot) - 1
+    }
+}
+
+/// Scales the weights to frequencies summing up to `TOTAL`, each at least 1.
+fn quantize(weights: &[u32]) -> Vec<u32> {
+    debug_assert!(!weights.is_empty() && weights.len() <= u16::MAX as usize);
+    let total_weight: u64 = weights.iter().map(|w| *w as u64).sum();
+    let mut frequencies: Vec<u32> = weights
+        .iter()
+        .map(|w| ((*w as u64 * TOTAL as u64 / total_weight) as u32).max(1))
+        .collect();
+
+    let mut sum: u32 = frequencies.iter().sum();
+    while sum != TOTAL {
+        let largest = (0..frequencies.len())
+            .max_by_key(|i| (frequencies[*i], std::cmp::Reverse(*i)))
+            .unwrap();
+        if sum < TOTAL {
+            frequencies[largest] += TOTAL - sum;
+            sum = TOTAL;
+        } else {
+            let excess = (sum - TOTAL).min(frequencies[largest] - 1);
+            frequencies[largest] -= excess;
+            sum -= excess;
+        }
+    }
+
+    frequencies
+}
@@ -0,0 +1,227 @@
+use std::io::Write;
+
+use byteorder::{ReadBytesExt, WriteBytesExt, LE};
+
+use crate::candidate_policy::CandidatePolicy;
+use crate::constants::SKIPPABLE_MAGIC_NUMBER;
+use crate::errors::{DecompressResult, Lz4DecompressError};
+use crate::offset_distribution::OffsetDistribution;
+
+const STEGO_HEADER_TAG: &[u8; 4] = b"LZ4S";
+
+const FIELD_CANDIDATE_POLICY: u8 = 1;
+const FIELD_BLOCK_POLICIES: u8 = 2;
+const FIELD_LITERAL_CHANNEL: u8 = 3;
+const FIELD_MATCH_TRUNCATION: u8 = 4;
+const FIELD_BLOCK_CHANNEL: u8 = 5;
+const FIELD_OFFSET_DISTRIBUTION: u8 = 6;
+const FIELD_TRELLIS_CODING: u8 = 7;
+const FIELD_VERSION: u8 = 8;
+
+/// Version of the hidden data coding. Headers without the version field were
+/// written before the forward range coder, as version 1.
+const HEADER_VERSION: u8 = 2;
+
+/// Largest skippable frame content read as a possible header. The fields
+/// written by lz4stego take well under this.
+pub const MAX_STEGO_HEADER_SIZE: usize = 4096;
+
+/// Steganography parameters, stored in a skippable frame preceding the LZ4
+/// frame so that regular decompressors ignore it. Written in every mode hiding
+/// data except the reference mode, whose output has to look like that of the
+/// reference `lz4` tool; streams without it use the original coding of the
+/// hidden data.
+#[derive(Debug, Clone)]
+pub struct StegoHeader {
+    pub candidate_policy: CandidatePolicy,
+    /// Policies that blocks choose from in the guaranteed capacity mode
+    pub block_policies: Vec<CandidatePolicy>,
+    /// Maximum length of matches that may be emitted as literals instead
+    pub literal_channel: Option<usize>,
+    /// Maximum number of bytes matches may be truncated by
+    pub match_truncation: Option<usize>,
+    /// Nominal block size and the range of sizes blocks may be shortened to
+    pub block_channel: Option<(usize, usize)>,
+    /// Distribution the candidate choices are shaped to
+    pub offset_distribution: Option<OffsetDistribution>,
+    /// Cover bits per hidden bit of the syndrome-trellis coding
+    pub trellis_ratio: Option<usize>,
+}
+
+impl StegoHeader {
+    pub fn new(candidate_policy: CandidatePolicy) -> Self {
+        Self {
+            candidate_policy,
+            block_policies: Vec::new(),
+            literal_channel: None,
+            match_truncation: None,
+            block_channel: None,
+            offset_distribution: None,
+            trellis_ratio: None,
+        }
+    }
+
+    pub fn write<W: Write>(&self, mut output_write: W) -> Result<(), std::io::Error> {
+        let mut content = Vec::new();
+        content.extend_from_slice(STEGO_HEADER_TAG);
+        write_field(&mut content, FIELD_VERSION, &[HEADER_VERSION]);
+
+        let mut field = Vec::new();
+        write_policy(&mut field, self.candidate_policy);
+        write_field(&mut content, FIELD_CANDIDATE_POLICY, &field);
+
+        if !self.block_policies.is_empty() {
+            let mut field = Vec::new();
+            for candidate_policy in &self.block_policies {
+                write_policy(&mut field, *candidate_policy);
+            }
+            write_field(&mut content, FIELD_BLOCK_POLICIES, &field);
+        }
+
+        if let Some(max_match_length) = self.literal_channel {
+            let mut field = Vec::new();
+            field.write_u32::<LE>(max_match_length as u32).unwrap();
+            write_field(&mut content, FIELD_LITERAL_CHANNEL, &field);
+        }
+
+        if let Some(max_truncation) = self.match_truncation {
+            let mut field = Vec::new();
+            field.write_u32::<LE>(max_truncation as u32).unwrap();
+            write_field(&mut content, FIELD_MATCH_TRUNCATION, &field);
+        }
+
+        if let Some((block_size, range)) = self.block_channel {
+            let mut field = Vec::new();
+            field.write_u32::<LE>(block_size as u32).unwrap();
+            field.write_u32::<LE>(range as u32).unwrap();
+            write_field(&mut content, FIELD_BLOCK_CHANNEL, &field);
+        }
+
+        if let Some(offset_distribution) = self.offset_distribution {
+            let mut field = Vec::new();
+            for weight in offset_distribution.weights() {
+                field.write_u32::<LE>(*weight).unwrap();
+            }
+            write_field(&mut content, FIELD_OFFSET_DISTRIBUTION, &field);
+        }
+
+        if let Some(ratio) = self.trellis_ratio {
+            let mut field = Vec::new();
+            field.write_u32::<LE>(ratio as u32).unwrap();
+            write_field(&mut content, FIELD_TRELLIS_CODING, &field);
+        }
+
+        output_write.write_u32::<LE>(SKIPPABLE_MAGIC_NUMBER)?;
+        output_write.write_u32::<LE>(content.len() as u32)?;
+        output_write.write_all(&content)?;
+
+        Ok(())
+    }
+
+    /// Parses the contents of a skippable frame, updating the header. Returns
+    /// `false` if the frame was not written by lz4stego, and an error if it
+    /// was written by an incompatible version.
+    pub fn read(&mut self, mut content: &[u8]) -> DecompressResult<bool> {
+        if !content.starts_with(STEGO_HEADER_TAG) {
+            return Ok(false);
+        }
+        content = &content[STEGO_HEADER_TAG.len()..];
+        let mut version = 1;
+
+        while !content.is_empty() {
+            let id = content.read_u8()?;
+            let length = content.read_u8()? as usize;
+            if content.len() < length {
+                return Err(Lz4DecompressError::from_static_str(
+                    "Steganography header is truncated",
+                ));
+            }
+            let mut field = &content[..length];
+            content = &content[length..];
+
+            match id {
+                FIELD_VERSION => {
+                    version = field.read_u8()?;
+                }
+                FIELD_CANDIDATE_POLICY => {
+                    self.candidate_policy = read_policy(&mut field)?;
+                }
+                FIELD_BLOCK_POLICIES => {
+                    self.block_policies.clear();
+                    while !field.is_empty() {
+                        self.block_policies.push(read_policy(&mut field)?);
+                    }
+                }
+                FIELD_LITERAL_CHANNEL => {
+                    self.literal_channel = Some(field.read_u32::<LE>()? as usize);
+                }
+                FIELD_MATCH_TRUNCATION => {
+                    self.match_truncation = Some(field.read_u32::<LE>()? as usize);
+                }
+                FIELD_BLOCK_CHANNEL => {
+                    let block_size = field.read_u32::<LE>()? as usize;
+                    let range = field.read_u32::<LE>()? as usize;
+                    if !(2..=u16::MAX as usize).contains(&range) {
+                        return Err(Lz4DecompressError::from_static_str(
+                            "Invalid block size channel range",
+                        ));
+                    }
+                    self.block_channel = Some((block_size, range));
+                }
+                FIELD_OFFSET_DISTRIBUTION => {
+                    let mut weights = Vec::new();
+                    while !field.is_empty() {
+                        weights.push(field.read_u32::<LE>()?);
+                    }
+                    let offset_distribution = OffsetDistribution::from_weights(&weights);
+                    if !offset_distribution.is_valid() {
+                        return Err(Lz4DecompressError::from_static_str(
+                            "Invalid offset distribution",
+                        ));
+                    }
+                    self.offset_distribution = Some(offset_distribution);
+                }
+                FIELD_TRELLIS_CODING => {
+                    let ratio = field.read_u32::<LE>()? as usize;
+                    if ratio == 0 {
+                        return Err(Lz4DecompressError::from_static_str(
+                            "Invalid trellis coding ratio",
+                        ));
+                    }
+                    self.trellis_ratio = Some(ratio);
+                }
+                // Fields added later are skipped, so that older versions
+                // still read the ones they know
+                _ => {}
+            }
+        }
+
+        if version != HEADER_VERSION {
+            return Err(Lz4DecompressError::from_static_str(
+                "Unsupported steganography header version",
+            ));
+        }
+
+        Ok(true)
+    }
+}
+
+fn write_field(output: &mut Vec<u8>, id: u8, data: &[u8]) {
+    output.write_u8(id).unwrap();
+    output.write_u8(data.len() as u8).unwrap();
+    output.extend_from_slice(data);
+}
+
+fn write_policy(output: &mut Vec<u8>, candidate_policy: CandidatePolicy) {
+    let (kind, param) = candidate_policy.to_raw();
+    output.write_u8(kind).unwrap();
+    output.write_u32::<LE>(param).unwrap();
+}
+
+fn read_policy(data: &mut &[u8]) -> DecompressResult<CandidatePolicy> {
+    let kind = data.read_u8()?;
+    let param = data.read_u32::<LE>()?;
+
+    CandidatePolicy::from_raw(kind, param)
+        .ok_or_else(|| Lz4DecompressError::from_static_str("Invalid candidate policy"))
+}
@@ -0,0 +1,65 @@
+use std::io::Write;
+
+use crate::analysis::analyze;
+use crate::builder::{CompressorBuilder, ParseMode};
+
+fn get_data() -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in 0..4000 {
+        writeln!(data, "line {} of the text, value {};", i % 37, i % 101).unwrap();
+    }
+
+    data
+}
+
+#[test]
+fn test_analyze_plain() {
+    let data = get_data();
+    let options = CompressorBuilder::new().mode(ParseMode::Fast(1));
+    let (result, _) = crate::compress(&data, b"", &options).unwrap();
+
+    let analysis = analyze(result.as_slice()).unwrap();
+    assert!(!analysis.stego_header);
+    assert!(analysis.choice_count() > 0);
+    assert_eq!(
+        analysis
+            .blocks
+            .iter()
+            .map(|block| block.size)
+            .sum::<usize>(),
+        data.len()
+    );
+    assert!(analysis.suspicion() < 0.01, "{}", analysis.suspicion());
+    assert!(analysis.estimated_bits() < 1.0);
+}
+
+#[test]
+fn test_analyze_hidden() {
+    let data = get_data();
+    let hidden_data: Vec<u8> = (0..2000u32).map(|i| (i * 7919 % 251) as u8).collect();
+    let (result, _) = crate::compress(&data, &hidden_data, &CompressorBuilder::new()).unwrap();
+
+    let analysis = analyze(result.as_slice()).unwrap();
+    assert!(analysis.stego_header);
+    assert!(analysis.suspicion() > 0.99, "{}", analysis.suspicion());
+
+    let block = &analysis.blocks[0];
+    assert!(block.non_nearest_fraction() > 0.5);
+    assert!(block.suspicion() > 0.99, "{}", block.suspicion());
+    assert!(block.estimated_bits() > 0.9 * block.capacity_bits);
+    assert_eq!(
+        block.rank_histogram.iter().sum::<usize>(),
+        block.choice_count
+    );
+}
+
+#[test]
+fn test_analyze_stego_header() {
+    let data = get_data();
+    let options = CompressorBuilder::new().match_truncation(1);
+    let (result, _) = crate::compress(&data, b"ab", &options).unwrap();
+
+    let analysis = analyze(result.as_slice()).unwrap();
+    assert!(analysis.stego_header);
+    assert_eq!(analysis.suspicion(), 1.0);
+}
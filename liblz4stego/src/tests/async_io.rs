@@ -0,0 +1,181 @@
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+
+use crate::async_io::{AsyncCompressor, AsyncDecompressor};
+use crate::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use crate::tests::support::get_text_data;
+use crate::{compress, decompress, HiddenDataMode};
+
+/// Accepts or returns at most a few bytes per call, and is pending every
+/// other call.
+struct Trickle {
+    data: Vec<u8>,
+    position: usize,
+    ready: bool,
+}
+
+impl Trickle {
+    fn new(data: Vec<u8>) -> Self {
+        Self {
+            data,
+            position: 0,
+            ready: false,
+        }
+    }
+
+    fn poll_turn(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        self.ready = !self.ready;
+        if self.ready {
+            Poll::Ready(())
+        } else {
+            cx.waker().wake_by_ref();
+            Poll::Pending
+        }
+    }
+}
+
+impl AsyncRead for Trickle {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_turn(cx));
+        let end = (this.position + 5).min(this.data.len());
+        buf.put_slice(&this.data[this.position..end]);
+        this.position = end;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncWrite for Trickle {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let this = self.get_mut();
+        std::task::ready!(this.poll_turn(cx));
+        let count = buf.len().min(5);
+        this.data.extend_from_slice(&buf[..count]);
+        Poll::Ready(Ok(count))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+#[tokio::test]
+async fn test_async_compressor() {
+    let data = get_text_data(24);
+    let hidden_data = b"async hidden data";
+    for options in [
+        CompressorBuilder::new().block_size(BlockMaxSize::Max64KB),
+        CompressorBuilder::new().mode(ParseMode::Trellis(2)),
+    ] {
+        let mut compressor = AsyncCompressor::new(Vec::new(), &options, hidden_data).unwrap();
+        for chunk in data.chunks(10_000) {
+            compressor.write_all(chunk).await.unwrap();
+            // Flushing does not end the blocks
+            compressor.flush().await.unwrap();
+        }
+        compressor.shutdown().await.unwrap();
+        let report = compressor.get_report().unwrap().clone();
+        let result = compressor.into_inner();
+
+        assert_eq!(result, compress(&data, hidden_data, &options).unwrap().0);
+        assert_eq!(report.compressed_size, result.len() as u64);
+        assert_eq!(
+            decompress(&result, HiddenDataMode::Default).unwrap(),
+            (data.clone(), hidden_data.to_vec())
+        );
+    }
+}
+
+#[tokio::test]
+async fn test_async_compressor_progress() {
+    let data = get_text_data(24);
+    let options = CompressorBuilder::new().block_size(BlockMaxSize::Max64KB);
+    let mut compressor = AsyncCompressor::new(Vec::new(), &options, b"progress").unwrap();
+    assert_eq!(compressor.get_progress().unwrap().blocks_written, 0);
+
+    compressor.write_all(&data).await.unwrap();
+    compressor.flush().await.unwrap();
+    let progress = compressor.get_progress().unwrap();
+    assert_eq!(progress.blocks_written, data.len() / (64 * 1024));
+    assert!(progress.compressed_bytes > 0);
+
+    compressor.shutdown().await.unwrap();
+    assert!(compressor.get_progress().is_none());
+    assert!(compressor.write_all(b"more").await.is_err());
+}
+
+#[tokio::test]
+async fn test_async_roundtrip() {
+    let data = get_text_data(24);
+    let options = CompressorBuilder::new().block_size(BlockMaxSize::Max64KB);
+    let mut compressor =
+        AsyncCompressor::new_owned(Trickle::new(Vec::new()), &options, b"owned".to_vec()).unwrap();
+    compressor.write_all(&data).await.unwrap();
+    compressor.flush().await.unwrap();
+    compressor.shutdown().await.unwrap();
+    let result = compressor.into_inner().data;
+
+    let mut decompressor = AsyncDecompressor::new(Trickle::new(result), HiddenDataMode::Default);
+    let mut output = Vec::new();
+    decompressor.read_to_end(&mut output).await.unwrap();
+
+    assert_eq!(output, data);
+    assert_eq!(decompressor.finish(), b"owned");
+}
+
+#[tokio::test]
+async fn test_async_decompressor_hidden_data() {
+    let data = get_text_data(24);
+    let hidden_data = b"read along with the data".repeat(4);
+    let options = CompressorBuilder::new().block_size(BlockMaxSize::Max64KB);
+    let (result, _) = compress(&data, &hidden_data, &options).unwrap();
+
+    let mut decompressor = AsyncDecompressor::new(result.as_slice(), HiddenDataMode::Default);
+    let mut output = Vec::new();
+    let mut hidden_output = Vec::new();
+    let mut buf = [0; 4096];
+    let mut hidden_buf = [0; 7];
+    loop {
+        let count = decompressor.read(&mut buf).await.unwrap();
+        output.extend_from_slice(&buf[..count]);
+        let hidden_count = decompressor.read_hidden_data(&mut hidden_buf);
+        hidden_output.extend_from_slice(&hidden_buf[..hidden_count]);
+        if count == 0 {
+            break;
+        }
+    }
+    // Most of the hidden data was read as the blocks were decompressed
+    assert!(hidden_output.len() > hidden_data.len() / 2);
+    hidden_output.extend_from_slice(&decompressor.finish());
+
+    assert_eq!(output, data);
+    assert_eq!(hidden_output, hidden_data);
+}
+
+#[tokio::test]
+async fn test_async_decompressor_errors() {
+    let data = get_text_data(24);
+    let (result, _) = compress(&data, b"", &CompressorBuilder::new()).unwrap();
+    let mut output = Vec::new();
+
+    let mut decompressor =
+        AsyncDecompressor::new(&result[..result.len() - 1], HiddenDataMode::Default);
+    assert!(decompressor.read_to_end(&mut output).await.is_err());
+
+    let mut decompressor = AsyncDecompressor::new(&b"not lz4"[..], HiddenDataMode::Default);
+    assert!(decompressor.read_to_end(&mut output).await.is_err());
+}
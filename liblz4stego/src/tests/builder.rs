@@ -0,0 +1,160 @@
+use std::io::{Read, Write};
+
+use crate::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use crate::candidate_policy::CandidatePolicy;
+use crate::compressor::Compressor;
+use crate::decompressor::Decompressor;
+use crate::descriptors::{BdByte, FlgByte};
+use crate::errors::OptionsError;
+use crate::offset_distribution::{OffsetDistribution, MAX_RANKS};
+use crate::tests::support::{decode_lz4, get_lz4_frame, get_text_data};
+
+#[test]
+fn test_builder_default() {
+    let data = get_text_data(12);
+    let hidden_data = b"same as the constructor";
+
+    let mut expected = Vec::new();
+    let mut compressor =
+        Compressor::new_with_hidden_data(&mut expected, hidden_data, false).unwrap();
+    compressor.write_all(&data).unwrap();
+    compressor.finish().unwrap();
+
+    let mut result = Vec::new();
+    let mut compressor = CompressorBuilder::new()
+        .build(&mut result, hidden_data)
+        .unwrap();
+    compressor.write_all(&data).unwrap();
+    compressor.finish().unwrap();
+
+    assert_eq!(result, expected);
+}
+
+#[test]
+fn test_builder_frame_options() {
+    let data = get_text_data(12);
+    let hidden_data = b"hidden in a frame with every flag set";
+
+    let mut result = Vec::new();
+    let mut compressor = CompressorBuilder::new()
+        .block_size(BlockMaxSize::Max64KB)
+        .block_independent(false)
+        .block_checksum(true)
+        .content_checksum(false)
+        .content_size(data.len() as u64)
+        .build(&mut result, hidden_data)
+        .unwrap();
+    compressor.write_all(&data).unwrap();
+    let available_bytes = compressor.finish().unwrap().hidden_bytes;
+    assert!(available_bytes >= hidden_data.len());
+
+    let frame = get_lz4_frame(&result);
+    let flg = FlgByte(frame[4]);
+    assert!(!flg.is_block_independent());
+    assert!(flg.is_block_checksum_added());
+    assert!(!flg.is_content_checksum_added());
+    assert!(flg.is_content_size_added());
+    assert_eq!(BdByte(frame[5]).get_block_max_size(), 4);
+    assert_eq!(frame[6..14], (data.len() as u64).to_le_bytes());
+
+    assert_eq!(decode_lz4(&result), data);
+
+    let mut decompressed = Vec::new();
+    let mut decompressor = Decompressor::new(result.as_slice(), false);
+    decompressor.read_to_end(&mut decompressed).unwrap();
+    assert_eq!(decompressed, data);
+    assert_eq!(decompressor.finish(), hidden_data);
+}
+
+#[test]
+fn test_builder_content_size_mismatch() {
+    let mut result = Vec::new();
+    let mut compressor = CompressorBuilder::new()
+        .content_size(10)
+        .build(&mut result, b"")
+        .unwrap();
+    compressor.write_all(b"too short").unwrap();
+
+    assert!(compressor.finish().is_err());
+}
+
+#[test]
+fn test_builder_validation() {
+    let build = |builder: CompressorBuilder| builder.build(Vec::new(), b"ab").err();
+
+    assert_eq!(
+        build(CompressorBuilder::new().mode(ParseMode::Trellis(0))),
+        Some(OptionsError::InvalidValue("trellis ratio"))
+    );
+    assert_eq!(
+        build(
+            CompressorBuilder::new()
+                .mode(ParseMode::Reference(1))
+                .candidate_policy(CandidatePolicy::Any)
+        ),
+        Some(OptionsError::Conflict("candidate policy", "reference mode"))
+    );
+    assert_eq!(
+        build(CompressorBuilder::new().literal_channel(8).search_depth(2)),
+        Some(OptionsError::Conflict("literal channel", "search depth"))
+    );
+    assert_eq!(
+        build(
+            CompressorBuilder::new()
+                .mode(ParseMode::Trellis(4))
+                .match_truncation(2)
+        ),
+        Some(OptionsError::Conflict("match truncation", "trellis mode"))
+    );
+    assert_eq!(
+        build(
+            CompressorBuilder::new()
+                .block_size(BlockMaxSize::Max64KB)
+                .block_size_channel(100_000, 16)
+        ),
+        Some(OptionsError::InvalidValue("block size channel"))
+    );
+    assert_eq!(
+        build(CompressorBuilder::new().mode(ParseMode::Fast(1))),
+        Some(OptionsError::Conflict("hidden data", "fast mode"))
+    );
+    for weights in [&[][..], &[4, 0, 1], &[1; MAX_RANKS + 1]] {
+        assert_eq!(
+            build(
+                CompressorBuilder::new()
+                    .offset_distribution(OffsetDistribution::from_weights(weights))
+            ),
+            Some(OptionsError::InvalidValue("offset distribution"))
+        );
+    }
+    assert!(build(CompressorBuilder::new().search_depth(2).match_truncation(2)).is_none());
+}
+
+#[test]
+fn test_compressor_with_errors() {
+    let hidden_data = b"ab";
+
+    let compressor = Compressor::new_hc_with_hidden_data(Vec::new(), hidden_data, 2).unwrap();
+    assert_eq!(
+        compressor.with_literal_channel(8).err(),
+        Some(OptionsError::Conflict("literal channel", "search depth"))
+    );
+    let compressor = Compressor::new_trellis_with_hidden_data(Vec::new(), hidden_data, 4).unwrap();
+    assert_eq!(
+        compressor.with_match_truncation(2).err(),
+        Some(OptionsError::Conflict("match truncation", "trellis mode"))
+    );
+    let compressor = Compressor::new_with_hidden_data(Vec::new(), hidden_data, false).unwrap();
+    assert_eq!(
+        compressor.with_block_size_channel(1024, 1).err(),
+        Some(OptionsError::InvalidValue("block size channel"))
+    );
+    let compressor = Compressor::new_with_hidden_data(Vec::new(), hidden_data, false).unwrap();
+    assert_eq!(
+        compressor
+            .with_offset_distribution(OffsetDistribution::from_weights(&[2, 0]))
+            .err(),
+        Some(OptionsError::InvalidValue("offset distribution"))
+    );
+    assert!(Compressor::new_trellis_with_hidden_data(Vec::new(), hidden_data, 0).is_err());
+}
@@ -0,0 +1,62 @@
+use crate::builder::CompressorBuilder;
+use crate::candidate_policy::CandidatePolicy;
+use crate::capacity::get_capacity;
+use crate::constants::MAX_BLOCK_SIZE;
+use crate::tests::support::get_text_data;
+
+#[test]
+fn test_capacity_matches_compressor() {
+    let data = get_text_data(8);
+    let policies = [
+        CandidatePolicy::Longest,
+        CandidatePolicy::WithinBytes(2),
+        CandidatePolicy::WithinPercent(10),
+        CandidatePolicy::Any,
+    ];
+
+    let capacities = get_capacity(&data, &policies);
+
+    assert_eq!(capacities.len(), policies.len());
+    for (capacity, policy) in capacities.iter().zip(policies) {
+        let options = CompressorBuilder::new().candidate_policy(policy);
+        let (_, report) = crate::compress(&data, &[], &options).unwrap();
+
+        assert_eq!(capacity.policy, policy);
+        assert_eq!(capacity.exact, policy == CandidatePolicy::Longest);
+        assert_eq!(capacity.bytes, report.hidden_bytes);
+        assert_eq!(capacity.blocks.len(), 1);
+        assert_eq!(capacity.blocks[0].size, data.len());
+        assert_eq!(capacity.blocks[0].bits, capacity.bits);
+        assert!(capacity.bits >= capacity.bytes as f64 * 8.0);
+    }
+    assert!(capacities[3].bits > capacities[0].bits);
+}
+
+#[test]
+fn test_capacity_empty() {
+    let capacities = get_capacity(b"", &[CandidatePolicy::Longest]);
+
+    assert!(capacities[0].blocks.is_empty());
+    assert_eq!(capacities[0].bits, 0.0);
+    assert_eq!(capacities[0].bytes, 0);
+}
+
+#[test]
+fn test_capacity_blocks() {
+    let mut data = get_text_data(8);
+    data = data.repeat(MAX_BLOCK_SIZE / data.len() + 1);
+
+    let capacities = get_capacity(&data, &[CandidatePolicy::Longest]);
+    let (_, report) = crate::compress(&data, &[], &CompressorBuilder::new()).unwrap();
+
+    let capacity = &capacities[0];
+    let sizes: Vec<usize> = capacity.blocks.iter().map(|block| block.size).collect();
+    assert_eq!(sizes, [MAX_BLOCK_SIZE, data.len() - MAX_BLOCK_SIZE]);
+    for (block, block_report) in capacity.blocks.iter().zip(&report.blocks) {
+        assert!(block.bits > 0.0);
+        assert_eq!(block.bits, block_report.hidden_bits);
+    }
+    let bits: f64 = capacity.blocks.iter().map(|block| block.bits).sum();
+    assert!((bits - capacity.bits).abs() < 1e-6);
+    assert_eq!(capacity.bytes, report.hidden_bytes);
+}
@@ -1,23 +1,19 @@
-use lz4::Decoder;
-use std::io::{Read, Write};
+use lz4::block::CompressionMode;
+use std::io::Write;
 
+use crate::builder::{CompressorBuilder, ParseMode};
 use crate::compressor::Compressor;
+use crate::constants::MF_LIMIT;
+use crate::tests::support::{decode_lz4, get_lz4_frame, pseudo_random_data};
 
-fn decode_lz4(data: &Vec<u8>) -> Vec<u8> {
-    let mut buffer = Vec::new();
-    let mut decoder = Decoder::new(data.as_slice()).unwrap();
-    decoder.read_to_end(&mut buffer).unwrap();
+fn compress_fast(data: &[u8], acceleration: u32) -> Vec<u8> {
+    let options = CompressorBuilder::new().mode(ParseMode::Fast(acceleration));
 
-    buffer
+    crate::compress(data, b"", &options).unwrap().0
 }
 
 fn compress(data: &[u8]) -> Vec<u8> {
-    let mut output = Vec::new();
-    let mut compressor = Compressor::new(&mut output).unwrap();
-    compressor.write(data).unwrap();
-    compressor.finish().unwrap();
-
-    output
+    compress_fast(data, 1)
 }
 
 #[test]
@@ -84,3 +80,143 @@ fn compress_long_match() {
 
     assert_eq!(data, decoded.as_slice());
 }
+
+#[test]
+fn compress_fast_acceleration() {
+    let data = include_bytes!("test_data/medium");
+    let result = compress_fast(data, 8);
+    let decoded = decode_lz4(&result);
+
+    assert_eq!(data, decoded.as_slice());
+}
+
+#[test]
+fn compress_lengths_ending_with_255() {
+    // Literal and match lengths whose last LSIC byte is 255 need a trailing 0
+    let literals = pseudo_random_data(270, 1);
+    let repeated = pseudo_random_data(274, 2);
+    let mut matched = repeated.clone();
+    matched.extend_from_slice(&repeated);
+    matched.extend_from_slice(&pseudo_random_data(20, 3));
+
+    for data in [literals, matched].iter() {
+        let result = compress(data);
+        let decoded = decode_lz4(&result);
+
+        assert_eq!(data, &decoded);
+
+        let (result, _) = crate::compress(data, b"", &CompressorBuilder::new()).unwrap();
+
+        assert_eq!(data, &decode_lz4(&result));
+        assert_eq!(
+            data,
+            &crate::decompress(&result, crate::HiddenDataMode::Default)
+                .unwrap()
+                .0
+        );
+    }
+}
+
+/// Decompressed positions of the matches of a compressed block.
+fn get_match_positions(mut block: &[u8]) -> Vec<usize> {
+    let read_length = |block: &mut &[u8], initial_val: usize| {
+        let mut val = initial_val;
+        if initial_val == 15 {
+            loop {
+                let byte = block[0] as usize;
+                *block = &block[1..];
+                val += byte;
+                if byte < 255 {
+                    break;
+                }
+            }
+        }
+        val
+    };
+    let mut positions = Vec::new();
+    let mut position = 0;
+    loop {
+        let token = block[0] as usize;
+        block = &block[1..];
+        let literals_length = read_length(&mut block, token >> 4);
+        block = &block[literals_length..];
+        position += literals_length;
+        if block.is_empty() {
+            return positions;
+        }
+
+        block = &block[2..];
+        positions.push(position);
+        position += read_length(&mut block, token & 15) + 4;
+    }
+}
+
+#[test]
+fn compress_no_match_near_block_end() {
+    // The last 10 bytes repeat earlier ones, but the format requires the last
+    // match to start at least 12 bytes before the end of the block
+    let mut data = b"abcdefghijklmnop".repeat(8);
+    let random = pseudo_random_data(20, 6);
+    data.extend_from_slice(&random);
+    data.extend_from_slice(&random[..10]);
+    let (result, _) = crate::compress(&data, b"", &CompressorBuilder::new()).unwrap();
+    let frame = get_lz4_frame(&result);
+
+    assert_eq!(frame[10] & 0x80, 0);
+    let positions = get_match_positions(&frame[11..frame.len() - 8]);
+    assert!(!positions.is_empty());
+    assert!(positions
+        .iter()
+        .all(|position| position + MF_LIMIT <= data.len()));
+    assert_eq!(decode_lz4(&result), data);
+}
+
+#[test]
+fn compress_matches_reference_block() {
+    // Inputs below and above the size limit for the 16-bit position table
+    let short_data = include_bytes!("test_data/text").to_vec();
+    let mut long_data = short_data.repeat(6);
+    long_data.extend_from_slice(&pseudo_random_data(10_000, 4));
+
+    for (data, block_size_id) in [(short_data, 4), (long_data, 5)].iter() {
+        for acceleration in [1, 8].iter() {
+            let result = compress_fast(data, *acceleration);
+
+            let expected = lz4::block::compress(
+                data,
+                Some(CompressionMode::FAST(*acceleration as i32)),
+                false,
+            )
+            .unwrap();
+            assert_eq!(result[5] >> 4, *block_size_id);
+            assert_eq!(&result[11..result.len() - 8], expected.as_slice());
+        }
+    }
+}
+
+#[test]
+fn compress_incompressible_block_uncompressed() {
+    let data = pseudo_random_data(1000, 5);
+    let result = compress(&data);
+
+    assert_eq!(result[10] & 0x80, 0x80);
+    assert_eq!(&result[11..result.len() - 8], data.as_slice());
+}
+
+#[test]
+fn compress_header_written_with_first_block() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"ab";
+    let mut output = Vec::new();
+    let mut compressor = Compressor::new_with_hidden_data(&mut output, hidden_data, false)
+        .unwrap()
+        .with_match_truncation(2)
+        .unwrap();
+    compressor.write_all(data).unwrap();
+    assert_eq!(compressor.get_progress().compressed_bytes, 0);
+    compressor.finish().unwrap();
+
+    // The header records the setting made after the constructor
+    let result = crate::decompress(&output, crate::HiddenDataMode::Default).unwrap();
+    assert_eq!(result, (data.to_vec(), hidden_data.to_vec()));
+}
@@ -1,13 +1,11 @@
-use crate::decompressor::Decompressor;
+use crate::decompressor::{get_block_max_size, Decompressor};
 use crate::errors::DecompressResult;
+use crate::tests::support::get_text_data;
+use crate::HiddenDataMode;
 use std::io::Read;
 
 fn decompress(data: &[u8]) -> DecompressResult<(Vec<u8>, Vec<u8>)> {
-    let mut output = Vec::new();
-    let mut decompressor = Decompressor::new(data, false);
-    decompressor.read_to_end(&mut output).unwrap();
-
-    Ok((output, decompressor.finish()))
+    crate::decompress(data, HiddenDataMode::Default)
 }
 
 #[test]
@@ -83,3 +81,187 @@ fn decompress_large_two_blocks() {
     assert!(result.is_ok(), "{}", result.unwrap_err());
     assert_eq!(result.unwrap().0, b"a".repeat(8_388_608));
 }
+
+#[test]
+fn decompress_without_hidden_data() {
+    let bytes = include_bytes!("test_data/medium.lz4");
+    let expected = include_bytes!("test_data/medium");
+    let mut output = Vec::new();
+    let mut decompressor = Decompressor::new_without_hidden_data(&bytes[..]);
+    decompressor.read_to_end(&mut output).unwrap();
+
+    assert_eq!(output, expected);
+    assert_eq!(decompressor.finish(), b"");
+}
+
+#[test]
+fn decompress_linked_blocks() {
+    let mut data = Vec::new();
+    for i in 0..20000 {
+        data.extend_from_slice(format!("line {} of {}\n", i % 61, i % 13).as_bytes());
+    }
+    let mut compressed = Vec::new();
+    let mut encoder = lz4::EncoderBuilder::new()
+        .block_size(lz4::BlockSize::Max64KB)
+        .block_mode(lz4::BlockMode::Linked)
+        .build(&mut compressed)
+        .unwrap();
+    std::io::Write::write_all(&mut encoder, &data).unwrap();
+    encoder.finish().1.unwrap();
+
+    let result = decompress(&compressed);
+
+    assert!(result.is_ok(), "{}", result.unwrap_err());
+    assert_eq!(result.unwrap().0, data);
+}
+
+#[test]
+fn decompress_block_max_size() {
+    assert_eq!(get_block_max_size(4).unwrap(), 64 * 1024);
+    assert_eq!(get_block_max_size(5).unwrap(), 256 * 1024);
+    assert_eq!(get_block_max_size(6).unwrap(), 1024 * 1024);
+    assert_eq!(get_block_max_size(7).unwrap(), 4 * 1024 * 1024);
+
+    // Values below 4 are reserved
+    let mut bytes = include_bytes!("test_data/medium.lz4").to_vec();
+    for id in 0..4 {
+        bytes[5] = (bytes[5] & 0x8F) | (id << 4);
+        assert!(decompress(&bytes).is_err());
+    }
+}
+
+/// Frame of compressed blocks without checksums.
+fn build_frame(block_independent: bool, blocks: &[&[u8]]) -> Vec<u8> {
+    let flg = if block_independent { 0x60 } else { 0x40 };
+    let mut frame = vec![0x04, 0x22, 0x4D, 0x18, flg, 0x40, 0x00];
+    for block in blocks {
+        frame.extend_from_slice(&(block.len() as u32).to_le_bytes());
+        frame.extend_from_slice(block);
+    }
+    frame.extend_from_slice(&[0; 4]);
+
+    frame
+}
+
+#[test]
+fn decompress_offset_out_of_range() {
+    // 6 literals, then a match of 4 bytes at offset 7, then 5 literals
+    let block = b"\x60abcdef\x07\x00\x50ghijk";
+    assert!(decompress(&build_frame(true, &[block])).is_err());
+
+    // Independent blocks cannot refer to the previous ones
+    let previous_block = b"\x10z";
+    assert!(decompress(&build_frame(true, &[previous_block, block])).is_err());
+}
+
+#[test]
+fn decompress_truncated_lengths() {
+    // The literals length and the match length continue past the block end
+    let blocks: [&[u8]; 2] = [b"\xf0", b"\x1fa\x01\x00"];
+    for block in blocks {
+        let frame = build_frame(true, &[block]);
+        assert!(decompress(&frame).is_err());
+        assert!(crate::decompress_into(&frame, &mut [0; 64]).is_err());
+    }
+}
+
+#[test]
+fn decompress_truncated_literals() {
+    // The literal run is longer than what is left of the block
+    let blocks: [&[u8]; 2] = [b"\x50ab", b"\x10a\x01\x00\x50ab"];
+    for block in blocks {
+        let frame = build_frame(true, &[block]);
+        assert!(decompress(&frame).is_err());
+        assert!(crate::decompress_into(&frame, &mut [0; 64]).is_err());
+    }
+}
+
+#[test]
+fn decompress_oversized_block() {
+    // A match of 100000 bytes in a frame of 64 KB blocks
+    let mut block = b"\x1fa\x01\x00".to_vec();
+    let length = 100_000 - 4 - 15;
+    block.resize(block.len() + length / 255, 255);
+    block.push((length % 255) as u8);
+    block.extend_from_slice(b"\x50abcde");
+    let frame = build_frame(true, &[&block]);
+
+    let error = decompress(&frame).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Decompressed block is too large"));
+    let error = crate::decompress_into(&frame, &mut vec![0; 200_000]).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("Decompressed block is too large"));
+}
+
+#[test]
+fn decompress_linked_history() {
+    // The match of the second block copies the last byte of the first one
+    let previous_block = b"\x10z";
+    let block = b"\x60abcdef\x07\x00\x50ghijk";
+    let result = decompress(&build_frame(false, &[previous_block, block]));
+
+    assert!(result.is_ok(), "{}", result.unwrap_err());
+    assert_eq!(result.unwrap().0, b"zabcdefzabcghijk");
+}
+
+fn compress_with_hidden_data(hidden_fraction: (usize, usize)) -> (Vec<u8>, Vec<u8>, Vec<u8>) {
+    use crate::builder::{BlockMaxSize, CompressorBuilder};
+    use std::io::Write;
+
+    let data = get_text_data(24);
+    let builder = CompressorBuilder::new().block_size(BlockMaxSize::Max64KB);
+
+    let mut probe = builder.build(std::io::sink(), &[0xA5; 1 << 16]).unwrap();
+    probe.write_all(&data).unwrap();
+    let hidden_len = probe.finish().unwrap().hidden_bytes * hidden_fraction.0 / hidden_fraction.1;
+    let hidden_data: Vec<u8> = (1..=hidden_len).map(|i| (i * 13) as u8).collect();
+
+    let mut compressed = Vec::new();
+    let mut compressor = builder.build(&mut compressed, &hidden_data).unwrap();
+    compressor.write_all(&data).unwrap();
+    compressor.finish().unwrap();
+
+    (data, hidden_data, compressed)
+}
+
+#[test]
+fn decompress_hidden_data_before_end() {
+    let (data, hidden_data, compressed) = compress_with_hidden_data((3, 4));
+
+    let mut decompressor = Decompressor::new(&compressed[..], false);
+    let mut output = vec![0; data.len() / 2];
+    decompressor.read_exact(&mut output).unwrap();
+    let mut hidden_result = vec![0; hidden_data.len()];
+    let count = decompressor.read_hidden_data(&mut hidden_result);
+
+    assert!(count > 0);
+    assert!(!decompressor.is_hidden_data_complete());
+    assert_eq!(hidden_result[..count], hidden_data[..count]);
+}
+
+#[test]
+fn decompress_hidden_reader() {
+    let (data, hidden_data, compressed) = compress_with_hidden_data((3, 4));
+
+    let decompressor = Decompressor::new(&compressed[..], false);
+    let mut hidden_reader = decompressor.into_hidden_reader(Vec::new());
+    let mut hidden_result = Vec::new();
+    hidden_reader.read_to_end(&mut hidden_result).unwrap();
+
+    assert_eq!(hidden_result, hidden_data);
+    assert_eq!(hidden_reader.into_inner(), data);
+}
+
+#[test]
+fn decompress_hidden_data_prefix() {
+    let (data, hidden_data, compressed) = compress_with_hidden_data((2, 1));
+
+    let (output, hidden_result) = decompress(&compressed).unwrap();
+
+    assert_eq!(output, data);
+    assert!(hidden_result.len() < hidden_data.len());
+    assert_eq!(hidden_result, hidden_data[..hidden_result.len()]);
+}
@@ -0,0 +1,80 @@
+use std::io::Write;
+
+use byteorder::{ByteOrder, LE};
+use lz4::{BlockMode, BlockSize, EncoderBuilder};
+
+use crate::embedder::{embed, extract};
+use crate::tests::support::{decode_lz4, get_text_data};
+
+fn compress_lz4(data: &[u8], block_mode: BlockMode, level: u32) -> Vec<u8> {
+    let mut compressed = Vec::new();
+    let mut encoder = EncoderBuilder::new()
+        .block_size(BlockSize::Max64KB)
+        .block_mode(block_mode)
+        .level(level)
+        .build(&mut compressed)
+        .unwrap();
+    encoder.write_all(data).unwrap();
+    encoder.finish().1.unwrap();
+
+    compressed
+}
+
+/// Block size fields of a frame without content size and block checksums.
+fn get_block_sizes(frame: &[u8]) -> Vec<u32> {
+    let mut block_sizes = Vec::new();
+    let mut position = 7;
+    loop {
+        let block_size = LE::read_u32(&frame[position..]);
+        block_sizes.push(block_size);
+        if block_size == 0 {
+            return block_sizes;
+        }
+        position += 4 + (block_size & 0x7FFF_FFFF) as usize;
+    }
+}
+
+fn test_embed(block_mode: BlockMode, level: u32) {
+    // Spans two blocks, which refer to each other in the linked mode
+    let data = get_text_data(12);
+    let hidden_data = b"hidden in somebody else's file";
+    let compressed = compress_lz4(&data, block_mode, level);
+
+    let mut result = Vec::new();
+    let available_bytes = embed(compressed.as_slice(), &mut result, hidden_data).unwrap();
+    assert!(available_bytes >= hidden_data.len());
+
+    assert_eq!(result.len(), compressed.len());
+    assert_eq!(result[..7], compressed[..7]);
+    assert_eq!(get_block_sizes(&result), get_block_sizes(&compressed));
+    assert_ne!(result, compressed);
+    assert_eq!(decode_lz4(&result), data);
+    assert_eq!(extract(result.as_slice()).unwrap(), hidden_data);
+}
+
+#[test]
+fn test_embed_linked_blocks() {
+    test_embed(BlockMode::Linked, 0);
+}
+
+#[test]
+fn test_embed_independent_blocks_hc() {
+    test_embed(BlockMode::Independent, 9);
+}
+
+#[test]
+fn test_extract_oversized_literals() {
+    // A single block holding one literal run longer than any block may be
+    let literals_length = 4 * 1024 * 1024 + 1;
+    let mut block = vec![0xF0];
+    block.resize(1 + (literals_length - 15) / 255, 255);
+    block.push(((literals_length - 15) % 255) as u8);
+    block.resize(block.len() + literals_length, b'a');
+
+    let mut frame = vec![0x04, 0x22, 0x4D, 0x18, 0x60, 0x70, 0x73];
+    frame.extend_from_slice(&(block.len() as u32).to_le_bytes());
+    frame.extend_from_slice(&block);
+    frame.extend_from_slice(&[0; 4]);
+
+    assert!(extract(frame.as_slice()).is_err());
+}
@@ -0,0 +1,126 @@
+use crate::builder::{BlockMaxSize, CompressorBuilder};
+use crate::candidate_policy::CandidatePolicy;
+use crate::compress;
+use crate::constants::{LZ4_MAGIC_NUMBER, SKIPPABLE_MAGIC_NUMBER};
+use crate::frame_parser::{Event, FrameParser};
+use crate::tests::support::{get_lz4_frame, get_text_data};
+
+#[test]
+fn test_frame_parser_steps() {
+    let data = get_text_data(24);
+    let options = CompressorBuilder::new()
+        .candidate_policy(CandidatePolicy::WithinBytes(2))
+        .block_size(BlockMaxSize::Max64KB)
+        .block_checksum(true)
+        .content_size(data.len() as u64);
+    let (result, _) = compress(&data, b"header", &options).unwrap();
+
+    let mut parser = FrameParser::new();
+    let mut position = 0;
+    let mut events = Vec::new();
+    while position < result.len() {
+        let len = parser.get_needed_len();
+        events.push(parser.advance(&result[position..position + len]).unwrap());
+        position += len;
+    }
+    assert_eq!(position, result.len());
+    assert!(parser.is_between_frames());
+
+    assert!(matches!(events[2], Event::SkippableFrame(_)));
+    match events[5] {
+        Event::FrameHeader(frame_options) => {
+            assert_eq!(frame_options.block_size_id, Some(4));
+            assert!(frame_options.block_checksum);
+            assert_eq!(frame_options.content_size, Some(data.len() as u64));
+        }
+        _ => panic!("the frame header is not the sixth step"),
+    }
+    let block_count = events
+        .iter()
+        .filter(|event| matches!(event, Event::Block(_)))
+        .count();
+    assert_eq!(block_count, data.len().div_ceil(65536));
+    assert!(matches!(events.last(), Some(Event::FrameEnd(Some(_)))));
+}
+
+#[test]
+fn test_frame_parser_errors() {
+    let mut parser = FrameParser::new();
+    assert!(parser.advance(b"lz4?").is_err());
+
+    // A block larger than the maximum of the frame header
+    let mut parser = FrameParser::new();
+    let frame = [
+        &LZ4_MAGIC_NUMBER.to_le_bytes()[..],
+        &[0x60, 0x40, 0x82],
+        &0x10001u32.to_le_bytes(),
+    ]
+    .concat();
+    let mut position = 0;
+    let error = loop {
+        let len = parser.get_needed_len();
+        match parser.advance(&frame[position..position + len]) {
+            Ok(_) => position += len,
+            Err(e) => break e,
+        }
+    };
+    assert_eq!(position, 7);
+    assert!(error.to_string().contains("Block is too large"));
+}
+
+#[test]
+fn test_frame_parser_read_event() {
+    let mut buffer = Vec::new();
+    let mut parser = FrameParser::new();
+    assert!(parser.read_event(&b""[..], &mut buffer).unwrap().is_none());
+
+    let (result, _) = compress(b"abc", b"", &CompressorBuilder::new()).unwrap();
+    let mut input = &result[..result.len() - 2];
+    let mut parser = FrameParser::new();
+    let error = loop {
+        match parser.read_event(&mut input, &mut buffer) {
+            Ok(event) => assert!(event.is_some()),
+            Err(e) => break e,
+        }
+    };
+    assert!(!parser.is_between_frames());
+    assert!(error.to_string().contains("end of file"));
+}
+
+#[test]
+fn test_frame_parser_large_skippable_frames() {
+    let (result, _) = compress(b"abc", b"", &CompressorBuilder::new()).unwrap();
+    let mut data = Vec::new();
+    // A skippable frame of another tool, and one too large to be a header
+    for magic_number in [SKIPPABLE_MAGIC_NUMBER + 1, SKIPPABLE_MAGIC_NUMBER] {
+        data.extend_from_slice(&magic_number.to_le_bytes());
+        data.extend_from_slice(&200_000u32.to_le_bytes());
+        data.resize(data.len() + 200_000, 0);
+    }
+    data.extend_from_slice(get_lz4_frame(&result));
+
+    let mut buffer = Vec::new();
+    let mut parser = FrameParser::new();
+    let mut input = data.as_slice();
+    let mut events = Vec::new();
+    while let Some(event) = parser.read_event(&mut input, &mut buffer).unwrap() {
+        events.push(event);
+    }
+    assert!(buffer.len() <= 64 * 1024);
+    assert!(!events
+        .iter()
+        .any(|event| matches!(event, Event::SkippableFrame(_))));
+    assert!(matches!(events.last(), Some(Event::FrameEnd(_))));
+
+    // The content announced is not read all at once
+    let mut buffer = Vec::new();
+    let mut parser = FrameParser::new();
+    let mut input = &[0x5F, 0x2A, 0x4D, 0x18, 0xFF, 0xFF, 0xFF, 0xFF, 0][..];
+    let error = loop {
+        if let Err(e) = parser.read_event(&mut input, &mut buffer) {
+            break e;
+        }
+    };
+    assert!(error.to_string().contains("end of file"));
+    assert!(buffer.len() <= 64 * 1024);
+}
@@ -0,0 +1,188 @@
+use std::io::{Read, Write};
+use std::sync::{Arc, Mutex};
+
+use crate::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use crate::compressor::{Compressor, OwnedCompressor};
+use crate::decompressor::Decompressor;
+use crate::errors::OptionsError;
+use crate::hidden_source::HiddenSource;
+use crate::tests::support::get_text_data;
+
+fn get_hidden_data(len: usize) -> Vec<u8> {
+    (1..=len).map(|i| (i * 7 + i / 251) as u8).collect()
+}
+
+fn extract(data: &[u8]) -> Vec<u8> {
+    let mut decompressor = Decompressor::new(data, false);
+    std::io::copy(&mut decompressor, &mut std::io::sink()).unwrap();
+
+    decompressor.finish()
+}
+
+/// Returns at most a few bytes per call, like a pipe would.
+struct TrickleReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Read for TrickleReader<'a> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let count = buf.len().min(self.data.len()).min(3);
+        buf[..count].copy_from_slice(&self.data[..count]);
+        self.data = &self.data[count..];
+
+        Ok(count)
+    }
+}
+
+#[derive(Clone)]
+struct SharedWriter(Arc<Mutex<Vec<u8>>>);
+
+impl Write for SharedWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+struct FailingReader;
+
+impl Read for FailingReader {
+    fn read(&mut self, _buf: &mut [u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::other("broken pipe"))
+    }
+}
+
+#[test]
+fn test_hidden_reader_matches_slice() {
+    let data = get_text_data(24);
+    let builder = CompressorBuilder::new().block_size(BlockMaxSize::Max64KB);
+
+    let mut probe = builder.build(std::io::sink(), &[0xA5; 1 << 16]).unwrap();
+    probe.write_all(&data).unwrap();
+    // Spans several blocks, so that read bytes are released along the way
+    let hidden_data = get_hidden_data(probe.finish().unwrap().hidden_bytes * 3 / 4);
+
+    let mut expected = Vec::new();
+    let mut compressor = builder.build(&mut expected, &hidden_data).unwrap();
+    compressor.write_all(&data).unwrap();
+    compressor.finish().unwrap();
+
+    let mut result = Vec::new();
+    let reader = TrickleReader { data: &hidden_data };
+    let mut compressor = builder.build_with_reader(&mut result, reader).unwrap();
+    compressor.write_all(&data).unwrap();
+    compressor.finish().unwrap();
+
+    assert_eq!(expected, result);
+    assert_eq!(hidden_data, extract(&result));
+}
+
+#[test]
+fn test_hidden_reader_constructor() {
+    let data = get_text_data(24);
+    let hidden_data = get_hidden_data(100);
+
+    let mut expected = Vec::new();
+    let mut compressor =
+        Compressor::new_with_hidden_data(&mut expected, &hidden_data, true).unwrap();
+    compressor.write_all(&data).unwrap();
+    compressor.finish().unwrap();
+
+    let mut result = Vec::new();
+    let mut compressor =
+        Compressor::new_with_hidden_reader(&mut result, hidden_data.as_slice(), true).unwrap();
+    compressor.write_all(&data).unwrap();
+    compressor.finish().unwrap();
+
+    assert_eq!(expected, result);
+}
+
+#[test]
+fn test_owned_compressor_in_thread() {
+    let data = get_text_data(24);
+    let hidden_data = get_hidden_data(20);
+
+    let builder = CompressorBuilder::new().mode(ParseMode::Trellis(2));
+    let result = SharedWriter(Arc::new(Mutex::new(Vec::new())));
+    let mut compressor: OwnedCompressor<SharedWriter> = builder
+        .build_owned(result.clone(), hidden_data.clone())
+        .unwrap();
+    std::thread::spawn(move || {
+        compressor.write_all(&data).unwrap();
+        compressor.finish().unwrap();
+    })
+    .join()
+    .unwrap();
+
+    let mut expected = Vec::new();
+    let mut reference = builder.build(&mut expected, &hidden_data).unwrap();
+    reference.write_all(&get_text_data(24)).unwrap();
+    reference.finish().unwrap();
+
+    let result = result.0.lock().unwrap();
+    assert_eq!(expected, *result);
+    assert_eq!(hidden_data, extract(&result));
+}
+
+#[test]
+fn test_hidden_reader_error() {
+    let data = get_text_data(24);
+    let mut compressor = CompressorBuilder::new()
+        .build_with_reader(std::io::sink(), FailingReader)
+        .unwrap();
+    compressor.write_all(&data).unwrap();
+
+    let error = compressor.finish().unwrap_err();
+    assert_eq!(error.to_string(), "broken pipe");
+}
+
+#[test]
+fn test_hidden_reader_conflicts() {
+    for mode in [
+        ParseMode::Fast(1),
+        ParseMode::Trellis(2),
+        ParseMode::Planned(10),
+    ] {
+        let result = CompressorBuilder::new()
+            .mode(mode)
+            .build_with_reader(std::io::sink(), std::io::empty());
+        assert!(matches!(
+            result,
+            Err(OptionsError::Conflict("hidden data reader", _))
+        ));
+    }
+}
+
+fn check_source_bytes(source: HiddenSource, data: &[u8]) {
+    for position in [0, 1, 4095, 4096, data.len() - 1] {
+        assert_eq!(source.get_byte(position), Some(data[position]));
+    }
+    source.release(5000);
+    assert_eq!(source.get_byte(5000), Some(data[5000]));
+    assert_eq!(source.get_byte(data.len()), None);
+    assert!(source.take_error().is_none());
+}
+
+#[test]
+fn test_source_bytes() {
+    let data = get_hidden_data(10_000);
+    check_source_bytes(HiddenSource::new(&data), &data);
+    check_source_bytes(HiddenSource::from_vec(data.clone()), &data);
+    check_source_bytes(
+        HiddenSource::from_reader(TrickleReader { data: &data }),
+        &data,
+    );
+}
+
+#[test]
+#[should_panic(expected = "Byte was already released")]
+fn test_source_released_byte() {
+    let data = get_hidden_data(100);
+    let source = HiddenSource::from_reader(data.as_slice());
+    source.get_byte(50);
+    source.release(50);
+    source.get_byte(49);
+}
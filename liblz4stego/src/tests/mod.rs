@@ -1,4 +1,21 @@
+mod analysis;
+#[cfg(feature = "tokio")]
+mod async_io;
+mod builder;
+mod capacity;
 mod compressor;
 mod decompressor;
+mod embedder;
+mod frame_parser;
+mod hidden_source;
 mod numeral_coding;
+mod occurrence_map;
+mod oneshot;
+mod patcher;
+mod progress;
+mod push_decoder;
+mod report;
+mod sanitizer;
 mod stego;
+mod support;
+mod trellis_coding;
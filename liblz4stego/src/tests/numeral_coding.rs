@@ -1,4 +1,6 @@
 use crate::numeral_coding::{Decoder, Encoder};
+use crate::shaped_coding::ShapedModel;
+use crate::tests::support::pseudo_random_data;
 
 fn encode(values: &[u16], max_values: &[u16]) -> Vec<u8> {
     let mut encoder = Encoder::new();
@@ -43,162 +45,202 @@ fn decode_empty() {
     assert_eq!(result, expected);
 }
 
+fn roundtrip(data: &[u8], max_values: &[u16]) -> (Vec<u16>, Vec<u8>) {
+    let digits = decode(data, max_values);
+    let result = encode(&digits, max_values);
+
+    (digits, result)
+}
+
+fn mixed_max_values(count: usize) -> Vec<u16> {
+    (0..count)
+        .map(|i| [2, 256, 10, 7, 53, 2133, 100, 15, 9533][i % 9])
+        .collect()
+}
+
 #[test]
-fn encode_single() {
-    let values = vec![1];
-    let max_values = vec![2];
-    let expected = vec![1];
+fn roundtrip_single() {
+    let (digits, result) = roundtrip(&[1], &[2; 16]);
 
-    let result = encode(&values, &max_values);
+    assert_eq!(result, vec![1]);
+    assert!(digits.iter().any(|digit| *digit != 0));
+}
 
-    assert_eq!(result, expected);
+#[test]
+fn roundtrip_bytes() {
+    let data = vec![15, 129, 215, 66, 100, 121, 5, 199];
+
+    let (_, result) = roundtrip(&data, &[256; 16]);
+
+    assert_eq!(result, data);
 }
 
 #[test]
-fn decode_single() {
-    let data = vec![1];
-    let max_values = vec![2];
-    let expected = vec![1];
+fn roundtrip_leading_zeros() {
+    let data = vec![0, 0, 7, 0];
 
-    let result = decode(&data, &max_values);
+    let (_, result) = roundtrip(&data, &[3; 64]);
 
-    assert_eq!(result, expected);
+    assert_eq!(result, data);
 }
 
 #[test]
-fn encode_bytes_short() {
-    let values = vec![15, 129];
-    let max_values = vec![256, 256];
-    let expected = vec![129, 15];
+fn roundtrip_mixed() {
+    let data = pseudo_random_data(300, 0x9e37_79b9);
 
-    let result = encode(&values, &max_values);
+    let (_, result) = roundtrip(&data, &mixed_max_values(1000));
 
-    assert_eq!(result, expected);
+    assert_eq!(result, data);
 }
 
 #[test]
-fn decode_bytes_short() {
-    let data = vec![129, 15];
-    let max_values = vec![256, 256];
-    let expected = vec![15, 129];
+fn roundtrip_long_chunks() {
+    let data = pseudo_random_data(255 * 3, 0x9e37_79b9);
 
-    let result = decode(&data, &max_values);
+    let (_, result) = roundtrip(&data, &[256; 1000]);
 
-    assert_eq!(result, expected);
+    assert_eq!(result, data);
 }
 
 #[test]
-fn encode_bytes() {
-    let values = vec![15, 129, 215, 66, 100, 121, 5, 199];
-    let max_values = vec![256, 256, 256, 256, 256, 256, 256, 256];
-    let expected = vec![199, 5, 15, 129, 215, 66, 100, 121];
+fn overflow_keeps_prefix() {
+    let data = pseudo_random_data(100, 0x9e37_79b9);
+    let max_values = mixed_max_values(40);
 
-    let result = encode(&values, &max_values);
+    let mut decoder = Decoder::new(&data);
+    let digits: Vec<u16> = max_values
+        .iter()
+        .map(|max_value| decoder.decode_value(*max_value))
+        .collect();
+    let available_bytes = decoder.get_available_bytes();
+    let result = encode(&digits, &max_values);
 
-    assert_eq!(result, expected);
+    assert!(available_bytes < data.len());
+    assert!(result.len() >= available_bytes);
+    assert_eq!(result, data[..result.len()]);
 }
 
 #[test]
-fn decode_bytes() {
-    let data = vec![199, 5, 15, 129, 215, 66, 100, 121];
-    let max_values = vec![256, 256, 256, 256, 256, 256, 256, 256];
-    let expected = vec![15, 129, 215, 66, 100, 121, 5, 199];
+fn read_before_finish() {
+    let data = pseudo_random_data(200, 0x9e37_79b9);
+    let max_values = mixed_max_values(1000);
+    let digits = decode(&data, &max_values);
 
-    let result = decode(&data, &max_values);
+    let mut encoder = Encoder::new();
+    let mut result = Vec::new();
+    let mut buf = [0; 16];
+    for (i, (digit, max_value)) in digits.iter().zip(&max_values).enumerate() {
+        encoder.add_value(*digit, *max_value);
+        let count = encoder.read(&mut buf);
+        result.extend_from_slice(&buf[..count]);
+        if i == max_values.len() / 2 {
+            assert!(result.len() > 50, "{} bytes read", result.len());
+        }
+    }
 
-    assert_eq!(result, expected);
+    assert!(!encoder.is_complete());
+    encoder.end();
+    assert!(encoder.is_complete());
+    result.extend(encoder.finish());
+    assert_eq!(result, data);
 }
 
 #[test]
-fn encode_mixed_short() {
-    let values = vec![3, 6, 10];
-    let max_values = vec![10, 7, 53];
-    let expected = vec![2, 251];
+fn available_bytes_matches_capacity() {
+    let max_values = [256; 100];
+    let mut decoder = Decoder::new(&[]);
+    for max_value in max_values {
+        decoder.decode_value(max_value);
+    }
+    let available_bytes = decoder.get_available_bytes();
+    let data = pseudo_random_data(available_bytes, 0x9e37_79b9);
 
-    let result = encode(&values, &max_values);
+    let (_, result) = roundtrip(&data, &max_values);
 
-    assert_eq!(result, expected);
+    assert!(available_bytes > 90);
+    assert_eq!(result, data);
 }
 
 #[test]
-fn decode_mixed_short() {
-    let data = vec![2, 251];
-    let max_values = vec![10, 7, 53];
-    let expected = vec![3, 6, 10];
+fn used_bytes_exclude_read_ahead() {
+    let data = pseudo_random_data(100, 0x9e37_79b9);
+    let mut decoder = Decoder::new(&data);
+    let mut read_ahead = false;
+
+    for _ in 0..120 {
+        decoder.decode_value(256);
+        let used_bytes = decoder.get_used_bytes();
+        assert!(used_bytes <= decoder.get_available_bytes());
+        assert!(used_bytes <= decoder.get_consumed_bytes());
+        read_ahead |= used_bytes < decoder.get_consumed_bytes();
+    }
 
-    let result = decode(&data, &max_values);
+    assert!(read_ahead);
+    assert_eq!(decoder.get_used_bytes(), data.len());
+}
 
-    assert_eq!(result, expected);
+fn shaped_roundtrip(data: &[u8], weights: &[Vec<u32>]) -> (Vec<u16>, Vec<u8>) {
+    let models: Vec<ShapedModel> = weights.iter().map(|w| ShapedModel::new(w)).collect();
+    let mut decoder = Decoder::new(data).into_shaped();
+    let digits: Vec<u16> = models
+        .iter()
+        .map(|model| decoder.decode_weighted(model))
+        .collect();
+
+    let mut encoder = Encoder::new_shaped();
+    for (digit, model) in digits.iter().zip(&models) {
+        encoder.add_weighted_value(*digit, model);
+    }
+
+    (digits, encoder.finish())
 }
 
 #[test]
-fn encode_mixed_medium() {
-    let values = vec![3, 6, 10, 100, 57, 42, 13, 20];
-    let max_values = vec![10, 7, 53, 256, 2133, 100, 15, 256];
-    let expected = vec![57, 191, 128, 100, 22, 191];
+fn shaped_roundtrip_empty() {
+    let weights = vec![vec![5, 1, 1]; 4];
 
-    let result = encode(&values, &max_values);
+    let (_, result) = shaped_roundtrip(&[], &weights);
 
-    assert_eq!(result, expected);
+    assert_eq!(result, Vec::<u8>::new());
 }
 
 #[test]
-fn decode_mixed_medium() {
-    let data = vec![57, 191, 128, 100, 22, 191];
-    let max_values = vec![10, 7, 53, 256, 2133, 100, 15, 256];
-    let expected = vec![3, 6, 10, 100, 57, 42, 13, 20];
+fn shaped_roundtrip_leading_zeros() {
+    let data = vec![0, 0, 7, 0];
+    let weights = vec![vec![10, 3, 2, 1]; 64];
 
-    let result = decode(&data, &max_values);
+    let (_, result) = shaped_roundtrip(&data, &weights);
 
-    assert_eq!(result, expected);
+    assert_eq!(result, data);
 }
 
 #[test]
-fn encode_mixed_long() {
-    let values = vec![
-        709, 337, 145, 429, 945, 234, 267, 218, 259, 449, 596, 795, 377, 979, 407, 205, 769, 224,
-        760, 545, 993, 467, 439, 55, 331, 767, 316, 463, 860, 56, 355, 679, 365, 725, 959, 444,
-        669, 999, 549, 990, 738, 560, 951, 397, 748, 0, 125, 166, 214, 445,
-    ];
-    let max_values = vec![
-        1366, 1160, 4796, 7814, 4199, 9361, 7434, 7265, 5424, 945, 7381, 2125, 8772, 3415, 8975,
-        7452, 7106, 2832, 3473, 4004, 1300, 4373, 6373, 9408, 5104, 7354, 4471, 1195, 8586, 9304,
-        8251, 3802, 5539, 8627, 7941, 1512, 7303, 5146, 559, 5481, 6868, 5058, 9318, 3976, 5698,
-        696, 3358, 3568, 344, 9533,
-    ];
-    let expected = vec![
-        36, 172, 175, 113, 189, 145, 9, 221, 117, 234, 135, 161, 96, 242, 83, 213, 47, 73, 89, 33,
-        113, 156, 242, 11, 182, 213, 213, 163, 96, 68, 216, 33, 29, 203, 152, 232, 105, 183, 83,
-        187, 127, 142, 72, 237, 197, 50, 8, 68, 54, 167, 36, 80, 49, 117, 124, 168, 108, 225, 241,
-        151, 196, 243, 3, 206, 112, 198, 171, 137, 245, 252, 232, 20, 63, 70, 206,
-    ];
+fn shaped_roundtrip_mixed() {
+    let data = pseudo_random_data(300, 0x9e37_79b9);
+    let weights: Vec<Vec<u32>> = (0..4000)
+        .map(|i| match i % 4 {
+            0 => vec![1; 2 + i % 7],
+            1 => vec![6284, 2156, 667, 339, 162],
+            2 => vec![1, 1000],
+            _ => (1..=(1 + i % 40) as u32).rev().collect(),
+        })
+        .collect();
 
-    let result = encode(&values, &max_values);
+    let (_, result) = shaped_roundtrip(&data, &weights);
 
-    assert_eq!(result, expected);
+    assert_eq!(result, data);
 }
 
 #[test]
-fn decode_mixed_long() {
-    let data = vec![
-        36, 172, 175, 113, 189, 145, 9, 221, 117, 234, 135, 161, 96, 242, 83, 213, 47, 73, 89, 33,
-        113, 156, 242, 11, 182, 213, 213, 163, 96, 68, 216, 33, 29, 203, 152, 232, 105, 183, 83,
-        187, 127, 142, 72, 237, 197, 50, 8, 68, 54, 167, 36, 80, 49, 117, 124, 168, 108, 225, 241,
-        151, 196, 243, 3, 206, 112, 198, 171, 137, 245, 252, 232, 20, 63, 70, 206,
-    ];
-    let max_values = vec![
-        1366, 1160, 4796, 7814, 4199, 9361, 7434, 7265, 5424, 945, 7381, 2125, 8772, 3415, 8975,
-        7452, 7106, 2832, 3473, 4004, 1300, 4373, 6373, 9408, 5104, 7354, 4471, 1195, 8586, 9304,
-        8251, 3802, 5539, 8627, 7941, 1512, 7303, 5146, 559, 5481, 6868, 5058, 9318, 3976, 5698,
-        696, 3358, 3568, 344, 9533,
-    ];
-    let expected = vec![
-        709, 337, 145, 429, 945, 234, 267, 218, 259, 449, 596, 795, 377, 979, 407, 205, 769, 224,
-        760, 545, 993, 467, 439, 55, 331, 767, 316, 463, 860, 56, 355, 679, 365, 725, 959, 444,
-        669, 999, 549, 990, 738, 560, 951, 397, 748, 0, 125, 166, 214, 445,
-    ];
+fn shaped_digits_follow_weights() {
+    let data = pseudo_random_data(2000, 0x9e37_79b9);
+    let model = ShapedModel::new(&[3, 1]);
 
-    let result = decode(&data, &max_values);
+    let mut decoder = Decoder::new(&data).into_shaped();
+    let zeros = (0..8000)
+        .filter(|_| decoder.decode_weighted(&model) == 0)
+        .count();
 
-    assert_eq!(result, expected);
+    assert!((5700..6300).contains(&zeros), "{} zeros", zeros);
+    assert!(decoder.get_shaping_cost_bits() > 0.15 * 8000.0);
 }
@@ -0,0 +1,35 @@
+use crate::candidate_policy::CandidatePolicy;
+use crate::constants::{MATCH_LENGTH_OFFSET, MF_LIMIT};
+use crate::occurrence_map::OccurrenceMap;
+
+#[test]
+fn peek_keeps_candidates() {
+    let mut data: Vec<u8> = (0..70_000).map(|i| (i * 7 % 251) as u8).collect();
+    data[..5].copy_from_slice(b"aaaaa");
+    data[65_535..65_540].copy_from_slice(b"aaaaa");
+    let mut occur = OccurrenceMap::new(&data, CandidatePolicy::Any);
+
+    occur.index_until(65_535);
+    occur.index_until(65_537);
+    // The occurrence at 0 is too far from 65536, but not from 65535
+    assert_eq!(occur.peek_occurrences(65_536).len(), 2);
+    assert_eq!(occur.get_occurrences(65_535).len(), 2);
+}
+
+#[test]
+fn no_candidates_near_end() {
+    let data = b"abcdabcdabcdabcdabcd";
+    for candidate_policy in [CandidatePolicy::Longest, CandidatePolicy::Any] {
+        let mut occur = OccurrenceMap::new(data, candidate_policy);
+        for index in 4..data.len() - 4 {
+            occur.index_until(index);
+            let occurrences = occur.get_occurrences(index);
+            if index + MF_LIMIT > data.len() {
+                assert!(occurrences.is_empty());
+            } else {
+                assert!(!occurrences.is_empty());
+                assert!(occurrences.max_match_length() >= MATCH_LENGTH_OFFSET as usize);
+            }
+        }
+    }
+}
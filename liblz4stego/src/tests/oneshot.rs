@@ -0,0 +1,91 @@
+use crate::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use crate::candidate_policy::CandidatePolicy;
+use crate::errors::Lz4DecompressErrorKind;
+use crate::tests::support::{decode_lz4, get_text_data};
+use crate::{compress, decompress, decompress_into, HiddenDataMode};
+
+#[test]
+fn test_oneshot_roundtrip() {
+    let data = get_text_data(24);
+    let hidden_data = b"one-shot hidden data";
+    for (options, mode) in [
+        (CompressorBuilder::new(), HiddenDataMode::Default),
+        (
+            CompressorBuilder::new().candidate_policy(CandidatePolicy::Any),
+            HiddenDataMode::PreferHidden,
+        ),
+        (
+            CompressorBuilder::new().mode(ParseMode::Reference(1)),
+            HiddenDataMode::Reference,
+        ),
+    ] {
+        let (result, report) = compress(&data, hidden_data, &options).unwrap();
+        let (output, extracted) = decompress(&result, mode).unwrap();
+
+        assert_eq!(report.compressed_size, result.len() as u64);
+        assert_eq!(output, data);
+        assert_eq!(extracted, hidden_data);
+    }
+}
+
+#[test]
+fn test_oneshot_invalid_options() {
+    let options = CompressorBuilder::new().mode(ParseMode::Fast(1));
+
+    assert!(compress(b"data", b"hidden", &options).is_err());
+}
+
+#[test]
+fn test_decompress_into() {
+    let data = get_text_data(24);
+    let options = CompressorBuilder::new()
+        .block_size(BlockMaxSize::Max64KB)
+        .block_independent(false)
+        .block_checksum(true);
+    let (result, _) = compress(&data, b"hidden", &options).unwrap();
+    let (plain, _) = compress(
+        &data,
+        b"",
+        &CompressorBuilder::new().mode(ParseMode::Fast(1)),
+    )
+    .unwrap();
+
+    for compressed in [result, plain] {
+        let mut output = vec![0; data.len() + 10];
+        let size = decompress_into(&compressed, &mut output).unwrap();
+
+        assert_eq!(output[..size], data);
+        assert_eq!(decode_lz4(&compressed), data);
+    }
+}
+
+#[test]
+fn test_decompress_into_errors() {
+    let data = get_text_data(24);
+    let (result, _) = compress(&data, b"", &CompressorBuilder::new()).unwrap();
+    let mut output = vec![0; data.len()];
+
+    assert_eq!(decompress_into(&result, &mut output).unwrap(), data.len());
+    let error = decompress_into(&result, &mut output[..data.len() - 1]).unwrap_err();
+    assert_eq!(error.get_kind(), Lz4DecompressErrorKind::BufferTooSmall);
+    let error = decompress_into(&result[..result.len() - 10], &mut output).unwrap_err();
+    assert_eq!(error.get_kind(), Lz4DecompressErrorKind::InvalidData);
+
+    let mut corrupted = result.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 1;
+    assert!(decompress_into(&corrupted, &mut output).is_err());
+}
+
+#[test]
+fn test_decompress_into_long_match() {
+    // A few bytes of matches expanding far beyond the output
+    let data = vec![0; 4 * 1024 * 1024];
+    let options = CompressorBuilder::new().mode(ParseMode::Fast(1));
+    let (result, _) = compress(&data, b"", &options).unwrap();
+    assert!(result.len() < data.len() / 100);
+
+    let mut output = [0; 64];
+    let error = decompress_into(&result, &mut output).unwrap_err();
+    assert_eq!(error.get_kind(), Lz4DecompressErrorKind::BufferTooSmall);
+}
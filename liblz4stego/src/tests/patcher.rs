@@ -0,0 +1,115 @@
+use std::io::{Cursor, Read};
+
+use crate::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use crate::decompressor::Decompressor;
+use crate::offset_distribution::OffsetDistribution;
+use crate::patcher::patch_hidden_data;
+use crate::tests::support::get_text_data;
+
+fn compress(
+    data: &[u8],
+    hidden_data: &[u8],
+    offset_distribution: Option<OffsetDistribution>,
+) -> Vec<u8> {
+    let mut options = CompressorBuilder::new();
+    if let Some(offset_distribution) = offset_distribution {
+        options = options.offset_distribution(offset_distribution);
+    }
+
+    crate::compress(data, hidden_data, &options).unwrap().0
+}
+
+fn extract(data: &[u8]) -> Vec<u8> {
+    let mut decompressor = Decompressor::new(data, false);
+    std::io::copy(&mut decompressor, &mut std::io::sink()).unwrap();
+
+    decompressor.finish()
+}
+
+fn test_patch(offset_distribution: Option<OffsetDistribution>) {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"the first secret";
+    let new_hidden_data = b"a replacement for the first one";
+    let compressed = compress(data, hidden_data, offset_distribution);
+
+    let mut file = Cursor::new(compressed);
+    let available_bytes = patch_hidden_data(&mut file, new_hidden_data).unwrap();
+    assert!(available_bytes >= new_hidden_data.len());
+
+    let patched = file.into_inner();
+    assert_eq!(
+        patched,
+        compress(data, new_hidden_data, offset_distribution)
+    );
+    assert_eq!(extract(&patched), new_hidden_data);
+
+    let mut decompressed = Vec::new();
+    Decompressor::new_without_hidden_data(patched.as_slice())
+        .read_to_end(&mut decompressed)
+        .unwrap();
+    assert_eq!(decompressed, data);
+}
+
+#[test]
+fn test_patch_uniform() {
+    test_patch(None);
+}
+
+#[test]
+fn test_patch_offset_distribution() {
+    test_patch(Some(OffsetDistribution::reference()));
+}
+
+#[test]
+fn test_patch_headerless() {
+    // Written by the default mode before it stored a header
+    let mut file = Cursor::new(include_bytes!("test_data/old_default.lz4").to_vec());
+    let available_bytes = patch_hidden_data(&mut file, b"patched in the old coding").unwrap();
+    assert!(available_bytes >= 25);
+
+    assert_eq!(extract(file.get_ref()), b"patched in the old coding");
+}
+
+#[test]
+fn test_patch_unsupported_channel() {
+    let data = include_bytes!("test_data/text");
+    let options = CompressorBuilder::new().match_truncation(2);
+    let (result, _) = crate::compress(data, b"secret", &options).unwrap();
+
+    assert!(patch_hidden_data(Cursor::new(result), b"other").is_err());
+}
+
+#[test]
+fn test_patch_blocks() {
+    let data = get_text_data(24);
+    let options = CompressorBuilder::new().block_size(BlockMaxSize::Max64KB);
+    let (compressed, _) = crate::compress(&data, b"the first secret", &options).unwrap();
+    let (expected, _) = crate::compress(&data, b"another secret", &options).unwrap();
+
+    let mut file = Cursor::new(compressed);
+    patch_hidden_data(&mut file, b"another secret").unwrap();
+    assert_eq!(file.into_inner(), expected);
+}
+
+#[test]
+fn test_patch_reference_mode() {
+    let data = include_bytes!("test_data/text");
+    let options = CompressorBuilder::new().mode(ParseMode::Reference(1));
+    let (compressed, _) = crate::compress(data, b"secret", &options).unwrap();
+
+    let mut file = Cursor::new(compressed.clone());
+    assert!(patch_hidden_data(&mut file, b"other").is_err());
+    assert_eq!(file.into_inner(), compressed);
+}
+
+#[test]
+fn test_patch_invalid_unchanged() {
+    let data = include_bytes!("test_data/text");
+    let mut compressed = compress(data, b"secret", None);
+    let last = compressed.len() - 1;
+    compressed[last] ^= 1;
+
+    let mut file = Cursor::new(compressed.clone());
+    assert!(patch_hidden_data(&mut file, b"other").is_err());
+    assert_eq!(file.into_inner(), compressed);
+}
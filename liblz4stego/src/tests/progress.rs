@@ -0,0 +1,85 @@
+use std::io::Write;
+use std::ops::ControlFlow;
+use std::sync::{Arc, Mutex};
+
+use crate::builder::{BlockMaxSize, CompressorBuilder};
+use crate::compressor::Progress;
+use crate::tests::support::get_text_data;
+
+#[test]
+fn test_progress_callback() {
+    let data = get_text_data(24);
+    let hidden_data = vec![0x5A; 2000];
+    let progresses = Arc::new(Mutex::new(Vec::new()));
+
+    let mut result = Vec::new();
+    let callback_progresses = progresses.clone();
+    let mut compressor = CompressorBuilder::new()
+        .block_size(BlockMaxSize::Max64KB)
+        .build(&mut result, &hidden_data)
+        .unwrap()
+        .with_progress_callback(move |progress| {
+            callback_progresses.lock().unwrap().push(*progress);
+            ControlFlow::Continue(())
+        });
+    assert_eq!(compressor.get_progress(), Progress::default());
+    compressor.write_all(&data).unwrap();
+    let available_bytes = compressor.finish().unwrap().hidden_bytes;
+
+    let progresses = progresses.lock().unwrap();
+    assert_eq!(progresses.len(), data.len().div_ceil(64 * 1024));
+    for (i, pair) in progresses.windows(2).enumerate() {
+        assert_eq!(pair[1].blocks_written, i + 2);
+        assert!(pair[1].compressed_bytes > pair[0].compressed_bytes);
+        assert!(pair[1].hidden_bits_used >= pair[0].hidden_bits_used);
+        assert!(pair[1].hidden_bits_available > pair[0].hidden_bits_available);
+    }
+
+    let last = progresses.last().unwrap();
+    // The end mark and the content checksum follow the last block
+    assert_eq!(last.compressed_bytes + 8, result.len() as u64);
+    assert_eq!(last.hidden_bits_available, available_bytes as u64 * 8);
+    assert_eq!(
+        last.hidden_bits_used,
+        (hidden_data.len() * 8).min(available_bytes * 8) as u64
+    );
+}
+
+#[test]
+fn test_progress_between_writes() {
+    let data = get_text_data(24);
+    let mut compressor = CompressorBuilder::new()
+        .block_size(BlockMaxSize::Max64KB)
+        .build(std::io::sink(), &[])
+        .unwrap();
+
+    compressor.write_all(&data[..100_000]).unwrap();
+    let progress = compressor.get_progress();
+
+    assert_eq!(progress.blocks_written, 1);
+    assert!(progress.compressed_bytes > 0);
+    assert_eq!(progress.hidden_bits_used, 0);
+    assert!(progress.hidden_bits_available > 0);
+}
+
+#[test]
+fn test_progress_cancel() {
+    let data = get_text_data(24);
+    let mut result = Vec::new();
+    let mut compressor = CompressorBuilder::new()
+        .block_size(BlockMaxSize::Max64KB)
+        .build(&mut result, b"cancelled")
+        .unwrap()
+        .with_progress_callback(|progress| {
+            if progress.blocks_written < 2 {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        });
+
+    assert!(compressor.write_all(&data).is_err());
+    assert_eq!(compressor.get_progress().blocks_written, 2);
+    assert!(compressor.write_all(b"more").is_err());
+    assert!(compressor.finish().is_err());
+}
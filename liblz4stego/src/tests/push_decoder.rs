@@ -0,0 +1,110 @@
+use crate::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use crate::push_decoder::PushDecoder;
+use crate::tests::support::get_text_data;
+use crate::{compress, decompress, HiddenDataMode};
+
+fn feed_chunks(data: &[u8], chunk_size: usize, mode: HiddenDataMode) -> (Vec<u8>, Vec<u8>) {
+    let mut decoder = PushDecoder::new(mode);
+    let mut output = Vec::new();
+    let mut hidden_data = Vec::new();
+    for chunk in data.chunks(chunk_size) {
+        let decoded = decoder.feed(chunk).unwrap();
+        output.extend_from_slice(&decoded.output);
+        hidden_data.extend_from_slice(&decoded.hidden_data);
+    }
+    assert!(decoder.is_finished());
+
+    (output, hidden_data)
+}
+
+#[test]
+fn test_push_decoder_chunks() {
+    let data = get_text_data(24);
+    let hidden_data = b"pushed hidden data";
+    for (options, mode) in [
+        (
+            CompressorBuilder::new()
+                .block_size(BlockMaxSize::Max64KB)
+                .block_independent(false)
+                .block_checksum(true)
+                .content_size(data.len() as u64),
+            HiddenDataMode::Default,
+        ),
+        (
+            CompressorBuilder::new().block_size_channel(10_000, 16),
+            HiddenDataMode::Default,
+        ),
+        (
+            CompressorBuilder::new().mode(ParseMode::Trellis(2)),
+            HiddenDataMode::Default,
+        ),
+        (
+            CompressorBuilder::new().mode(ParseMode::Reference(1)),
+            HiddenDataMode::Reference,
+        ),
+    ] {
+        let (result, _) = compress(&data, hidden_data, &options).unwrap();
+        let expected = decompress(&result, mode).unwrap();
+
+        for chunk_size in [1, 7, 4096, result.len()] {
+            assert_eq!(feed_chunks(&result, chunk_size, mode), expected);
+        }
+        assert_eq!(expected, (data.clone(), hidden_data.to_vec()));
+    }
+}
+
+#[test]
+fn test_push_decoder_skippable_frames() {
+    let data = get_text_data(4);
+    let (result, _) = compress(&data, b"hidden", &CompressorBuilder::new()).unwrap();
+    let mut input = vec![0x5F, 0x2A, 0x4D, 0x18];
+    input.extend_from_slice(&300_000u32.to_le_bytes());
+    input.resize(input.len() + 300_000, 0);
+    input.extend_from_slice(&result);
+
+    for chunk_size in [4096, input.len()] {
+        assert_eq!(
+            feed_chunks(&input, chunk_size, HiddenDataMode::Default),
+            (data.clone(), b"hidden".to_vec())
+        );
+    }
+}
+
+#[test]
+fn test_push_decoder_partial() {
+    let data = get_text_data(24);
+    let options = CompressorBuilder::new().block_size(BlockMaxSize::Max64KB);
+    let (result, _) = compress(&data, b"hidden", &options).unwrap();
+    let mut decoder = PushDecoder::new(HiddenDataMode::Default);
+
+    let decoded = decoder.feed(&result[..result.len() / 2]).unwrap();
+    assert!(!decoded.output.is_empty());
+    assert!(decoded.output.len() < data.len());
+    assert!(!decoded.finished);
+
+    let rest = decoder.feed(&result[result.len() / 2..]).unwrap();
+    assert!(rest.finished);
+    assert_eq!([decoded.output, rest.output].concat(), data);
+    assert_eq!([decoded.hidden_data, rest.hidden_data].concat(), b"hidden");
+}
+
+#[test]
+fn test_push_decoder_errors() {
+    let data = get_text_data(24);
+    let options = CompressorBuilder::new().content_checksum(true);
+    let (result, _) = compress(&data, b"", &options).unwrap();
+
+    let mut corrupted = result.clone();
+    let last = corrupted.len() - 1;
+    corrupted[last] ^= 1;
+    let mut decoder = PushDecoder::new(HiddenDataMode::Default);
+    assert!(decoder.feed(&corrupted).is_err());
+    assert!(decoder.feed(b"").is_err());
+
+    let mut decoder = PushDecoder::new(HiddenDataMode::Default);
+    assert!(decoder.feed(b"not an lz4 frame").is_err());
+
+    let mut decoder = PushDecoder::new(HiddenDataMode::Default);
+    let decoded = decoder.feed(&result[..result.len() - 1]).unwrap();
+    assert!(!decoded.finished);
+}
@@ -0,0 +1,88 @@
+use std::io::{Read, Write};
+
+use crate::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use crate::decompressor::Decompressor;
+use crate::report::{BlockReport, Report};
+use crate::tests::support::get_text_data;
+
+fn compress(builder: &CompressorBuilder, data: &[u8], hidden_data: &[u8]) -> (Vec<u8>, Report) {
+    let mut result = Vec::new();
+    let mut compressor = builder.build(&mut result, hidden_data).unwrap();
+    compressor.write_all(data).unwrap();
+    let report = compressor.finish().unwrap();
+
+    (result, report)
+}
+
+fn decompress(data: &[u8]) -> (Vec<u8>, Report) {
+    let mut decompressor = Decompressor::new(data, false);
+    decompressor.read_to_end(&mut Vec::new()).unwrap();
+
+    decompressor.finish_with_report()
+}
+
+#[test]
+fn test_report_matches_decompressor() {
+    let data = get_text_data(24);
+    let hidden_data = vec![0x3C; 500];
+    for mode in [ParseMode::Default, ParseMode::Trellis(2)] {
+        let builder = CompressorBuilder::new()
+            .mode(mode)
+            .block_size(BlockMaxSize::Max64KB);
+
+        let (result, report) = compress(&builder, &data, &hidden_data);
+        let (extracted, decompressor_report) = decompress(&result);
+
+        assert_eq!(report.uncompressed_size, data.len() as u64);
+        assert_eq!(report.compressed_size, result.len() as u64);
+        assert_eq!(report.block_count, data.len().div_ceil(64 * 1024));
+        assert!(report.ratio > 1.0);
+        let choice_count = report.choice_count.unwrap();
+        assert!(choice_count > 0 && choice_count <= report.match_count);
+        assert_eq!(
+            report.match_count + report.block_count,
+            report.sequence_count
+        );
+        assert_eq!(decompressor_report.hidden_bytes, extracted.len());
+        assert_eq!(
+            Report {
+                hidden_bytes: report.hidden_bytes,
+                ..decompressor_report
+            },
+            report
+        );
+    }
+}
+
+#[test]
+fn test_report_uncompressed_block() {
+    // Too short to be compressed
+    let data = b"abcabcabc".to_vec();
+    let (result, report) = compress(&CompressorBuilder::new(), &data, &[]);
+
+    assert_eq!(
+        report.blocks,
+        vec![BlockReport::new_uncompressed(data.len())]
+    );
+    assert_eq!(report.literal_bytes, data.len());
+    assert_eq!(report.compressed_size, result.len() as u64);
+    assert_eq!(decompress(&result).1, report);
+}
+
+#[test]
+fn test_report_without_extraction() {
+    let data = get_text_data(24);
+    let (result, report) = compress(&CompressorBuilder::new(), &data, b"hidden");
+
+    let mut decompressor = Decompressor::new_without_hidden_data(result.as_slice());
+    decompressor.read_to_end(&mut Vec::new()).unwrap();
+    let (_, decompressor_report) = decompressor.finish_with_report();
+
+    assert!(report.choice_count.unwrap() > 0);
+    assert_eq!(decompressor_report.choice_count, None);
+    assert_eq!(decompressor_report.match_count, report.match_count);
+    assert!(decompressor_report
+        .blocks
+        .iter()
+        .all(|block| block.choice_count.is_none()));
+}
@@ -0,0 +1,82 @@
+use std::io::Write;
+
+use lz4::{BlockMode, BlockSize, ContentChecksum, EncoderBuilder};
+
+use crate::analysis::analyze;
+use crate::builder::{CompressorBuilder, ParseMode};
+use crate::sanitizer::sanitize;
+use crate::tests::support::decode_lz4;
+use crate::{decompress, HiddenDataMode};
+
+fn get_data() -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in 0..20000 {
+        writeln!(data, "entry {} of {}: {}", i % 53, i % 7, i % 89).unwrap();
+    }
+
+    data
+}
+
+#[test]
+fn test_sanitize_hidden() {
+    let data = get_data();
+    let hidden_data: Vec<u8> = (0..5000u32).map(|i| (i * 7919 % 251) as u8).collect();
+    let options = CompressorBuilder::new()
+        .literal_channel(8)
+        .block_checksum(true)
+        .content_checksum(true);
+    let (compressed, _) = crate::compress(&data, &hidden_data, &options).unwrap();
+    // A skippable frame of another tool ahead of the lz4stego one
+    let mut input = vec![0x5F, 0x2A, 0x4D, 0x18, 4, 0, 0, 0];
+    input.extend_from_slice(b"meta");
+    input.extend_from_slice(&compressed);
+
+    let mut sanitized = Vec::new();
+    let changed_count = sanitize(input.as_slice(), &mut sanitized).unwrap();
+    assert!(changed_count > 0);
+    assert_eq!(sanitized.len(), input.len());
+    assert_eq!(sanitized[..12], input[..12]);
+    let (decompressed, _) = decompress(&sanitized, HiddenDataMode::None).unwrap();
+    assert_eq!(decompressed, data);
+
+    let analysis = analyze(sanitized.as_slice()).unwrap();
+    assert!(analysis.stego_header);
+    assert!(analysis.choice_count() > 0);
+    assert_eq!(analysis.non_nearest_count(), 0);
+
+    let mut sanitized_again = Vec::new();
+    assert_eq!(
+        sanitize(sanitized.as_slice(), &mut sanitized_again).unwrap(),
+        0
+    );
+    assert_eq!(sanitized_again, sanitized);
+}
+
+#[test]
+fn test_sanitize_linked_blocks() {
+    let data = get_data();
+    let mut compressed = Vec::new();
+    let mut encoder = EncoderBuilder::new()
+        .block_size(BlockSize::Max64KB)
+        .block_mode(BlockMode::Linked)
+        .checksum(ContentChecksum::ChecksumEnabled)
+        .build(&mut compressed)
+        .unwrap();
+    encoder.write_all(&data).unwrap();
+    encoder.finish().1.unwrap();
+
+    let mut sanitized = Vec::new();
+    sanitize(compressed.as_slice(), &mut sanitized).unwrap();
+    assert_eq!(sanitized.len(), compressed.len());
+    assert_eq!(decode_lz4(&sanitized), data);
+}
+
+#[test]
+fn test_sanitize_invalid_checksum() {
+    let options = CompressorBuilder::new().mode(ParseMode::Fast(1));
+    let (mut compressed, _) = crate::compress(&get_data(), b"", &options).unwrap();
+    let last = compressed.len() - 1;
+    compressed[last] ^= 1;
+
+    assert!(sanitize(compressed.as_slice(), std::io::sink()).is_err());
+}
@@ -1,35 +1,34 @@
-use std::io::{Read, Write};
+use std::io::Write;
 
-use lz4::Decoder;
+use byteorder::{ByteOrder, WriteBytesExt, LE};
 
+use crate::builder::{CompressorBuilder, ParseMode};
+use crate::candidate_policy::CandidatePolicy;
 use crate::compressor::Compressor;
-use crate::decompressor::Decompressor;
 use crate::errors::DecompressResult;
+use crate::offset_distribution::OffsetDistribution;
+use crate::tests::support::{decode_lz4, pseudo_random_data};
+use crate::HiddenDataMode;
 
 fn decompress(data: &[u8]) -> DecompressResult<(Vec<u8>, Vec<u8>)> {
-    let mut output = Vec::new();
-    let mut decompressor = Decompressor::new(data, false);
-    decompressor.read_to_end(&mut output).unwrap();
-
-    Ok((output, decompressor.finish()))
+    crate::decompress(data, HiddenDataMode::Default)
 }
 
-fn decode_lz4(data: &Vec<u8>) -> Vec<u8> {
-    let mut buffer = Vec::new();
-    let mut decoder = Decoder::new(data.as_slice()).unwrap();
-    decoder.read_to_end(&mut buffer).unwrap();
+fn compress(data: &[u8], hidden_data: &[u8], builder: CompressorBuilder) -> (Vec<u8>, usize) {
+    let (result, report) = crate::compress(data, hidden_data, &builder).unwrap();
 
-    buffer
+    (result, report.hidden_bytes)
 }
 
 #[test]
 fn test_single_byte() {
     let data = b"ala a ala b ala c ala d ala e ala f ala g ala h ala i ala j ala k ala l ala";
     let hidden_data = b"ab";
-    let mut result = Vec::new();
-    let mut compressor = Compressor::new_with_hidden_data(&mut result, hidden_data, true).unwrap();
-    compressor.write(data).unwrap();
-    compressor.finish().unwrap();
+    let builder = CompressorBuilder::new().candidate_policy(CandidatePolicy::Any);
+    let (result, available_bytes) = compress(data, hidden_data, builder);
+    // The data offers about 18 bits, but the chunk length takes a byte of
+    // them, so only the first byte fits
+    assert_eq!(available_bytes, 1);
 
     let decoded_expected = decode_lz4(&result);
     let decoded_actual = decompress(&result);
@@ -37,5 +36,526 @@ fn test_single_byte() {
     assert!(decoded_actual.is_ok(), "{}", decoded_actual.unwrap_err());
     let result = decoded_actual.unwrap();
     assert_eq!(result.0, decoded_expected);
-    assert_eq!(result.1, hidden_data);
+    assert_eq!(result.1, b"a");
+}
+
+fn compress_hc(data: &[u8], hidden_data: &[u8], level: usize) -> (Vec<u8>, usize) {
+    compress(
+        data,
+        hidden_data,
+        CompressorBuilder::new().search_depth(level),
+    )
+}
+
+#[test]
+fn test_hc() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"hidden in a lazily parsed stream";
+
+    for level in 1..=3 {
+        let (result, available_bytes) = compress_hc(data, hidden_data, level);
+        assert!(available_bytes >= hidden_data.len());
+
+        let decoded_actual = decompress(&result);
+
+        assert!(decoded_actual.is_ok(), "{}", decoded_actual.unwrap_err());
+        let decoded_actual = decoded_actual.unwrap();
+        assert_eq!(decoded_actual.0, data);
+        assert_eq!(decoded_actual.0, decode_lz4(&result));
+        assert_eq!(decoded_actual.1, hidden_data);
+    }
+}
+
+#[test]
+fn test_hc_large() {
+    // Words of the text in a random order, longer than the 64 KiB window, so
+    // that candidates expire during the lookahead
+    let text = std::str::from_utf8(include_bytes!("test_data/text")).unwrap();
+    let words: Vec<&str> = text.split_whitespace().take(256).collect();
+    let mut data = Vec::new();
+    for byte in pseudo_random_data(14_000, 0x2545_f491) {
+        write!(data, "{} ", words[byte as usize % words.len()]).unwrap();
+    }
+    assert!(data.len() > 64 * 1024);
+    let hidden_data = pseudo_random_data(1024, 0x2545_f491);
+
+    for level in 1..=4 {
+        let (result, available_bytes) = compress_hc(&data, &hidden_data, level);
+        assert!(available_bytes >= hidden_data.len());
+
+        let decoded_actual = decompress(&result).unwrap();
+        assert_eq!(decoded_actual.0, data);
+        assert_eq!(decoded_actual.1, hidden_data);
+    }
+}
+
+#[test]
+fn test_hc_not_larger_than_greedy() {
+    let data = include_bytes!("test_data/text");
+
+    let (greedy, _) = compress_hc(data, b"", 0);
+    let (hc, _) = compress_hc(data, b"", 2);
+
+    assert!(hc.len() <= greedy.len());
+}
+
+fn skip_skippable_frame(data: &[u8]) -> Vec<u8> {
+    let frame_size = LE::read_u32(&data[4..8]) as usize;
+
+    data[8 + frame_size..].to_vec()
+}
+
+fn compress_with_policy(
+    data: &[u8],
+    hidden_data: &[u8],
+    candidate_policy: CandidatePolicy,
+) -> (Vec<u8>, usize) {
+    let builder = CompressorBuilder::new().candidate_policy(candidate_policy);
+
+    compress(data, hidden_data, builder)
+}
+
+#[test]
+fn test_candidate_policy() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"the policy is read from the stream header";
+
+    for candidate_policy in [
+        CandidatePolicy::WithinBytes(2),
+        CandidatePolicy::WithinPercent(25),
+    ] {
+        let (result, available_bytes) = compress_with_policy(data, hidden_data, candidate_policy);
+        assert!(available_bytes >= hidden_data.len());
+
+        let decoded_actual = decompress(&result);
+
+        assert!(decoded_actual.is_ok(), "{}", decoded_actual.unwrap_err());
+        let decoded_actual = decoded_actual.unwrap();
+        assert_eq!(decoded_actual.0, data);
+        assert_eq!(decoded_actual.0, decode_lz4(&skip_skippable_frame(&result)));
+        assert_eq!(decoded_actual.1, hidden_data);
+    }
+}
+
+#[test]
+fn test_unknown_header_field() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"unknown fields are skipped";
+    let (result, _) = compress_with_policy(data, hidden_data, CandidatePolicy::WithinBytes(2));
+
+    let frame_size = LE::read_u32(&result[4..8]);
+    let mut patched = result[..4].to_vec();
+    patched.write_u32::<LE>(frame_size + 4).unwrap();
+    patched.extend_from_slice(&result[8..12]);
+    patched.extend_from_slice(&[0xff, 2, 0xab, 0xcd]);
+    patched.extend_from_slice(&result[12..]);
+
+    let decoded_actual = decompress(&patched).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+}
+
+#[test]
+fn test_header_version() {
+    let data = include_bytes!("test_data/text");
+    let (result, _) = compress_with_policy(data, b"version", CandidatePolicy::WithinBytes(2));
+    // The version field comes right after the tag
+    assert_eq!(result[12..15], [8, 1, 2]);
+
+    let mut newer = result.clone();
+    newer[14] = 3;
+    assert!(decompress(&newer).is_err());
+
+    let frame_size = LE::read_u32(&result[4..8]);
+    let mut older = result[..4].to_vec();
+    older.write_u32::<LE>(frame_size - 3).unwrap();
+    older.extend_from_slice(&result[8..12]);
+    older.extend_from_slice(&result[15..]);
+    assert!(decompress(&older).is_err());
+}
+
+#[test]
+fn test_headerless_files() {
+    // Written by the default and reference modes before the header carried a
+    // version; the streams without one still use the original coding
+    let mut data = Vec::new();
+    for i in 0..1000 {
+        writeln!(data, "entry {} of {}: {}", i % 53, i % 7, i % 89).unwrap();
+    }
+    let hidden_data = b"hidden before the streaming extraction";
+    let old_default = include_bytes!("test_data/old_default.lz4");
+    let old_reference = include_bytes!("test_data/old_reference.lz4");
+
+    let decoded = decompress(old_default).unwrap();
+    assert_eq!(decoded.0, data);
+    assert_eq!(decoded.1, hidden_data);
+    let decoded = crate::decompress(old_reference, HiddenDataMode::Reference).unwrap();
+    assert_eq!(decoded.0, data);
+    assert_eq!(decoded.1, hidden_data);
+
+    let builder = CompressorBuilder::new().mode(ParseMode::Reference(1));
+    assert_eq!(compress(&data, hidden_data, builder).0, old_reference);
+}
+
+#[test]
+fn test_candidate_policy_capacity() {
+    let data = include_bytes!("test_data/text");
+
+    let capacities: Vec<usize> = [
+        CandidatePolicy::Longest,
+        CandidatePolicy::WithinBytes(1),
+        CandidatePolicy::WithinBytes(4),
+        CandidatePolicy::Any,
+    ]
+    .iter()
+    .map(|candidate_policy| compress_with_policy(data, b"", *candidate_policy).1)
+    .collect();
+
+    assert!(capacities.windows(2).all(|pair| pair[0] <= pair[1]));
+}
+
+fn compress_planned(data: &[u8], hidden_data: &[u8]) -> (Vec<u8>, usize) {
+    let builder = CompressorBuilder::new().mode(ParseMode::Planned(data.len()));
+
+    compress(data, hidden_data, builder)
+}
+
+#[test]
+fn test_planned() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"0123456789".repeat(20);
+
+    let (result, available_bytes) = compress_planned(data, &hidden_data);
+    assert!(available_bytes >= hidden_data.len());
+
+    let decoded_actual = decompress(&result);
+
+    assert!(decoded_actual.is_ok(), "{}", decoded_actual.unwrap_err());
+    let decoded_actual = decoded_actual.unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.0, decode_lz4(&skip_skippable_frame(&result)));
+    assert_eq!(decoded_actual.1, hidden_data);
+}
+
+#[test]
+fn test_planned_multiple_blocks() {
+    let text = include_bytes!("test_data/text");
+    let data = text.repeat(400);
+    let hidden_data = b"0123456789".repeat(50);
+
+    let (result, available_bytes) = compress_planned(&data, &hidden_data);
+    assert!(available_bytes >= hidden_data.len());
+
+    let (prefer_hidden_result, _) = compress_with_policy(&data, &hidden_data, CandidatePolicy::Any);
+    assert!(result.len() < prefer_hidden_result.len());
+
+    let decoded_actual = decompress(&result).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+}
+
+#[test]
+fn test_literal_channel() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"some matches are left as literals";
+
+    for candidate_policy in [CandidatePolicy::Longest, CandidatePolicy::Any] {
+        let builder = CompressorBuilder::new()
+            .candidate_policy(candidate_policy)
+            .literal_channel(6);
+        let (result, available_bytes) = compress(data, hidden_data, builder);
+
+        let (_, available_bytes_without_channel) =
+            compress_with_policy(data, hidden_data, candidate_policy);
+        assert!(available_bytes > available_bytes_without_channel);
+
+        let decoded_actual = decompress(&result);
+
+        assert!(decoded_actual.is_ok(), "{}", decoded_actual.unwrap_err());
+        let decoded_actual = decoded_actual.unwrap();
+        assert_eq!(decoded_actual.0, data);
+        assert_eq!(decoded_actual.0, decode_lz4(&skip_skippable_frame(&result)));
+        assert_eq!(decoded_actual.1, hidden_data);
+    }
+}
+
+#[test]
+fn test_literal_channel_planned() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"0123456789".repeat(20);
+
+    let builder = CompressorBuilder::new()
+        .mode(ParseMode::Planned(data.len()))
+        .literal_channel(5);
+    let (result, available_bytes) = compress(data, &hidden_data, builder);
+    assert!(available_bytes >= hidden_data.len());
+
+    let decoded_actual = decompress(&result).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+}
+
+#[test]
+fn test_match_truncation() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"match lengths are shorter than they could be";
+
+    for candidate_policy in [CandidatePolicy::Longest, CandidatePolicy::Any] {
+        let builder = CompressorBuilder::new()
+            .candidate_policy(candidate_policy)
+            .match_truncation(8);
+        let (result, available_bytes) = compress(data, hidden_data, builder);
+
+        let (_, available_bytes_without_channel) =
+            compress_with_policy(data, hidden_data, candidate_policy);
+        assert!(available_bytes > available_bytes_without_channel);
+
+        let decoded_actual = decompress(&result);
+
+        assert!(decoded_actual.is_ok(), "{}", decoded_actual.unwrap_err());
+        let decoded_actual = decoded_actual.unwrap();
+        assert_eq!(decoded_actual.0, data);
+        assert_eq!(decoded_actual.0, decode_lz4(&skip_skippable_frame(&result)));
+        assert_eq!(decoded_actual.1, hidden_data);
+    }
+}
+
+#[test]
+fn test_match_truncation_hc() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"lazy matching with truncated matches";
+
+    let builder = CompressorBuilder::new().search_depth(2).match_truncation(3);
+    let (result, _) = compress(data, hidden_data, builder);
+
+    let decoded_actual = decompress(&result).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+}
+
+#[test]
+fn test_block_size_channel() {
+    let data = pseudo_random_data(64 * 1024, 0x2545_f491);
+    let hidden_data = b"hidden in the block boundaries of random data";
+
+    let builder = CompressorBuilder::new().block_size_channel(1024, 256);
+    let (result, available_bytes) = compress(&data, hidden_data, builder);
+    assert!(available_bytes >= hidden_data.len());
+
+    let decoded_actual = decompress(&result).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+
+    let decoded_lz4 = decode_lz4(&skip_skippable_frame(&result));
+    assert_eq!(decoded_lz4, data);
+}
+
+#[test]
+fn test_block_size_channel_with_matches() {
+    let data = include_bytes!("test_data/text").repeat(4);
+    let hidden_data = b"block sizes and offsets together";
+
+    let mut result = Vec::new();
+    let mut compressor = Compressor::new_with_hidden_data(&mut result, hidden_data, false)
+        .unwrap()
+        .with_block_size_channel(4096, 1024)
+        .unwrap();
+    compressor.write_all(&data[..1000]).unwrap();
+    compressor.write_all(&data[1000..]).unwrap();
+    compressor.finish().unwrap();
+
+    let decoded_actual = decompress(&result).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+
+    let decoded_lz4 = decode_lz4(&skip_skippable_frame(&result));
+    assert_eq!(decoded_lz4, data);
+}
+
+#[test]
+fn test_block_size_channel_flush() {
+    let data = include_bytes!("test_data/text").repeat(6);
+    let hidden_data = b"flushed between blocks";
+
+    let mut result = Vec::new();
+    let mut compressor = Compressor::new_with_hidden_data(&mut result, hidden_data, false)
+        .unwrap()
+        .with_block_size_channel(20_000, 16)
+        .unwrap();
+    compressor.write_all(&data[..30_000]).unwrap();
+    compressor.flush().unwrap();
+    compressor.write_all(&data[30_000..]).unwrap();
+    compressor.finish().unwrap();
+
+    let decoded_actual = decompress(&result).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+}
+
+#[test]
+fn test_offset_distribution() {
+    let data = include_bytes!("test_data/text").repeat(4);
+    let hidden_data = b"offsets shaped like the ones of the reference lz4";
+
+    let mut result = Vec::new();
+    let mut compressor = Compressor::new_with_hidden_data(&mut result, hidden_data, false)
+        .unwrap()
+        .with_offset_distribution(OffsetDistribution::reference())
+        .unwrap();
+    compressor.write_all(&data).unwrap();
+    let (report, shaping_cost_bytes) = compressor.finish_with_shaping_cost().unwrap();
+    assert!(report.hidden_bytes >= hidden_data.len());
+    assert!(shaping_cost_bytes > 0);
+
+    let decoded_actual = decompress(&result).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+
+    let decoded_lz4 = decode_lz4(&skip_skippable_frame(&result));
+    assert_eq!(decoded_lz4, data);
+}
+
+#[test]
+fn test_offset_distribution_capacity_cost() {
+    let data = include_bytes!("test_data/text").repeat(4);
+
+    let (_, uniform_bytes) = compress(&data, b"", CompressorBuilder::new());
+
+    let mut shaped_compressor = Compressor::new_with_hidden_data(Vec::new(), b"", false)
+        .unwrap()
+        .with_offset_distribution(OffsetDistribution::reference())
+        .unwrap();
+    shaped_compressor.write_all(&data).unwrap();
+    let (shaped_report, shaping_cost_bytes) = shaped_compressor.finish_with_shaping_cost().unwrap();
+    let shaped_bytes = shaped_report.hidden_bytes;
+
+    assert!(shaped_bytes < uniform_bytes);
+    assert!(shaped_bytes + shaping_cost_bytes + 1 >= uniform_bytes);
+
+    let distribution = OffsetDistribution::reference();
+    assert_eq!(distribution.capacity_cost(1), 0.0);
+    assert!(distribution.capacity_cost(2) > 0.0);
+    assert!(distribution.bits_per_choice(8) < 3.0);
+}
+
+#[test]
+fn test_offset_distribution_with_channels() {
+    let data = include_bytes!("test_data/text").repeat(2);
+    let hidden_data = b"shaped offsets, truncation and block sizes";
+
+    let builder = CompressorBuilder::new()
+        .candidate_policy(CandidatePolicy::Any)
+        .match_truncation(2)
+        .block_size_channel(8192, 512)
+        .offset_distribution(OffsetDistribution::from_weights(&[8, 4, 2, 1]));
+    let (result, _) = compress(&data, hidden_data, builder);
+
+    let decoded_actual = crate::decompress(&result, HiddenDataMode::PreferHidden).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+}
+
+/// Positions of the offset fields in a frame without a stego header
+fn offset_positions(frame: &[u8]) -> Vec<usize> {
+    let mut positions = Vec::new();
+    let mut i = 7;
+    loop {
+        let block_size = LE::read_u32(&frame[i..]);
+        i += 4;
+        let end = i + (block_size & 0x7FFF_FFFF) as usize;
+        if block_size == 0 {
+            return positions;
+        }
+
+        while block_size & 0x8000_0000 == 0 && i < end {
+            let token = frame[i];
+            i += 1;
+            let mut literals_length = (token >> 4) as usize;
+            if literals_length == 15 {
+                while frame[i] == 255 {
+                    literals_length += 255;
+                    i += 1;
+                }
+                literals_length += frame[i] as usize;
+                i += 1;
+            }
+            i += literals_length;
+            if i == end {
+                break;
+            }
+
+            positions.push(i);
+            i += 2;
+            if token & 0x0F == 15 {
+                while frame[i] == 255 {
+                    i += 1;
+                }
+                i += 1;
+            }
+        }
+        i = end;
+    }
+}
+
+#[test]
+fn test_reference_parse() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = b"mimic";
+
+    let builder = CompressorBuilder::new().mode(ParseMode::Reference(1));
+    let (result, available_bytes) = compress(data, hidden_data, builder);
+    assert!(available_bytes >= hidden_data.len());
+
+    let (plain_result, _) = compress(data, b"", CompressorBuilder::new().mode(ParseMode::Fast(1)));
+
+    // Identical to the plain output except in the offsets
+    assert_eq!(result.len(), plain_result.len());
+    let positions = offset_positions(&result);
+    assert_eq!(positions, offset_positions(&plain_result));
+    let differing: Vec<usize> = (0..result.len())
+        .filter(|i| result[*i] != plain_result[*i])
+        .collect();
+    assert!(!differing.is_empty());
+    assert!(differing
+        .iter()
+        .all(|i| positions.contains(i) || positions.contains(&(i - 1))));
+
+    let decoded_actual = crate::decompress(&result, HiddenDataMode::Reference).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+    assert_eq!(decode_lz4(&result), data);
+}
+
+#[test]
+fn test_trellis_coding() {
+    let data = include_bytes!("test_data/text").repeat(4);
+    let hidden_data = b"trellis";
+
+    let builder = CompressorBuilder::new().mode(ParseMode::Trellis(2));
+    let (result, available_bytes) = compress(&data, hidden_data, builder);
+    assert!(available_bytes >= hidden_data.len());
+
+    let decoded_actual = decompress(&result).unwrap();
+    assert_eq!(decoded_actual.0, data);
+    assert_eq!(decoded_actual.1, hidden_data);
+
+    let decoded_lz4 = decode_lz4(&skip_skippable_frame(&result));
+    assert_eq!(decoded_lz4, data);
+}
+
+#[test]
+fn test_overflow_available_bytes() {
+    let data = include_bytes!("test_data/text");
+    let hidden_data = pseudo_random_data(1000, 0x2545_f491);
+
+    // The decompressor recovers fewer bytes than the bits carried allow for
+    // some lengths, which the compressor has to report
+    for len in (2000..6000).step_by(777) {
+        let (result, available_bytes) =
+            compress(&data[..len], &hidden_data, CompressorBuilder::new());
+
+        let decoded_actual = decompress(&result).unwrap();
+        assert!(available_bytes < hidden_data.len());
+        assert_eq!(decoded_actual.1, hidden_data[..available_bytes]);
+    }
 }
@@ -0,0 +1,49 @@
+//! Helpers shared by the tests.
+
+use std::io::{Read, Write};
+
+/// Next state of a xorshift32 generator; `state` must not be 0.
+pub fn xorshift(state: &mut u32) -> u32 {
+    *state ^= *state << 13;
+    *state ^= *state >> 17;
+    *state ^= *state << 5;
+    *state
+}
+
+/// Reproducible incompressible data.
+pub fn pseudo_random_data(len: usize, mut state: u32) -> Vec<u8> {
+    (0..len).map(|_| xorshift(&mut state) as u8).collect()
+}
+
+/// The sample text repeated `parts` times, each copy followed by its number
+/// so that the copies differ.
+pub fn get_text_data(parts: usize) -> Vec<u8> {
+    let mut data = Vec::new();
+    for i in 0..parts {
+        data.extend_from_slice(include_bytes!("test_data/text"));
+        writeln!(data, "part {}", i).unwrap();
+    }
+
+    data
+}
+
+/// The LZ4 frame of `data`, past the skippable frames before it.
+pub fn get_lz4_frame(mut data: &[u8]) -> &[u8] {
+    while data[0] & 0xF0 == 0x50 && data[1..4] == [0x2A, 0x4D, 0x18] {
+        let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+        data = &data[8 + size..];
+    }
+
+    data
+}
+
+/// Decompresses `data` with the reference LZ4 implementation. The skippable
+/// frames before the LZ4 frame are dropped first, as the `lz4` crate stops
+/// reading after them, unlike the `lz4` tool.
+pub fn decode_lz4(data: &[u8]) -> Vec<u8> {
+    let mut buffer = Vec::new();
+    let mut decoder = lz4::Decoder::new(get_lz4_frame(data)).unwrap();
+    decoder.read_to_end(&mut buffer).unwrap();
+
+    buffer
+}
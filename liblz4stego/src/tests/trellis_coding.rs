@@ -0,0 +1,59 @@
+use crate::hidden_source::HiddenSource;
+use crate::tests::support::xorshift;
+use crate::trellis_coding::{Decoder, Encoder};
+
+fn pseudo_random_costs(len: usize, mut state: u32) -> Vec<f64> {
+    (0..len)
+        .map(|_| 1.0 + (xorshift(&mut state) % 1000) as f64 / 100.0)
+        .collect()
+}
+
+fn roundtrip(data: &[u8], ratio: usize, block_lengths: &[usize]) -> (Vec<u8>, usize) {
+    let mut decoder = Decoder::from_source(HiddenSource::new(data), ratio);
+    let mut encoder = Encoder::new(ratio);
+    let mut flipped = 0;
+
+    for (i, block_length) in block_lengths.iter().enumerate() {
+        let cover = decoder.embed_block(&pseudo_random_costs(*block_length, i as u32 + 1));
+        flipped += cover.iter().filter(|bit| **bit).count();
+        encoder.add_block(&cover);
+    }
+
+    (encoder.finish(), flipped)
+}
+
+#[test]
+fn trellis_roundtrip_empty() {
+    let (result, _) = roundtrip(b"", 2, &[100]);
+
+    assert_eq!(result, b"");
+}
+
+#[test]
+fn trellis_roundtrip_blocks() {
+    let data = b"hidden in the parities of the candidate ranks";
+    let (result, _) = roundtrip(data, 3, &[50, 0, 7, 400, 1000, 300]);
+
+    assert_eq!(result, data);
+}
+
+#[test]
+fn trellis_roundtrip_truncated() {
+    let data = b"more data than the cover can carry";
+    let (result, _) = roundtrip(data, 4, &[400]);
+
+    assert_eq!(result, &data[..result.len()]);
+    assert_eq!(result.len(), (400 / 4 - 32) / 8);
+}
+
+#[test]
+fn trellis_flips_fewer_with_higher_ratio() {
+    let data = b"0123456789abcdef";
+    let (_, flipped_dense) = roundtrip(data, 1, &[2000]);
+    let (result, flipped_sparse) = roundtrip(data, 8, &[2000]);
+
+    assert_eq!(result, data);
+    // Without coding, half of the message bits would need a flip
+    assert!(flipped_sparse < (32 + data.len() * 8) / 4);
+    assert!(flipped_sparse < flipped_dense);
+}
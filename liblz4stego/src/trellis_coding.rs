@@ -0,0 +1,295 @@
+//! Syndrome-trellis coding of the hidden data. Each block provides a sequence
+//! of cover bits, the parities of the recency ranks of the chosen candidates,
+//! which are 0 for the natural choices. The hidden bits are the syndrome of the
+//! cover bits under a parity-check matrix built from a short fixed submatrix,
+//! and the compressor picks the cover bits with the Viterbi algorithm so that
+//! the total cost of the flipped ones is the lowest possible.
+
+use std::collections::VecDeque;
+
+use byteorder::{ByteOrder, LE};
+
+use crate::hidden_source::HiddenSource;
+
+/// Constraint height of the code; every column spans this many message bits
+pub const HEIGHT: u32 = 7;
+const STATE_COUNT: usize = 1 << HEIGHT;
+/// The data is preceded by its length, so that the extraction knows where to
+/// stop
+const LENGTH_BITS: usize = 32;
+
+/// Column of the submatrix; the first and last bit are always set, which keeps
+/// every message bit reachable.
+fn get_column(column_index: usize) -> u8 {
+    let mut x = (column_index as u32).wrapping_mul(0x9E37_79B9) ^ 0x5BD1_E995;
+    for _ in 0..3 {
+        x ^= x << 13;
+        x ^= x >> 17;
+        x ^= x << 5;
+    }
+
+    (x as u8 & (STATE_COUNT as u8 - 1)) | 1 | (1 << (HEIGHT - 1))
+}
+
+/// Number of message bits carried by `cover_len` cover bits, given the number
+/// of message bits still expected, if known.
+fn get_message_len(cover_len: usize, ratio: usize, remaining: Option<usize>) -> usize {
+    match remaining {
+        Some(0) => 0,
+        _ => cover_len / ratio,
+    }
+}
+
+/// Columns of the parity-check matrix for message bit `row`, out of
+/// `message_len`, with the bits past the last row cleared.
+fn get_row_columns(
+    row: usize,
+    message_len: usize,
+    cover_len: usize,
+) -> impl Iterator<Item = (usize, u8)> {
+    let start = row * cover_len / message_len;
+    let end = (row + 1) * cover_len / message_len;
+    let rows_left = message_len - row;
+    let mask = if rows_left < HEIGHT as usize {
+        (1u8 << rows_left) - 1
+    } else {
+        u8::MAX
+    };
+
+    (start..end).map(move |i| (i, get_column(i - start) & mask))
+}
+
+/// Turns the hidden data into cover bits, like `numeral_coding::Decoder` does
+/// into digits.
+#[derive(Clone)]
+pub struct Decoder<'a> {
+    source: HiddenSource<'a>,
+    data_len: usize,
+    length: [u8; 4],
+    position: usize,
+    ratio: usize,
+    available_bits: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// `ratio` is the number of cover bits per hidden bit; higher values flip
+    /// fewer of them. The length of the source has to be known, as it
+    /// precedes the data.
+    pub fn from_source(source: HiddenSource<'a>, ratio: usize) -> Self {
+        let data_len = source.get_len().expect("Hidden data length is unknown");
+        let mut length = [0; 4];
+        LE::write_u32(&mut length, data_len as u32);
+
+        Self {
+            source,
+            data_len,
+            length,
+            position: 0,
+            ratio,
+            available_bits: 0,
+        }
+    }
+
+    fn get_total_bits(&self) -> usize {
+        LENGTH_BITS + self.data_len * 8
+    }
+
+    /// Hidden bit at `position`; bits past the end of the data can take any
+    /// value.
+    fn get_bit(&self, position: usize) -> Option<bool> {
+        let byte = if position < LENGTH_BITS {
+            self.length[position / 8]
+        } else {
+            self.source.get_byte((position - LENGTH_BITS) / 8)?
+        };
+
+        Some(byte >> (position % 8) & 1 == 1)
+    }
+
+    /// Chooses the cover bits of a block, where flipping bit `i` from 0 costs
+    /// `costs[i]`.
+    pub fn embed_block(&mut self, costs: &[f64]) -> Vec<bool> {
+        let cover_len = costs.len();
+        let remaining = self.get_total_bits().saturating_sub(self.position);
+        let message_len = get_message_len(cover_len, self.ratio, Some(remaining));
+        self.available_bits += cover_len / self.ratio;
+        if message_len == 0 {
+            return vec![false; cover_len];
+        }
+
+        // Bit `s` of `path[i]` tells whether cover bit `i` is set on the
+        // cheapest way to state `s`
+        let mut path = vec![0u128; cover_len];
+        // Message bits chosen for the free rows, per state after the row
+        let mut free_bits = vec![0u128; message_len];
+        let mut weights = [f64::INFINITY; STATE_COUNT];
+        weights[0] = 0.0;
+
+        for (row, row_free_bits) in free_bits.iter_mut().enumerate() {
+            for (i, column) in get_row_columns(row, message_len, cover_len) {
+                let mut new_weights = [f64::INFINITY; STATE_COUNT];
+                for (state, new_weight) in new_weights.iter_mut().enumerate() {
+                    let unchanged = weights[state];
+                    let flipped = weights[state ^ column as usize] + costs[i];
+                    if flipped < unchanged {
+                        *new_weight = flipped;
+                        path[i] |= 1 << state;
+                    } else {
+                        *new_weight = unchanged;
+                    }
+                }
+                weights = new_weights;
+            }
+
+            let mut new_weights = [f64::INFINITY; STATE_COUNT];
+            for (state, new_weight) in new_weights.iter_mut().take(STATE_COUNT / 2).enumerate() {
+                *new_weight = match self.get_bit(self.position + row) {
+                    Some(bit) => weights[2 * state + bit as usize],
+                    None if weights[2 * state + 1] < weights[2 * state] => {
+                        *row_free_bits |= 1 << state;
+                        weights[2 * state + 1]
+                    }
+                    None => weights[2 * state],
+                };
+            }
+            weights = new_weights;
+        }
+
+        let mut cover = vec![false; cover_len];
+        let mut state = 0;
+        for row in (0..message_len).rev() {
+            let bit = match self.get_bit(self.position + row) {
+                Some(bit) => bit,
+                None => free_bits[row] >> state & 1 == 1,
+            };
+            state = (state << 1) | bit as usize;
+
+            let columns: Vec<(usize, u8)> = get_row_columns(row, message_len, cover_len).collect();
+            for (i, column) in columns.into_iter().rev() {
+                if path[i] >> state & 1 == 1 {
+                    cover[i] = true;
+                    state ^= column as usize;
+                }
+            }
+        }
+        debug_assert_eq!(state, 0);
+
+        self.position += message_len;
+        cover
+    }
+
+    pub fn get_available_bits(&self) -> usize {
+        self.available_bits
+    }
+
+    pub fn get_available_bytes(&self) -> usize {
+        self.available_bits.saturating_sub(LENGTH_BITS) / 8
+    }
+
+    /// Number of whole bytes of the data embedded so far.
+    pub fn get_consumed_bytes(&self) -> usize {
+        self.get_used_bits() / 8
+    }
+
+    /// Number of bits of the data embedded so far.
+    pub fn get_used_bits(&self) -> usize {
+        self.position
+            .saturating_sub(LENGTH_BITS)
+            .min(self.data_len * 8)
+    }
+}
+
+/// Recovers the hidden data from the cover bits, like
+/// `numeral_coding::Encoder` does from digits.
+pub struct Encoder {
+    bit_count: usize,
+    /// The length preceding the data, once known
+    length: u32,
+    current_byte: u8,
+    /// Hidden data recovered so far and not read yet
+    output: VecDeque<u8>,
+    ratio: usize,
+    available_bits: usize,
+}
+
+impl Encoder {
+    pub fn new(ratio: usize) -> Self {
+        Self {
+            bit_count: 0,
+            length: 0,
+            current_byte: 0,
+            output: VecDeque::new(),
+            ratio,
+            available_bits: 0,
+        }
+    }
+
+    fn get_total_bits(&self) -> Option<usize> {
+        if self.bit_count < LENGTH_BITS {
+            return None;
+        }
+
+        Some(LENGTH_BITS + self.length as usize * 8)
+    }
+
+    fn push_bit(&mut self, bit: bool) {
+        if self.bit_count < LENGTH_BITS {
+            self.length |= (bit as u32) << self.bit_count;
+        } else if self.bit_count < self.get_total_bits().unwrap() {
+            let bit_index = (self.bit_count - LENGTH_BITS) % 8;
+            self.current_byte |= (bit as u8) << bit_index;
+            if bit_index == 7 {
+                self.output
+                    .push_back(std::mem::take(&mut self.current_byte));
+            }
+        }
+        self.bit_count += 1;
+    }
+
+    pub fn add_block(&mut self, cover: &[bool]) {
+        let remaining = self
+            .get_total_bits()
+            .map(|total_bits| total_bits.saturating_sub(self.bit_count));
+        let message_len = get_message_len(cover.len(), self.ratio, remaining);
+        self.available_bits += cover.len() / self.ratio;
+
+        let mut state = 0;
+        for row in 0..message_len {
+            for (i, column) in get_row_columns(row, message_len, cover.len()) {
+                if cover[i] {
+                    state ^= column;
+                }
+            }
+            self.push_bit(state & 1 == 1);
+            state >>= 1;
+        }
+    }
+
+    /// Bits the cover bits added so far carry, like
+    /// `Decoder::get_available_bits`.
+    pub fn get_available_bits(&self) -> usize {
+        self.available_bits
+    }
+
+    /// Whether the whole hidden data was recovered.
+    pub fn is_complete(&self) -> bool {
+        self.get_total_bits()
+            .is_some_and(|total_bits| self.bit_count >= total_bits)
+    }
+
+    /// Moves the hidden data recovered so far to `buf`, returning the number
+    /// of bytes moved.
+    pub fn read(&mut self, buf: &mut [u8]) -> usize {
+        let count = buf.len().min(self.output.len());
+        for (target, byte) in buf.iter_mut().zip(self.output.drain(..count)) {
+            *target = byte;
+        }
+
+        count
+    }
+
+    /// Returns the hidden data not read yet.
+    pub fn finish(self) -> Vec<u8> {
+        self.output.into()
+    }
+}
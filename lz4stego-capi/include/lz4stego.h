@@ -0,0 +1,251 @@
+/*
+ * C API of liblz4stego, a bit recycling steganography for the LZ4
+ * compression algorithm.
+ *
+ * Every fallible function returns LZ4STEGO_OK or one of the negative
+ * LZ4STEGO_ERROR_* codes; lz4stego_status_message describes them. The
+ * handles are opaque and have to be released with their *_free function,
+ * except for a compressor passed to lz4stego_compressor_finish. A handle
+ * must not be used from two threads at once.
+ */
+
+#ifndef LZ4STEGO_H
+#define LZ4STEGO_H
+
+/* Generated by cbindgen from src/lib.rs; do not edit. */
+
+#include <stddef.h>
+#include <stdint.h>
+
+/**
+ * Version of the ABI this header describes, increased on every incompatible
+ * change; compare it to lz4stego_abi_version to detect a mismatched library
+ */
+#define LZ4STEGO_ABI_VERSION 1
+
+#define LZ4STEGO_OK 0
+
+#define LZ4STEGO_ERROR_NULL_POINTER -1
+
+/**
+ * An argument is out of its range, or the data written does not match the
+ * declared content size
+ */
+#define LZ4STEGO_ERROR_INVALID_ARGUMENT -2
+
+/**
+ * The options cannot be used together
+ */
+#define LZ4STEGO_ERROR_INVALID_OPTIONS -3
+
+/**
+ * A write callback returned a non-zero value
+ */
+#define LZ4STEGO_ERROR_IO -4
+
+/**
+ * The compressed data is invalid or truncated
+ */
+#define LZ4STEGO_ERROR_INVALID_DATA -5
+
+#define LZ4STEGO_ERROR_BUFFER_TOO_SMALL -6
+
+/**
+ * A bug in the library. The handle the call was made on is poisoned: every
+ * later call on it fails with this status, and it can only be freed.
+ */
+#define LZ4STEGO_ERROR_PANIC -7
+
+/**
+ * Parse modes of lz4stego_options_set_mode
+ */
+#define LZ4STEGO_MODE_DEFAULT 0
+
+/**
+ * Reference lz4 parse with the given acceleration, without hidden data
+ */
+#define LZ4STEGO_MODE_FAST 1
+
+/**
+ * Reference lz4 mimicry with the given acceleration
+ */
+#define LZ4STEGO_MODE_REFERENCE 2
+
+/**
+ * Syndrome-trellis coding with the given ratio
+ */
+#define LZ4STEGO_MODE_TRELLIS 3
+
+/**
+ * Guaranteed capacity for an input of the given size
+ */
+#define LZ4STEGO_MODE_PLANNED 4
+
+/**
+ * Hidden data modes of lz4stego_decompressor_new; they have to match the
+ * options the data was compressed with
+ */
+#define LZ4STEGO_HIDDEN_DEFAULT 0
+
+#define LZ4STEGO_HIDDEN_PREFER_HIDDEN 1
+
+#define LZ4STEGO_HIDDEN_REFERENCE 2
+
+#define LZ4STEGO_HIDDEN_NONE 3
+
+typedef struct lz4stego_compressor lz4stego_compressor;
+
+typedef struct lz4stego_decompressor lz4stego_decompressor;
+
+typedef struct lz4stego_options lz4stego_options;
+
+/**
+ * Receives the output; returns 0 on success.
+ */
+typedef int (*lz4stego_write_fn)(void *context, const uint8_t *data, size_t len);
+
+typedef struct lz4stego_report {
+  uint64_t uncompressed_size;
+  /**
+   * Size of the whole stream, including the headers
+   */
+  uint64_t compressed_size;
+  uint64_t block_count;
+  /**
+   * Hidden data capacity of the stream in bits
+   */
+  double hidden_bits;
+  /**
+   * Hidden data capacity in whole bytes; the payload is embedded in full
+   * if it is not larger
+   */
+  uint64_t hidden_bytes;
+  /**
+   * Bytes of the payload that did not fit, 0 if it was embedded in full
+   */
+  uint64_t hidden_bytes_left;
+} lz4stego_report;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * LZ4STEGO_ABI_VERSION of the header the library was built with.
+ */
+uint32_t lz4stego_abi_version(void);
+
+/**
+ * Static description of a status code.
+ */
+const char *lz4stego_status_message(int status);
+
+/**
+ * Compressor settings, checked when the compressor is created.
+ */
+struct lz4stego_options *lz4stego_options_new(void);
+
+void lz4stego_options_free(struct lz4stego_options *options);
+
+/**
+ * Block maximum size ID of the frame descriptor: 4 (64 KB) to 7 (4 MB)
+ */
+int lz4stego_options_set_block_size(struct lz4stego_options *options, int block_size_id);
+
+int lz4stego_options_set_block_independent(struct lz4stego_options *options, int block_independent);
+
+int lz4stego_options_set_block_checksum(struct lz4stego_options *options, int block_checksum);
+
+int lz4stego_options_set_content_checksum(struct lz4stego_options *options, int content_checksum);
+
+/**
+ * Offers any match as a candidate, trading compression ratio for capacity
+ */
+int lz4stego_options_set_prefer_hidden(struct lz4stego_options *options, int prefer_hidden);
+
+/**
+ * One of LZ4STEGO_MODE_*, with its acceleration, ratio or input size; the
+ * acceleration has to fit in 32 bits, and the ratio and size in size_t
+ */
+int lz4stego_options_set_mode(struct lz4stego_options *options, int mode, uint64_t parameter);
+
+/**
+ * Creates a compressor hiding a copy of hidden_data and passing the
+ * compressed data to write. options may be NULL for the defaults.
+ */
+int lz4stego_compressor_new(const struct lz4stego_options *options,
+                            const uint8_t *hidden_data,
+                            size_t hidden_len,
+                            lz4stego_write_fn write,
+                            void *context,
+                            struct lz4stego_compressor **compressor);
+
+int lz4stego_compressor_write(struct lz4stego_compressor *compressor,
+                              const uint8_t *data,
+                              size_t len);
+
+/**
+ * Ends the current block.
+ */
+int lz4stego_compressor_flush(struct lz4stego_compressor *compressor);
+
+/**
+ * Ends the frame and releases the compressor, even on failure. report may be
+ * NULL; otherwise it is filled on success.
+ */
+int lz4stego_compressor_finish(struct lz4stego_compressor *compressor,
+                               struct lz4stego_report *report);
+
+/**
+ * Releases the compressor without ending the frame.
+ */
+void lz4stego_compressor_free(struct lz4stego_compressor *compressor);
+
+/**
+ * Creates a decompressor fed with the compressed data as it arrives.
+ */
+int lz4stego_decompressor_new(int mode, struct lz4stego_decompressor **decompressor);
+
+/**
+ * Decompresses every block completed by data, passing the decompressed data
+ * to write. Incomplete blocks are kept until the rest of them is fed.
+ */
+int lz4stego_decompressor_feed(struct lz4stego_decompressor *decompressor,
+                               const uint8_t *data,
+                               size_t len,
+                               lz4stego_write_fn write,
+                               void *context);
+
+/**
+ * Returns 1 once the end of the frame was fed, 0 otherwise or if poisoned.
+ */
+int lz4stego_decompressor_is_finished(const struct lz4stego_decompressor *decompressor);
+
+/**
+ * Copies the hidden data extracted so far to buffer, setting len to its
+ * size. Returns LZ4STEGO_ERROR_BUFFER_TOO_SMALL if it does not fit, so that
+ * the size can be queried with a NULL buffer and capacity 0.
+ */
+int lz4stego_decompressor_get_hidden_data(const struct lz4stego_decompressor *decompressor,
+                                          uint8_t *buffer,
+                                          size_t capacity,
+                                          size_t *len);
+
+void lz4stego_decompressor_free(struct lz4stego_decompressor *decompressor);
+
+/**
+ * Decompresses a whole LZ4 frame into output without extracting the hidden
+ * data, setting output_len to the decompressed size. Returns
+ * LZ4STEGO_ERROR_BUFFER_TOO_SMALL if the output does not fit in capacity.
+ */
+int lz4stego_decompress_into(const uint8_t *data,
+                             size_t len,
+                             uint8_t *output,
+                             size_t capacity,
+                             size_t *output_len);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LZ4STEGO_H */
@@ -0,0 +1,570 @@
+//! C API of liblz4stego. The handles are opaque pointers to the Rust types,
+//! and every fallible function returns one of the `LZ4STEGO_*` status codes.
+//! `include/lz4stego.h` is generated from this file by cbindgen, so the doc
+//! comments here are the documentation of the C API.
+
+#![allow(clippy::missing_safety_doc)]
+
+use std::convert::TryFrom;
+use std::ffi::c_void;
+use std::io::Write;
+use std::os::raw::{c_char, c_int};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use liblz4stego::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use liblz4stego::candidate_policy::CandidatePolicy;
+use liblz4stego::compressor::OwnedCompressor;
+use liblz4stego::errors::Lz4DecompressErrorKind;
+use liblz4stego::push_decoder::PushDecoder;
+use liblz4stego::{decompress_into, HiddenDataMode};
+
+/// Version of the ABI this header describes, increased on every incompatible
+/// change; compare it to lz4stego_abi_version to detect a mismatched library
+pub const LZ4STEGO_ABI_VERSION: u32 = 1;
+
+pub const LZ4STEGO_OK: c_int = 0;
+pub const LZ4STEGO_ERROR_NULL_POINTER: c_int = -1;
+/// An argument is out of its range, or the data written does not match the
+/// declared content size
+pub const LZ4STEGO_ERROR_INVALID_ARGUMENT: c_int = -2;
+/// The options cannot be used together
+pub const LZ4STEGO_ERROR_INVALID_OPTIONS: c_int = -3;
+/// A write callback returned a non-zero value
+pub const LZ4STEGO_ERROR_IO: c_int = -4;
+/// The compressed data is invalid or truncated
+pub const LZ4STEGO_ERROR_INVALID_DATA: c_int = -5;
+pub const LZ4STEGO_ERROR_BUFFER_TOO_SMALL: c_int = -6;
+/// A bug in the library. The handle the call was made on is poisoned: every
+/// later call on it fails with this status, and it can only be freed.
+pub const LZ4STEGO_ERROR_PANIC: c_int = -7;
+
+/// Parse modes of lz4stego_options_set_mode
+pub const LZ4STEGO_MODE_DEFAULT: c_int = 0;
+/// Reference lz4 parse with the given acceleration, without hidden data
+pub const LZ4STEGO_MODE_FAST: c_int = 1;
+/// Reference lz4 mimicry with the given acceleration
+pub const LZ4STEGO_MODE_REFERENCE: c_int = 2;
+/// Syndrome-trellis coding with the given ratio
+pub const LZ4STEGO_MODE_TRELLIS: c_int = 3;
+/// Guaranteed capacity for an input of the given size
+pub const LZ4STEGO_MODE_PLANNED: c_int = 4;
+
+/// Hidden data modes of lz4stego_decompressor_new; they have to match the
+/// options the data was compressed with
+pub const LZ4STEGO_HIDDEN_DEFAULT: c_int = 0;
+pub const LZ4STEGO_HIDDEN_PREFER_HIDDEN: c_int = 1;
+pub const LZ4STEGO_HIDDEN_REFERENCE: c_int = 2;
+pub const LZ4STEGO_HIDDEN_NONE: c_int = 3;
+
+/// Receives the output; returns 0 on success.
+pub type WriteCallback =
+    Option<unsafe extern "C" fn(context: *mut c_void, data: *const u8, len: usize) -> c_int>;
+
+pub struct Options {
+    builder: CompressorBuilder,
+    poisoned: bool,
+}
+
+pub struct Compressor {
+    compressor: OwnedCompressor<CallbackWriter>,
+    hidden_len: usize,
+    poisoned: bool,
+}
+
+pub struct Decompressor {
+    decoder: PushDecoder,
+    hidden_data: Vec<u8>,
+    poisoned: bool,
+}
+
+/// A handle whose state cannot be trusted once a call on it panicked.
+trait Handle {
+    fn get_poisoned(&mut self) -> &mut bool;
+}
+
+impl Handle for Options {
+    fn get_poisoned(&mut self) -> &mut bool {
+        &mut self.poisoned
+    }
+}
+
+impl Handle for Compressor {
+    fn get_poisoned(&mut self) -> &mut bool {
+        &mut self.poisoned
+    }
+}
+
+impl Handle for Decompressor {
+    fn get_poisoned(&mut self) -> &mut bool {
+        &mut self.poisoned
+    }
+}
+
+#[repr(C)]
+pub struct Report {
+    pub uncompressed_size: u64,
+    /// Size of the whole stream, including the headers
+    pub compressed_size: u64,
+    pub block_count: u64,
+    /// Hidden data capacity of the stream in bits
+    pub hidden_bits: f64,
+    /// Hidden data capacity in whole bytes; the payload is embedded in full
+    /// if it is not larger
+    pub hidden_bytes: u64,
+    /// Bytes of the payload that did not fit, 0 if it was embedded in full
+    pub hidden_bytes_left: u64,
+}
+
+struct CallbackWriter {
+    callback: unsafe extern "C" fn(*mut c_void, *const u8, usize) -> c_int,
+    context: *mut c_void,
+}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if unsafe { (self.callback)(self.context, buf.as_ptr(), buf.len()) } != 0 {
+            return Err(std::io::Error::other("Write callback failed"));
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs `f`, turning a panic into an error code, as unwinding into C is
+/// undefined behavior.
+fn guard<F: FnOnce() -> c_int>(f: F) -> c_int {
+    catch_unwind(AssertUnwindSafe(f)).unwrap_or(LZ4STEGO_ERROR_PANIC)
+}
+
+/// Like `guard`, for a call on `handle`. A panic poisons the handle: every
+/// later call on it fails with `LZ4STEGO_ERROR_PANIC`.
+fn guard_handle<H: Handle, F: FnOnce(&mut H) -> c_int>(handle: &mut H, f: F) -> c_int {
+    if *handle.get_poisoned() {
+        return LZ4STEGO_ERROR_PANIC;
+    }
+
+    match catch_unwind(AssertUnwindSafe(|| f(handle))) {
+        Ok(status) => status,
+        Err(_) => {
+            *handle.get_poisoned() = true;
+            LZ4STEGO_ERROR_PANIC
+        }
+    }
+}
+
+fn get_io_status(e: std::io::Error) -> c_int {
+    match e.kind() {
+        std::io::ErrorKind::InvalidInput => LZ4STEGO_ERROR_INVALID_ARGUMENT,
+        _ => LZ4STEGO_ERROR_IO,
+    }
+}
+
+unsafe fn get_slice<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match len {
+        0 => Some(&[]),
+        _ if data.is_null() => None,
+        _ => Some(std::slice::from_raw_parts(data, len)),
+    }
+}
+
+/// LZ4STEGO_ABI_VERSION of the header the library was built with.
+#[no_mangle]
+pub extern "C" fn lz4stego_abi_version() -> u32 {
+    LZ4STEGO_ABI_VERSION
+}
+
+/// Static description of a status code.
+#[no_mangle]
+pub extern "C" fn lz4stego_status_message(status: c_int) -> *const c_char {
+    let message: &'static [u8] = match status {
+        LZ4STEGO_OK => b"OK\0",
+        LZ4STEGO_ERROR_NULL_POINTER => b"Null pointer\0",
+        LZ4STEGO_ERROR_INVALID_ARGUMENT => b"Invalid argument\0",
+        LZ4STEGO_ERROR_INVALID_OPTIONS => b"Invalid combination of options\0",
+        LZ4STEGO_ERROR_IO => b"Write callback failed\0",
+        LZ4STEGO_ERROR_INVALID_DATA => b"Invalid compressed data\0",
+        LZ4STEGO_ERROR_BUFFER_TOO_SMALL => b"Buffer is too small\0",
+        LZ4STEGO_ERROR_PANIC => b"Internal error\0",
+        _ => b"Unknown status\0",
+    };
+
+    message.as_ptr() as *const c_char
+}
+
+/// Compressor settings, checked when the compressor is created.
+#[no_mangle]
+pub extern "C" fn lz4stego_options_new() -> *mut Options {
+    Box::into_raw(Box::new(Options {
+        builder: CompressorBuilder::new(),
+        poisoned: false,
+    }))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_options_free(options: *mut Options) {
+    if !options.is_null() {
+        drop(Box::from_raw(options));
+    }
+}
+
+/// Replaces the builder of `options` with `f` applied to it.
+unsafe fn set_option<F: FnOnce(CompressorBuilder) -> CompressorBuilder>(
+    options: *mut Options,
+    f: F,
+) -> c_int {
+    match options.as_mut() {
+        Some(options) => guard_handle(options, |options| {
+            options.builder = f(std::mem::take(&mut options.builder));
+            LZ4STEGO_OK
+        }),
+        None => LZ4STEGO_ERROR_NULL_POINTER,
+    }
+}
+
+/// Block maximum size ID of the frame descriptor: 4 (64 KB) to 7 (4 MB)
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_options_set_block_size(
+    options: *mut Options,
+    block_size_id: c_int,
+) -> c_int {
+    let block_size = match block_size_id {
+        4 => BlockMaxSize::Max64KB,
+        5 => BlockMaxSize::Max256KB,
+        6 => BlockMaxSize::Max1MB,
+        7 => BlockMaxSize::Max4MB,
+        _ => return LZ4STEGO_ERROR_INVALID_ARGUMENT,
+    };
+    set_option(options, |builder| builder.block_size(block_size))
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_options_set_block_independent(
+    options: *mut Options,
+    block_independent: c_int,
+) -> c_int {
+    set_option(options, |builder| {
+        builder.block_independent(block_independent != 0)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_options_set_block_checksum(
+    options: *mut Options,
+    block_checksum: c_int,
+) -> c_int {
+    set_option(options, |builder| {
+        builder.block_checksum(block_checksum != 0)
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_options_set_content_checksum(
+    options: *mut Options,
+    content_checksum: c_int,
+) -> c_int {
+    set_option(options, |builder| {
+        builder.content_checksum(content_checksum != 0)
+    })
+}
+
+/// Offers any match as a candidate, trading compression ratio for capacity
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_options_set_prefer_hidden(
+    options: *mut Options,
+    prefer_hidden: c_int,
+) -> c_int {
+    set_option(options, |builder| {
+        builder.candidate_policy(CandidatePolicy::from_prefer_hidden(prefer_hidden != 0))
+    })
+}
+
+/// One of LZ4STEGO_MODE_*, with its acceleration, ratio or input size; the
+/// acceleration has to fit in 32 bits, and the ratio and size in size_t
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_options_set_mode(
+    options: *mut Options,
+    mode: c_int,
+    parameter: u64,
+) -> c_int {
+    let mode = match mode {
+        LZ4STEGO_MODE_DEFAULT => Some(ParseMode::Default),
+        LZ4STEGO_MODE_FAST => u32::try_from(parameter).ok().map(ParseMode::Fast),
+        LZ4STEGO_MODE_REFERENCE => u32::try_from(parameter).ok().map(ParseMode::Reference),
+        LZ4STEGO_MODE_TRELLIS => usize::try_from(parameter).ok().map(ParseMode::Trellis),
+        LZ4STEGO_MODE_PLANNED => usize::try_from(parameter).ok().map(ParseMode::Planned),
+        _ => None,
+    };
+    match mode {
+        Some(mode) => set_option(options, |builder| builder.mode(mode)),
+        None => LZ4STEGO_ERROR_INVALID_ARGUMENT,
+    }
+}
+
+/// Creates a compressor hiding a copy of hidden_data and passing the
+/// compressed data to write. options may be NULL for the defaults.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_compressor_new(
+    options: *const Options,
+    hidden_data: *const u8,
+    hidden_len: usize,
+    write: WriteCallback,
+    context: *mut c_void,
+    compressor: *mut *mut Compressor,
+) -> c_int {
+    let (hidden_data, write) = match (get_slice(hidden_data, hidden_len), write) {
+        (Some(hidden_data), Some(write)) if !compressor.is_null() => (hidden_data, write),
+        _ => return LZ4STEGO_ERROR_NULL_POINTER,
+    };
+    let default_options = Options {
+        builder: CompressorBuilder::new(),
+        poisoned: false,
+    };
+    let options = options.as_ref().unwrap_or(&default_options);
+    if options.poisoned {
+        return LZ4STEGO_ERROR_PANIC;
+    }
+
+    guard(|| {
+        let writer = CallbackWriter {
+            callback: write,
+            context,
+        };
+        match options.builder.build_owned(writer, hidden_data.to_vec()) {
+            Ok(built) => {
+                *compressor = Box::into_raw(Box::new(Compressor {
+                    compressor: built,
+                    hidden_len,
+                    poisoned: false,
+                }));
+                LZ4STEGO_OK
+            }
+            Err(_) => LZ4STEGO_ERROR_INVALID_OPTIONS,
+        }
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_compressor_write(
+    compressor: *mut Compressor,
+    data: *const u8,
+    len: usize,
+) -> c_int {
+    let (compressor, data) = match (compressor.as_mut(), get_slice(data, len)) {
+        (Some(compressor), Some(data)) => (compressor, data),
+        _ => return LZ4STEGO_ERROR_NULL_POINTER,
+    };
+
+    guard_handle(compressor, |compressor| {
+        match compressor.compressor.write_all(data) {
+            Ok(()) => LZ4STEGO_OK,
+            Err(e) => get_io_status(e),
+        }
+    })
+}
+
+/// Ends the current block.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_compressor_flush(compressor: *mut Compressor) -> c_int {
+    let compressor = match compressor.as_mut() {
+        Some(compressor) => compressor,
+        None => return LZ4STEGO_ERROR_NULL_POINTER,
+    };
+
+    guard_handle(compressor, |compressor| {
+        match compressor.compressor.flush() {
+            Ok(()) => LZ4STEGO_OK,
+            Err(e) => get_io_status(e),
+        }
+    })
+}
+
+/// Ends the frame and releases the compressor, even on failure. report may be
+/// NULL; otherwise it is filled on success.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_compressor_finish(
+    compressor: *mut Compressor,
+    report: *mut Report,
+) -> c_int {
+    if compressor.is_null() {
+        return LZ4STEGO_ERROR_NULL_POINTER;
+    }
+    let compressor = Box::from_raw(compressor);
+    if compressor.poisoned {
+        return LZ4STEGO_ERROR_PANIC;
+    }
+
+    guard(|| {
+        let hidden_len = compressor.hidden_len;
+        let result = match compressor.compressor.finish() {
+            Ok(result) => result,
+            Err(e) => return get_io_status(e),
+        };
+        if let Some(report) = report.as_mut() {
+            *report = Report {
+                uncompressed_size: result.uncompressed_size,
+                compressed_size: result.compressed_size,
+                block_count: result.block_count as u64,
+                hidden_bits: result.hidden_bits,
+                hidden_bytes: result.hidden_bytes as u64,
+                hidden_bytes_left: hidden_len.saturating_sub(result.hidden_bytes) as u64,
+            };
+        }
+
+        LZ4STEGO_OK
+    })
+}
+
+/// Releases the compressor without ending the frame.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_compressor_free(compressor: *mut Compressor) {
+    if !compressor.is_null() {
+        drop(Box::from_raw(compressor));
+    }
+}
+
+/// Creates a decompressor fed with the compressed data as it arrives.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_decompressor_new(
+    mode: c_int,
+    decompressor: *mut *mut Decompressor,
+) -> c_int {
+    if decompressor.is_null() {
+        return LZ4STEGO_ERROR_NULL_POINTER;
+    }
+    let mode = match mode {
+        LZ4STEGO_HIDDEN_DEFAULT => HiddenDataMode::Default,
+        LZ4STEGO_HIDDEN_PREFER_HIDDEN => HiddenDataMode::PreferHidden,
+        LZ4STEGO_HIDDEN_REFERENCE => HiddenDataMode::Reference,
+        LZ4STEGO_HIDDEN_NONE => HiddenDataMode::None,
+        _ => return LZ4STEGO_ERROR_INVALID_ARGUMENT,
+    };
+
+    *decompressor = Box::into_raw(Box::new(Decompressor {
+        decoder: PushDecoder::new(mode),
+        hidden_data: Vec::new(),
+        poisoned: false,
+    }));
+    LZ4STEGO_OK
+}
+
+/// Decompresses every block completed by data, passing the decompressed data
+/// to write. Incomplete blocks are kept until the rest of them is fed.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_decompressor_feed(
+    decompressor: *mut Decompressor,
+    data: *const u8,
+    len: usize,
+    write: WriteCallback,
+    context: *mut c_void,
+) -> c_int {
+    let (decompressor, data, write) = match (decompressor.as_mut(), get_slice(data, len), write) {
+        (Some(decompressor), Some(data), Some(write)) => (decompressor, data, write),
+        _ => return LZ4STEGO_ERROR_NULL_POINTER,
+    };
+
+    guard_handle(decompressor, |decompressor| {
+        let decoded = match decompressor.decoder.feed(data) {
+            Ok(decoded) => decoded,
+            Err(_) => return LZ4STEGO_ERROR_INVALID_DATA,
+        };
+        decompressor
+            .hidden_data
+            .extend_from_slice(&decoded.hidden_data);
+        if !decoded.output.is_empty()
+            && write(context, decoded.output.as_ptr(), decoded.output.len()) != 0
+        {
+            return LZ4STEGO_ERROR_IO;
+        }
+
+        LZ4STEGO_OK
+    })
+}
+
+/// Returns 1 once the end of the frame was fed, 0 otherwise or if poisoned.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_decompressor_is_finished(
+    decompressor: *const Decompressor,
+) -> c_int {
+    match decompressor.as_ref() {
+        Some(decompressor) if !decompressor.poisoned => decompressor.decoder.is_finished() as c_int,
+        _ => 0,
+    }
+}
+
+/// Copies the hidden data extracted so far to buffer, setting len to its
+/// size. Returns LZ4STEGO_ERROR_BUFFER_TOO_SMALL if it does not fit, so that
+/// the size can be queried with a NULL buffer and capacity 0.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_decompressor_get_hidden_data(
+    decompressor: *const Decompressor,
+    buffer: *mut u8,
+    capacity: usize,
+    len: *mut usize,
+) -> c_int {
+    let decompressor = match decompressor.as_ref() {
+        Some(decompressor) if !len.is_null() => decompressor,
+        _ => return LZ4STEGO_ERROR_NULL_POINTER,
+    };
+    if decompressor.poisoned {
+        return LZ4STEGO_ERROR_PANIC;
+    }
+    let hidden_data = &decompressor.hidden_data;
+    *len = hidden_data.len();
+    if capacity < hidden_data.len() {
+        return LZ4STEGO_ERROR_BUFFER_TOO_SMALL;
+    }
+    if !hidden_data.is_empty() {
+        if buffer.is_null() {
+            return LZ4STEGO_ERROR_NULL_POINTER;
+        }
+        std::ptr::copy_nonoverlapping(hidden_data.as_ptr(), buffer, hidden_data.len());
+    }
+
+    LZ4STEGO_OK
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_decompressor_free(decompressor: *mut Decompressor) {
+    if !decompressor.is_null() {
+        drop(Box::from_raw(decompressor));
+    }
+}
+
+/// Decompresses a whole LZ4 frame into output without extracting the hidden
+/// data, setting output_len to the decompressed size. Returns
+/// LZ4STEGO_ERROR_BUFFER_TOO_SMALL if the output does not fit in capacity.
+#[no_mangle]
+pub unsafe extern "C" fn lz4stego_decompress_into(
+    data: *const u8,
+    len: usize,
+    output: *mut u8,
+    capacity: usize,
+    output_len: *mut usize,
+) -> c_int {
+    let data = match get_slice(data, len) {
+        Some(data) if !output_len.is_null() && (capacity == 0 || !output.is_null()) => data,
+        _ => return LZ4STEGO_ERROR_NULL_POINTER,
+    };
+    let output: &mut [u8] = match capacity {
+        0 => &mut [],
+        _ => std::slice::from_raw_parts_mut(output, capacity),
+    };
+
+    guard(|| match decompress_into(data, output) {
+        Ok(size) => {
+            *output_len = size;
+            LZ4STEGO_OK
+        }
+        Err(e) if e.get_kind() == Lz4DecompressErrorKind::BufferTooSmall => {
+            LZ4STEGO_ERROR_BUFFER_TOO_SMALL
+        }
+        Err(_) => LZ4STEGO_ERROR_INVALID_DATA,
+    })
+}
+
+#[cfg(test)]
+mod tests;
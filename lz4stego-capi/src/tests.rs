@@ -0,0 +1,82 @@
+use std::ffi::c_void;
+use std::ptr::null_mut;
+
+use crate::*;
+
+unsafe extern "C" fn discard(_context: *mut c_void, _data: *const u8, _len: usize) -> c_int {
+    0
+}
+
+#[test]
+fn test_poisoned_handles() {
+    unsafe {
+        let options = lz4stego_options_new();
+        assert_eq!(
+            guard_handle(&mut *options, |_| panic!("options")),
+            LZ4STEGO_ERROR_PANIC
+        );
+        assert_eq!(
+            lz4stego_options_set_block_checksum(options, 1),
+            LZ4STEGO_ERROR_PANIC
+        );
+        let mut compressor = null_mut();
+        assert_eq!(
+            lz4stego_compressor_new(
+                options,
+                [].as_ptr(),
+                0,
+                Some(discard),
+                null_mut(),
+                &mut compressor
+            ),
+            LZ4STEGO_ERROR_PANIC
+        );
+        lz4stego_options_free(options);
+
+        assert_eq!(
+            lz4stego_compressor_new(
+                null_mut(),
+                [].as_ptr(),
+                0,
+                Some(discard),
+                null_mut(),
+                &mut compressor
+            ),
+            LZ4STEGO_OK
+        );
+        assert_eq!(
+            guard_handle(&mut *compressor, |_| panic!("compressor")),
+            LZ4STEGO_ERROR_PANIC
+        );
+        assert_eq!(
+            lz4stego_compressor_write(compressor, b"data".as_ptr(), 4),
+            LZ4STEGO_ERROR_PANIC
+        );
+        assert_eq!(lz4stego_compressor_flush(compressor), LZ4STEGO_ERROR_PANIC);
+        assert_eq!(
+            lz4stego_compressor_finish(compressor, null_mut()),
+            LZ4STEGO_ERROR_PANIC
+        );
+
+        let mut decompressor = null_mut();
+        assert_eq!(
+            lz4stego_decompressor_new(LZ4STEGO_HIDDEN_DEFAULT, &mut decompressor),
+            LZ4STEGO_OK
+        );
+        assert_eq!(
+            guard_handle(&mut *decompressor, |_| panic!("decompressor")),
+            LZ4STEGO_ERROR_PANIC
+        );
+        assert_eq!(
+            lz4stego_decompressor_feed(decompressor, [].as_ptr(), 0, Some(discard), null_mut()),
+            LZ4STEGO_ERROR_PANIC
+        );
+        assert_eq!(lz4stego_decompressor_is_finished(decompressor), 0);
+        let mut len = 0;
+        assert_eq!(
+            lz4stego_decompressor_get_hidden_data(decompressor, null_mut(), 0, &mut len),
+            LZ4STEGO_ERROR_PANIC
+        );
+        lz4stego_decompressor_free(decompressor);
+    }
+}
@@ -0,0 +1,191 @@
+/* Round trip through the C API; exits with a non-zero status on failure. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "lz4stego.h"
+
+#define CHECK(expr)                                                        \
+    do {                                                                   \
+        if (!(expr)) {                                                     \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__,         \
+                    __LINE__, #expr);                                      \
+            exit(1);                                                       \
+        }                                                                  \
+    } while (0)
+
+#define CHECK_OK(expr)                                                     \
+    do {                                                                   \
+        int status_ = (expr);                                              \
+        if (status_ != LZ4STEGO_OK) {                                      \
+            fprintf(stderr, "%s:%d: %s: %s\n", __FILE__, __LINE__, #expr,  \
+                    lz4stego_status_message(status_));                     \
+            exit(1);                                                       \
+        }                                                                  \
+    } while (0)
+
+typedef struct buffer {
+    uint8_t *data;
+    size_t len;
+} buffer;
+
+static int append(void *context, const uint8_t *data, size_t len) {
+    buffer *buf = context;
+    uint8_t *grown = realloc(buf->data, buf->len + len);
+    if (grown == NULL) {
+        return 1;
+    }
+    memcpy(grown + buf->len, data, len);
+    buf->data = grown;
+    buf->len += len;
+    return 0;
+}
+
+static int fail(void *context, const uint8_t *data, size_t len) {
+    (void)context;
+    (void)data;
+    (void)len;
+    return 1;
+}
+
+static buffer make_input(void) {
+    buffer input = {NULL, 0};
+    char line[64];
+    for (int i = 0; i < 4000; i++) {
+        int len = snprintf(line, sizeof(line), "line %d of the cover text, %d\n", i % 97, i % 13);
+        CHECK(append(&input, (const uint8_t *)line, (size_t)len) == 0);
+    }
+    return input;
+}
+
+static buffer compress(const buffer *input, const lz4stego_options *options,
+                       const char *hidden, lz4stego_report *report) {
+    buffer compressed = {NULL, 0};
+    lz4stego_compressor *compressor = NULL;
+    CHECK_OK(lz4stego_compressor_new(options, (const uint8_t *)hidden, strlen(hidden),
+                                     append, &compressed, &compressor));
+    size_t half = input->len / 2;
+    CHECK_OK(lz4stego_compressor_write(compressor, input->data, half));
+    CHECK_OK(lz4stego_compressor_write(compressor, input->data + half, input->len - half));
+    CHECK_OK(lz4stego_compressor_finish(compressor, report));
+    return compressed;
+}
+
+static void test_roundtrip(void) {
+    const char *hidden = "hidden through the C API";
+    buffer input = make_input();
+    lz4stego_options *options = lz4stego_options_new();
+    CHECK_OK(lz4stego_options_set_block_size(options, 4));
+    CHECK_OK(lz4stego_options_set_block_checksum(options, 1));
+    CHECK_OK(lz4stego_options_set_content_checksum(options, 1));
+
+    lz4stego_report report;
+    buffer compressed = compress(&input, options, hidden, &report);
+    lz4stego_options_free(options);
+    CHECK(report.uncompressed_size == input.len);
+    CHECK(report.compressed_size == compressed.len);
+    CHECK(report.hidden_bytes >= strlen(hidden));
+    CHECK(report.hidden_bytes_left == 0);
+    CHECK(report.hidden_bits >= 8.0 * strlen(hidden));
+
+    /* Feed the compressed data in small chunks */
+    buffer output = {NULL, 0};
+    lz4stego_decompressor *decompressor = NULL;
+    CHECK_OK(lz4stego_decompressor_new(LZ4STEGO_HIDDEN_DEFAULT, &decompressor));
+    for (size_t i = 0; i < compressed.len; i += 100) {
+        size_t len = compressed.len - i < 100 ? compressed.len - i : 100;
+        CHECK_OK(lz4stego_decompressor_feed(decompressor, compressed.data + i, len, append,
+                                            &output));
+    }
+    CHECK(lz4stego_decompressor_is_finished(decompressor));
+    CHECK(output.len == input.len);
+    CHECK(memcmp(output.data, input.data, input.len) == 0);
+
+    size_t hidden_len = 0;
+    CHECK(lz4stego_decompressor_get_hidden_data(decompressor, NULL, 0, &hidden_len) ==
+          LZ4STEGO_ERROR_BUFFER_TOO_SMALL);
+    CHECK(hidden_len == strlen(hidden));
+    char extracted[64];
+    CHECK_OK(lz4stego_decompressor_get_hidden_data(decompressor, (uint8_t *)extracted,
+                                                   sizeof(extracted), &hidden_len));
+    CHECK(memcmp(extracted, hidden, hidden_len) == 0);
+    lz4stego_decompressor_free(decompressor);
+
+    uint8_t *plain = malloc(input.len);
+    size_t plain_len = 0;
+    CHECK_OK(lz4stego_decompress_into(compressed.data, compressed.len, plain, input.len,
+                                      &plain_len));
+    CHECK(plain_len == input.len);
+    CHECK(memcmp(plain, input.data, input.len) == 0);
+    CHECK(lz4stego_decompress_into(compressed.data, compressed.len, plain, input.len - 1,
+                                   &plain_len) == LZ4STEGO_ERROR_BUFFER_TOO_SMALL);
+    CHECK(lz4stego_decompress_into(compressed.data, compressed.len - 1, plain, input.len,
+                                   &plain_len) == LZ4STEGO_ERROR_INVALID_DATA);
+
+    free(plain);
+    free(output.data);
+    free(compressed.data);
+    free(input.data);
+}
+
+static void test_overflow(void) {
+    buffer input = {NULL, 0};
+    CHECK(append(&input, (const uint8_t *)"abcabcabcabcabcabcabcabc", 24) == 0);
+    static char hidden[4096];
+    memset(hidden, 'x', sizeof(hidden) - 1);
+
+    lz4stego_report report;
+    buffer compressed = compress(&input, NULL, hidden, &report);
+    CHECK(report.hidden_bytes < strlen(hidden));
+    CHECK(report.hidden_bytes + report.hidden_bytes_left == strlen(hidden));
+
+    free(compressed.data);
+    free(input.data);
+}
+
+static void test_errors(void) {
+    lz4stego_options *options = lz4stego_options_new();
+    CHECK(lz4stego_options_set_block_size(options, 3) == LZ4STEGO_ERROR_INVALID_ARGUMENT);
+    CHECK(lz4stego_options_set_mode(options, 42, 0) == LZ4STEGO_ERROR_INVALID_ARGUMENT);
+    CHECK(lz4stego_options_set_mode(options, LZ4STEGO_MODE_FAST, UINT64_C(1) << 32) ==
+          LZ4STEGO_ERROR_INVALID_ARGUMENT);
+#if SIZE_MAX > UINT32_MAX
+    CHECK_OK(lz4stego_options_set_mode(options, LZ4STEGO_MODE_PLANNED, UINT64_C(1) << 32));
+#endif
+    CHECK(lz4stego_options_set_mode(NULL, LZ4STEGO_MODE_FAST, 1) ==
+          LZ4STEGO_ERROR_NULL_POINTER);
+
+    /* The fast mode cannot hide data */
+    CHECK_OK(lz4stego_options_set_mode(options, LZ4STEGO_MODE_FAST, 1));
+    buffer compressed = {NULL, 0};
+    lz4stego_compressor *compressor = NULL;
+    CHECK(lz4stego_compressor_new(options, (const uint8_t *)"hidden", 6, append, &compressed,
+                                  &compressor) == LZ4STEGO_ERROR_INVALID_OPTIONS);
+    lz4stego_options_free(options);
+
+    /* Failing write callback */
+    CHECK_OK(lz4stego_compressor_new(NULL, NULL, 0, fail, NULL, &compressor));
+    CHECK_OK(lz4stego_compressor_write(compressor, (const uint8_t *)"data", 4));
+    CHECK(lz4stego_compressor_finish(compressor, NULL) == LZ4STEGO_ERROR_IO);
+
+    lz4stego_decompressor *decompressor = NULL;
+    CHECK(lz4stego_decompressor_new(42, &decompressor) == LZ4STEGO_ERROR_INVALID_ARGUMENT);
+    CHECK_OK(lz4stego_decompressor_new(LZ4STEGO_HIDDEN_DEFAULT, &decompressor));
+    CHECK(lz4stego_decompressor_feed(decompressor, (const uint8_t *)"not an lz4 frame", 16,
+                                     append, &compressed) == LZ4STEGO_ERROR_INVALID_DATA);
+    CHECK(!lz4stego_decompressor_is_finished(decompressor));
+    lz4stego_decompressor_free(decompressor);
+
+    CHECK(strcmp(lz4stego_status_message(LZ4STEGO_OK), "OK") == 0);
+    free(compressed.data);
+}
+
+int main(void) {
+    CHECK(lz4stego_abi_version() == LZ4STEGO_ABI_VERSION);
+    test_roundtrip();
+    test_overflow();
+    test_errors();
+    printf("C API tests passed\n");
+    return 0;
+}
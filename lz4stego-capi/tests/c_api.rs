@@ -0,0 +1,67 @@
+//! Builds the C test program against the shared library and runs it. Skipped
+//! if there is no C compiler, or outside of Linux, where the flags below are
+//! not understood by every compiler.
+
+use std::io::ErrorKind;
+use std::process::Command;
+
+#[test]
+fn test_c_program() {
+    if !cfg!(target_os = "linux") {
+        eprintln!("Skipping the C API test outside of Linux");
+        return;
+    }
+
+    let manifest_dir = env!("CARGO_MANIFEST_DIR");
+    // The libraries are built next to the test binary, in target/<profile>/deps
+    let library_dir = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .unwrap()
+        .to_path_buf();
+    let library_name = env!("CARGO_PKG_NAME").replace('-', "_");
+    let library_file = format!(
+        "{}{}{}",
+        std::env::consts::DLL_PREFIX,
+        library_name,
+        std::env::consts::DLL_SUFFIX
+    );
+    assert!(
+        library_dir.join(&library_file).exists(),
+        "{} was not built in {}",
+        library_file,
+        library_dir.display()
+    );
+    let program = std::path::Path::new(env!("CARGO_TARGET_TMPDIR")).join("test_capi");
+
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(&compiler)
+        .args(["-std=c99", "-Wall", "-Werror", "-I"])
+        .arg(format!("{}/include", manifest_dir))
+        .arg(format!("{}/tests/c/test_capi.c", manifest_dir))
+        .arg("-L")
+        .arg(&library_dir)
+        .arg(format!("-l{}", library_name))
+        .arg("-o")
+        .arg(&program)
+        .status();
+    let status = match status {
+        Ok(status) => status,
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            eprintln!("Skipping the C API test, {} is not available", compiler);
+            return;
+        }
+        Err(e) => panic!("Cannot run {}: {}", compiler, e),
+    };
+    assert!(status.success());
+
+    let output = Command::new(&program)
+        .env("LD_LIBRARY_PATH", &library_dir)
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
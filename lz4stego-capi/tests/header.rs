@@ -0,0 +1,32 @@
+//! Checks that `include/lz4stego.h` is the header cbindgen generates from the
+//! crate. Run with `LZ4STEGO_UPDATE_HEADER=1` to write the generated header
+//! instead.
+
+use std::path::Path;
+
+#[test]
+fn test_header_up_to_date() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(manifest_dir.join("cbindgen.toml")).unwrap();
+    let bindings = cbindgen::Builder::new()
+        .with_crate(manifest_dir)
+        .with_config(config)
+        .generate()
+        .unwrap();
+    let mut generated = Vec::new();
+    bindings.write(&mut generated);
+    let generated = String::from_utf8(generated).unwrap();
+
+    let header_path = manifest_dir.join("include/lz4stego.h");
+    if std::env::var_os("LZ4STEGO_UPDATE_HEADER").is_some() {
+        std::fs::write(&header_path, &generated).unwrap();
+        return;
+    }
+
+    let header = std::fs::read_to_string(&header_path).unwrap();
+    assert!(
+        header == generated,
+        "include/lz4stego.h is out of date; regenerate it with \
+         LZ4STEGO_UPDATE_HEADER=1 cargo test -p lz4stego-capi --test header"
+    );
+}
@@ -1,44 +1,156 @@
+use liblz4stego::analysis::{self, Analysis};
+use liblz4stego::builder::{BlockMaxSize, CompressorBuilder, ParseMode};
+use liblz4stego::candidate_policy::CandidatePolicy;
+use liblz4stego::capacity::{self, PolicyCapacity};
+use liblz4stego::embedder;
+use liblz4stego::offset_distribution::OffsetDistribution;
+use liblz4stego::patcher;
+use liblz4stego::sanitizer;
+use serde_json::json;
 use std::fs;
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
+
+pub enum CompressionMode {
+    Fast(u32),
+    Reference(u32),
+    Trellis(usize),
+    Hidden(CandidatePolicy),
+    Hc(usize),
+    Planned,
+}
+
+/// What is printed after compressing
+pub enum Summary {
+    None,
+    /// The number of bytes that can be hidden
+    Count,
+    /// The whole report, as JSON
+    Json,
+}
+
+/// Settings of the hidden channels, on top of the match offsets
+pub struct HiddenChannels {
+    pub literal_channel: Option<usize>,
+    pub match_truncation: Option<usize>,
+    pub block_channel: Option<(usize, usize)>,
+    pub offset_distribution: Option<OffsetDistribution>,
+}
+
+/// Settings of the LZ4 frame
+pub struct FrameSettings {
+    pub block_size: Option<BlockMaxSize>,
+    pub linked_blocks: bool,
+    pub block_checksum: bool,
+    pub content_checksum: bool,
+    pub content_size: bool,
+}
 
 pub fn compress(
     input_path: &str,
-    output_path: &str,
+    output_path_opt: Option<&str>,
     hidden_path_opt: Option<&str>,
-    count: bool,
-    prefer_hidden: bool,
+    summary: Summary,
+    mode: CompressionMode,
+    channels: HiddenChannels,
+    frame: FrameSettings,
 ) {
     let mut input_file = fs::File::open(input_path).unwrap();
-    let output_file = fs::File::create(output_path).unwrap();
-    let hidden_data = if let Some(hidden_path) = hidden_path_opt {
-        fs::read(hidden_path).unwrap()
+    let input_size = input_file.metadata().unwrap().len() as usize;
+    let output_write: Box<dyn Write> = if let Some(output_path) = output_path_opt {
+        Box::new(fs::File::create(output_path).unwrap())
+    } else {
+        Box::new(std::io::sink())
+    };
+    let hidden_len = hidden_path_opt.map_or(0, |hidden_path| {
+        fs::metadata(hidden_path).unwrap().len() as usize
+    });
+    let builder = match mode {
+        CompressionMode::Fast(acceleration) => {
+            CompressorBuilder::new().mode(ParseMode::Fast(acceleration))
+        }
+        CompressionMode::Reference(acceleration) => {
+            CompressorBuilder::new().mode(ParseMode::Reference(acceleration))
+        }
+        CompressionMode::Trellis(ratio) => CompressorBuilder::new().mode(ParseMode::Trellis(ratio)),
+        CompressionMode::Hidden(candidate_policy) => {
+            CompressorBuilder::new().candidate_policy(candidate_policy)
+        }
+        CompressionMode::Hc(level) => CompressorBuilder::new().search_depth(level),
+        CompressionMode::Planned => CompressorBuilder::new().mode(ParseMode::Planned(input_size)),
+    };
+    let builder = match channels.literal_channel {
+        Some(max_length) => builder.literal_channel(max_length),
+        None => builder,
+    };
+    let builder = match channels.match_truncation {
+        Some(max_truncation) => builder.match_truncation(max_truncation),
+        None => builder,
+    };
+    let builder = match channels.block_channel {
+        Some((block_size, range)) => builder.block_size_channel(block_size, range),
+        None => builder,
+    };
+    let builder = match channels.offset_distribution {
+        Some(offset_distribution) => builder.offset_distribution(offset_distribution),
+        None => builder,
+    };
+    let builder = match frame.block_size {
+        Some(block_size) => builder.block_size(block_size),
+        None => builder,
+    };
+    let builder = builder
+        .block_independent(!frame.linked_blocks)
+        .block_checksum(frame.block_checksum)
+        .content_checksum(frame.content_checksum);
+    let builder = if frame.content_size {
+        builder.content_size(input_size as u64)
     } else {
-        vec![]
+        builder
+    };
+    // The trellis and planned modes need the whole hidden data up front
+    let compressor = match hidden_path_opt {
+        Some(hidden_path)
+            if !matches!(mode, CompressionMode::Trellis(_) | CompressionMode::Planned) =>
+        {
+            let hidden_read = io::BufReader::new(fs::File::open(hidden_path).unwrap());
+            builder.build_with_reader(output_write, hidden_read)
+        }
+        Some(hidden_path) => builder.build_owned(output_write, fs::read(hidden_path).unwrap()),
+        None => builder.build_owned(output_write, vec![]),
     };
-    let mut compressor = liblz4stego::compressor::Compressor::new_with_hidden_data(
-        output_file,
-        &hidden_data,
-        prefer_hidden,
-    )
-    .unwrap();
+    let mut compressor = compressor.unwrap_or_else(|e| panic!("Invalid settings: {}", e));
 
     const BUFFER_SIZE: usize = 4 * 1024 * 1024;
-    let mut buffer = Vec::with_capacity(BUFFER_SIZE);
-    unsafe {
-        buffer.set_len(BUFFER_SIZE);
-    }
+    let mut buffer = vec![0; BUFFER_SIZE];
     loop {
         let bytes_read = input_file.read(&mut buffer).unwrap();
         if bytes_read == 0 {
             break;
         }
 
-        compressor.write(&buffer[..bytes_read]).unwrap();
+        compressor.write_all(&buffer[..bytes_read]).unwrap();
+    }
+
+    if let Summary::Json = summary {
+        let report = compressor.finish().unwrap();
+        println!("{}", serde_json::to_string(&report).unwrap());
+        return;
     }
 
-    let available_bytes = compressor.finish().unwrap();
-    if count {
+    let (report, shaping_cost_bytes) = compressor.finish_with_shaping_cost().unwrap();
+    let available_bytes = report.hidden_bytes;
+    if matches!(summary, Summary::Count) || output_path_opt.is_none() {
         eprintln!("Available hidden data bytes: {}", available_bytes);
+        if channels.offset_distribution.is_some() {
+            eprintln!("Bytes lost to offset shaping: {}", shaping_cost_bytes);
+        }
+    }
+    if output_path_opt.is_none() && hidden_path_opt.is_some() {
+        if available_bytes >= hidden_len {
+            eprintln!("Hidden data fits ({} bytes)", hidden_len);
+        } else {
+            eprintln!("Hidden data does not fit ({} bytes)", hidden_len);
+        }
     }
 }
 
@@ -47,27 +159,232 @@ pub fn decompress(
     output_path: &str,
     hidden_path_opt: Option<&str>,
     prefer_hidden: bool,
+    reference: bool,
+    json: bool,
 ) {
     let input_file = fs::File::open(input_path).unwrap();
     let mut output_file = fs::File::create(output_path).unwrap();
-    let mut decompressor = liblz4stego::decompressor::Decompressor::new(input_file, prefer_hidden);
+    let mut decompressor = if hidden_path_opt.is_some() && reference {
+        liblz4stego::decompressor::Decompressor::new_reference(input_file)
+    } else if hidden_path_opt.is_some() {
+        liblz4stego::decompressor::Decompressor::new(input_file, prefer_hidden)
+    } else {
+        liblz4stego::decompressor::Decompressor::new_without_hidden_data(input_file)
+    };
 
-    const BUFFER_SIZE: usize = 4 * 1024 * 1024;
-    let mut buffer = Vec::with_capacity(BUFFER_SIZE);
-    unsafe {
-        buffer.set_len(BUFFER_SIZE);
+    if let Some(hidden_path) = hidden_path_opt {
+        // The hidden data is written as it is extracted
+        let mut hidden_file = io::BufWriter::new(fs::File::create(hidden_path).unwrap());
+        let mut hidden_reader = decompressor.into_hidden_reader(output_file);
+        io::copy(&mut hidden_reader, &mut hidden_file).unwrap();
+        hidden_file.flush().unwrap();
+        if json {
+            let (_, report) = hidden_reader.into_decompressor().finish_with_report();
+            println!("{}", serde_json::to_string(&report).unwrap());
+        }
+        return;
     }
+
+    const BUFFER_SIZE: usize = 4 * 1024 * 1024;
+    let mut buffer = vec![0; BUFFER_SIZE];
     loop {
         let bytes_read = decompressor.read(&mut buffer).unwrap();
         if bytes_read == 0 {
             break;
         }
 
-        output_file.write(&buffer[..bytes_read]).unwrap();
+        output_file.write_all(&buffer[..bytes_read]).unwrap();
     }
 
-    let hidden_data = decompressor.finish();
-    if let Some(hidden_path) = hidden_path_opt {
-        fs::write(hidden_path, hidden_data).unwrap();
+    if json {
+        let (_, report) = decompressor.finish_with_report();
+        println!("{}", serde_json::to_string(&report).unwrap());
     }
 }
+
+pub fn analyze(input_path: &str, json: bool) {
+    let input_file = fs::File::open(input_path).unwrap();
+    let analysis = analysis::analyze(input_file).unwrap();
+
+    if json {
+        println!("{}", format_analysis_json(&analysis));
+    } else {
+        print_analysis(&analysis);
+    }
+}
+
+pub fn patch(path: &str, hidden_path: &str) {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open(path)
+        .unwrap();
+    let hidden_data = fs::read(hidden_path).unwrap();
+    let available_bytes = patcher::patch_hidden_data(file, &hidden_data).unwrap();
+
+    eprintln!("Available hidden data bytes: {}", available_bytes);
+    if available_bytes < hidden_data.len() {
+        eprintln!("Hidden data does not fit ({} bytes)", hidden_data.len());
+    }
+}
+
+pub fn sanitize(input_path: &str, output_path: &str) {
+    let input_file = io::BufReader::new(fs::File::open(input_path).unwrap());
+    let output_file = io::BufWriter::new(fs::File::create(output_path).unwrap());
+    let changed_count = sanitizer::sanitize(input_file, output_file).unwrap();
+
+    println!("Offsets changed: {}", changed_count);
+}
+
+pub fn embed(input_path: &str, output_path: &str, hidden_path: &str) {
+    let input_file = io::BufReader::new(fs::File::open(input_path).unwrap());
+    let output_file = io::BufWriter::new(fs::File::create(output_path).unwrap());
+    let hidden_data = fs::read(hidden_path).unwrap();
+    let available_bytes = embedder::embed(input_file, output_file, &hidden_data).unwrap();
+
+    eprintln!("Available hidden data bytes: {}", available_bytes);
+    if available_bytes < hidden_data.len() {
+        eprintln!("Hidden data does not fit ({} bytes)", hidden_data.len());
+    }
+}
+
+pub fn extract(input_path: &str, hidden_path: &str) {
+    let input_file = io::BufReader::new(fs::File::open(input_path).unwrap());
+    let hidden_data = embedder::extract(input_file).unwrap();
+    fs::write(hidden_path, hidden_data).unwrap();
+}
+
+pub fn capacity(input_path: &str, policies: &[CandidatePolicy], json: bool) {
+    let data = fs::read(input_path).unwrap();
+    let capacities = capacity::get_capacity(&data, policies);
+
+    if json {
+        println!("{}", format_capacity_json(&capacities));
+    } else {
+        print_capacity(&capacities);
+    }
+}
+
+fn format_policy(policy: CandidatePolicy) -> String {
+    match policy {
+        CandidatePolicy::Longest => "longest".to_string(),
+        CandidatePolicy::WithinBytes(bytes) => format!("within {}", bytes),
+        CandidatePolicy::WithinPercent(percent) => format!("within {}%", percent),
+        CandidatePolicy::Any => "any".to_string(),
+    }
+}
+
+fn print_capacity(capacities: &[PolicyCapacity]) {
+    for capacity in capacities {
+        let estimate = if capacity.exact { "" } else { " (estimate)" };
+        println!("Policy: {}{}", format_policy(capacity.policy), estimate);
+        println!("{:>5} {:>8} {:>12}", "Block", "Size", "Bits");
+        for (i, block) in capacity.blocks.iter().enumerate() {
+            println!("{:>5} {:>8} {:>12.1}", i, block.size, block.bits);
+        }
+        println!(
+            "Total: {:.1} bits, {} bytes of hidden data",
+            capacity.bits, capacity.bytes
+        );
+        println!();
+    }
+}
+
+fn format_capacity_json(capacities: &[PolicyCapacity]) -> String {
+    let policies: Vec<serde_json::Value> = capacities
+        .iter()
+        .map(|capacity| {
+            let blocks: Vec<serde_json::Value> = capacity
+                .blocks
+                .iter()
+                .map(|block| json!({"size": block.size, "bits": block.bits}))
+                .collect();
+            json!({
+                "policy": format_policy(capacity.policy),
+                "bits": capacity.bits,
+                "bytes": capacity.bytes,
+                "exact": capacity.exact,
+                "blocks": blocks,
+            })
+        })
+        .collect();
+
+    json!({ "policies": policies }).to_string()
+}
+
+fn print_analysis(analysis: &Analysis) {
+    println!(
+        "{:>5} {:>8} {:>8} {:>8} {:>11} {:>10} {:>9}",
+        "Block", "Size", "Matches", "Choices", "Non-nearest", "Est. bits", "Suspicion"
+    );
+    for (i, block) in analysis.blocks.iter().enumerate() {
+        println!(
+            "{:>5} {:>8} {:>8} {:>8} {:>10.1}% {:>10.0} {:>9.3}",
+            i,
+            block.size,
+            block.match_count,
+            block.choice_count,
+            block.non_nearest_fraction() * 100.0,
+            block.estimated_bits(),
+            block.suspicion()
+        );
+    }
+
+    let histogram = analysis.rank_histogram();
+    let last_rank = histogram.iter().rposition(|count| *count > 0).unwrap_or(0);
+    let ranks: Vec<String> = histogram[..=last_rank]
+        .iter()
+        .enumerate()
+        .map(|(rank, count)| format!("{}: {}", rank, count))
+        .collect();
+
+    println!();
+    println!(
+        "lz4stego header: {}",
+        if analysis.stego_header { "yes" } else { "no" }
+    );
+    println!(
+        "Recency ranks of the chosen candidates: {}",
+        ranks.join(", ")
+    );
+    println!(
+        "Non-nearest choices: {:.1}% of {}",
+        analysis.non_nearest_fraction() * 100.0,
+        analysis.choice_count()
+    );
+    println!("Estimated hidden bits: {:.0}", analysis.estimated_bits());
+    println!("Suspicion: {:.3}", analysis.suspicion());
+}
+
+/// Non-finite scores are written as `null`.
+fn format_analysis_json(analysis: &Analysis) -> String {
+    let blocks: Vec<serde_json::Value> = analysis
+        .blocks
+        .iter()
+        .map(|block| {
+            json!({
+                "compressed": block.compressed,
+                "size": block.size,
+                "matches": block.match_count,
+                "choices": block.choice_count,
+                "non_nearest": block.non_nearest_count,
+                "non_longest": block.non_longest_count,
+                "non_nearest_fraction": block.non_nearest_fraction(),
+                "rank_histogram": block.rank_histogram,
+                "estimated_bits": block.estimated_bits(),
+                "suspicion": block.suspicion(),
+            })
+        })
+        .collect();
+
+    json!({
+        "stego_header": analysis.stego_header,
+        "choices": analysis.choice_count(),
+        "non_nearest_fraction": analysis.non_nearest_fraction(),
+        "rank_histogram": analysis.rank_histogram(),
+        "estimated_bits": analysis.estimated_bits(),
+        "suspicion": analysis.suspicion(),
+        "blocks": blocks,
+    })
+    .to_string()
+}
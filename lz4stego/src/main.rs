@@ -1,8 +1,13 @@
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, SubCommand};
+use liblz4stego::builder::BlockMaxSize;
+use liblz4stego::candidate_policy::CandidatePolicy;
+use liblz4stego::offset_distribution::OffsetDistribution;
 use log::{Level, LevelFilter, Metadata, Record, SetLoggerError};
 
 mod library_ctrl;
 
+use library_ctrl::{CompressionMode, FrameSettings, HiddenChannels, Summary};
+
 pub const PROJ_NAME: &str = env!("CARGO_PKG_NAME");
 pub const PROJ_VERSION: &str = env!("CARGO_PKG_VERSION");
 pub const PROJ_AUTHORS: &str = env!("CARGO_PKG_AUTHORS");
@@ -34,11 +39,165 @@ pub fn init_logging() -> Result<(), SetLoggerError> {
     log::set_logger(&LOGGER).map(|()| log::set_max_level(LevelFilter::Debug))
 }
 
+fn parse_candidate_policy(within: &str) -> CandidatePolicy {
+    if let Some(percent) = within.strip_suffix('%') {
+        let percent = percent
+            .parse::<u32>()
+            .ok()
+            .filter(|percent| *percent <= 100)
+            .expect("Percentage must be an integer between 0 and 100");
+        CandidatePolicy::WithinPercent(percent)
+    } else {
+        let bytes = within
+            .parse::<u32>()
+            .expect("Byte count must be a non-negative integer");
+        CandidatePolicy::WithinBytes(bytes)
+    }
+}
+
+fn parse_block_channel(block_channel: &str) -> (usize, usize) {
+    let (block_size, range) = block_channel
+        .split_once(':')
+        .expect("Block channel must be given as BLOCK_SIZE:RANGE");
+    let block_size = block_size
+        .parse::<usize>()
+        .expect("Block size must be an integer");
+    let range = range.parse::<usize>().expect("Range must be an integer");
+    if block_size > 4 * 1024 * 1024 - 12 || range < 2 || range > block_size.min(65535) {
+        panic!("Block size must be at most 4194292 and range between 2 and min(block size, 65535)");
+    }
+
+    (block_size, range)
+}
+
+fn parse_block_size(block_size_id: &str) -> BlockMaxSize {
+    match block_size_id {
+        "4" => BlockMaxSize::Max64KB,
+        "5" => BlockMaxSize::Max256KB,
+        "6" => BlockMaxSize::Max1MB,
+        "7" => BlockMaxSize::Max4MB,
+        _ => panic!("Block size id must be between 4 and 7"),
+    }
+}
+
 fn main() {
     let matches = App::new(PROJ_NAME)
         .version(PROJ_VERSION)
         .author(PROJ_AUTHORS)
         .about("LZ4 compressor and decompressor with steganography")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .subcommand(
+            SubCommand::with_name("analyze")
+                .about("Score an LZ4 file for likely hidden data in its match offsets")
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
+                )
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("input filename")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("capacity")
+                .about("Count how many bits and bytes of data can be hidden in a file with each candidate policy, per block and in total, without compressing it to a file")
+                .arg(
+                    Arg::with_name("within")
+                        .long("within")
+                        .value_name("BYTES|PERCENT%")
+                        .multiple(true)
+                        .number_of_values(1)
+                        .help("Also count the capacity when accepting matches at most BYTES or PERCENT% shorter than the longest one"),
+                )
+                .arg(
+                    Arg::with_name("json")
+                        .long("json")
+                        .help("Print the report as JSON"),
+                )
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("input filename")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("patch")
+                .about("Replace the data hidden in a file compressed in the default mode, rewriting only its match offsets in place")
+                .arg(
+                    Arg::with_name("hidden")
+                        .short("i")
+                        .long("hidden")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Hidden data file path"),
+                )
+                .arg(
+                    Arg::with_name("FILE")
+                        .help("LZ4 file to modify")
+                        .required(true)
+                        .index(1),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("sanitize")
+                .about("Point every match of an LZ4 file at the nearest candidate, removing data hidden in the offsets")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("input filename")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .help("output filename")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("embed")
+                .about("Hide data in the match offsets of an existing LZ4 file, keeping its frame settings, blocks and sequences")
+                .arg(
+                    Arg::with_name("hidden")
+                        .short("i")
+                        .long("hidden")
+                        .value_name("FILE")
+                        .required(true)
+                        .help("Hidden data file path"),
+                )
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("input filename")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("OUTPUT")
+                        .help("output filename")
+                        .required(true)
+                        .index(2),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("extract")
+                .about("Recover the data hidden with the embed subcommand")
+                .arg(
+                    Arg::with_name("INPUT")
+                        .help("input filename")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::with_name("HIDDEN")
+                        .help("hidden data output filename")
+                        .required(true)
+                        .index(2),
+                ),
+        )
         .arg(
             Arg::with_name("decompress")
                 .short("d")
@@ -51,6 +210,11 @@ fn main() {
                 .long("count")
                 .help("Count how many bytes of data can be hidden"),
         )
+        .arg(
+            Arg::with_name("json")
+                .long("json")
+                .help("Print a report of the compression or decompression as JSON"),
+        )
         .arg(
             Arg::with_name("hidden")
                 .short("i")
@@ -64,6 +228,109 @@ fn main() {
                 .long("prefer-hidden")
                 .help("Prefer hidden data capacity over compression ratio. Must be set for decompressing as well"),
         )
+        .arg(
+            Arg::with_name("within")
+                .long("within")
+                .value_name("BYTES|PERCENT%")
+                .conflicts_with_all(&["prefer-hidden", "hc"])
+                .help("Accept matches at most BYTES or PERCENT% shorter than the longest one. Stored in the output, so it is not needed for decompressing"),
+        )
+        .arg(
+            Arg::with_name("hc")
+                .long("hc")
+                .value_name("LEVEL")
+                .conflicts_with("prefer-hidden")
+                .help("High compression mode with lazy matching, checking up to LEVEL following positions for a better match"),
+        )
+        .arg(
+            Arg::with_name("fit")
+                .long("fit")
+                .conflicts_with_all(&["prefer-hidden", "within", "hc"])
+                .help("Choose the match candidate policy for each block so that the hidden data just fits with the smallest size cost. Stored in the output, so it is not needed for decompressing"),
+        )
+        .arg(
+            Arg::with_name("literal-channel")
+                .long("literal-channel")
+                .value_name("MAX_LENGTH")
+                .conflicts_with("hc")
+                .help("Hide additional data by emitting some matches of at most MAX_LENGTH bytes as literals. Stored in the output, so it is not needed for decompressing"),
+        )
+        .arg(
+            Arg::with_name("truncation")
+                .long("truncation")
+                .value_name("MAX_BYTES")
+                .help("Hide additional data by cutting up to MAX_BYTES bytes off the end of each match. Stored in the output, so it is not needed for decompressing"),
+        )
+        .arg(
+            Arg::with_name("block-channel")
+                .long("block-channel")
+                .value_name("BLOCK_SIZE:RANGE")
+                .help("Hide additional data by making each block up to RANGE-1 bytes shorter than BLOCK_SIZE. Stored in the output, so it is not needed for decompressing"),
+        )
+        .arg(
+            Arg::with_name("natural-offsets")
+                .long("natural-offsets")
+                .conflicts_with("decompress")
+                .help("Prefer the nearest match candidates like the reference lz4 does, making the output harder to detect at the cost of capacity. Stored in the output, so it is not needed for decompressing"),
+        )
+        .arg(
+            Arg::with_name("reference")
+                .long("reference")
+                .conflicts_with_all(&["prefer-hidden", "within", "hc", "fit", "literal-channel", "truncation", "block-channel", "natural-offsets"])
+                .help("Produce the same output as the reference lz4 with the --fast acceleration factor, except for the match offsets. Must be set for decompressing as well"),
+        )
+        .arg(
+            Arg::with_name("trellis")
+                .long("trellis")
+                .value_name("RATIO")
+                .conflicts_with_all(&["prefer-hidden", "within", "hc", "fit", "literal-channel", "truncation", "block-channel", "natural-offsets", "reference"])
+                .help("Hide one bit per RATIO matches with several candidates, taking the nearest candidate for as many matches as possible. Stored in the output, so it is not needed for decompressing"),
+        )
+        .arg(
+            Arg::with_name("dry-run")
+                .long("dry-run")
+                .conflicts_with("decompress")
+                .help("Compress without writing the output and report whether the hidden data fits"),
+        )
+        .arg(
+            Arg::with_name("fast")
+                .long("fast")
+                .value_name("ACCELERATION")
+                .default_value("1")
+                .help("Acceleration factor of the LZ4_compress_fast parse used when no hidden data is given; higher is faster but compresses worse"),
+        )
+        .arg(
+            Arg::with_name("block-size")
+                .short("B")
+                .long("block-size")
+                .value_name("ID")
+                .conflicts_with("decompress")
+                .help("Block maximum size as in lz4: 4 = 64 KiB, 5 = 256 KiB, 6 = 1 MiB, 7 = 4 MiB"),
+        )
+        .arg(
+            Arg::with_name("linked-blocks")
+                .long("linked-blocks")
+                .conflicts_with("decompress")
+                .help("Mark the blocks as linked in the frame descriptor"),
+        )
+        .arg(
+            Arg::with_name("block-checksum")
+                .long("block-checksum")
+                .conflicts_with("decompress")
+                .help("Add a checksum to every block"),
+        )
+        .arg(
+            Arg::with_name("no-frame-crc")
+                .long("no-frame-crc")
+                .conflicts_with("decompress")
+                .help("Leave out the content checksum"),
+        )
+        .arg(
+            Arg::with_name("content-size")
+                .long("content-size")
+                .conflicts_with("decompress")
+                .help("Store the size of the input in the frame descriptor"),
+        )
         .arg(
             Arg::with_name("verbose")
                 .short("v")
@@ -79,26 +346,170 @@ fn main() {
         .arg(
             Arg::with_name("OUTPUT")
                 .help("output filename")
-                .required(true)
+                .required_unless("dry-run")
                 .index(2),
         )
         .get_matches();
 
+    if let Some(analyze_matches) = matches.subcommand_matches("analyze") {
+        library_ctrl::analyze(
+            analyze_matches.value_of("INPUT").unwrap(),
+            analyze_matches.is_present("json"),
+        );
+        return;
+    }
+    if let Some(capacity_matches) = matches.subcommand_matches("capacity") {
+        let mut policies = vec![CandidatePolicy::Longest, CandidatePolicy::Any];
+        if let Some(within) = capacity_matches.values_of("within") {
+            policies.extend(within.map(parse_candidate_policy));
+        }
+        library_ctrl::capacity(
+            capacity_matches.value_of("INPUT").unwrap(),
+            &policies,
+            capacity_matches.is_present("json"),
+        );
+        return;
+    }
+    if let Some(patch_matches) = matches.subcommand_matches("patch") {
+        library_ctrl::patch(
+            patch_matches.value_of("FILE").unwrap(),
+            patch_matches.value_of("hidden").unwrap(),
+        );
+        return;
+    }
+    if let Some(sanitize_matches) = matches.subcommand_matches("sanitize") {
+        library_ctrl::sanitize(
+            sanitize_matches.value_of("INPUT").unwrap(),
+            sanitize_matches.value_of("OUTPUT").unwrap(),
+        );
+        return;
+    }
+    if let Some(embed_matches) = matches.subcommand_matches("embed") {
+        library_ctrl::embed(
+            embed_matches.value_of("INPUT").unwrap(),
+            embed_matches.value_of("OUTPUT").unwrap(),
+            embed_matches.value_of("hidden").unwrap(),
+        );
+        return;
+    }
+    if let Some(extract_matches) = matches.subcommand_matches("extract") {
+        library_ctrl::extract(
+            extract_matches.value_of("INPUT").unwrap(),
+            extract_matches.value_of("HIDDEN").unwrap(),
+        );
+        return;
+    }
+
     let input = matches.value_of("INPUT").unwrap();
-    let output = matches.value_of("OUTPUT").unwrap();
+    let output = matches.value_of("OUTPUT");
     let hidden = matches.value_of("hidden");
     let decompress = matches.is_present("decompress");
     let count = matches.is_present("count");
+    let json = matches.is_present("json");
     let prefer_hidden = matches.is_present("prefer-hidden");
     let verbose = matches.is_present("verbose");
+    let acceleration = matches
+        .value_of("fast")
+        .unwrap()
+        .parse::<u32>()
+        .expect("Acceleration must be a positive integer");
+    let candidate_policy = match matches.value_of("within") {
+        Some(within) => parse_candidate_policy(within),
+        None => CandidatePolicy::from_prefer_hidden(prefer_hidden),
+    };
+    let hc_level = matches
+        .value_of("hc")
+        .map(|level| level.parse::<usize>().expect("Level must be an integer"));
+    let fit = matches.is_present("fit");
+    let reference = matches.is_present("reference");
+    let trellis_ratio = matches.value_of("trellis").map(|ratio| {
+        ratio
+            .parse::<usize>()
+            .ok()
+            .filter(|ratio| *ratio > 0)
+            .expect("Ratio must be a positive integer")
+    });
+    let dry_run = matches.is_present("dry-run");
+    let match_truncation = matches.value_of("truncation").map(|max_bytes| {
+        max_bytes
+            .parse::<usize>()
+            .expect("Maximum truncation must be an integer")
+    });
+    let block_channel = matches.value_of("block-channel").map(parse_block_channel);
+    let offset_distribution = if matches.is_present("natural-offsets") {
+        Some(OffsetDistribution::reference())
+    } else {
+        None
+    };
+    let frame = FrameSettings {
+        block_size: matches.value_of("block-size").map(parse_block_size),
+        linked_blocks: matches.is_present("linked-blocks"),
+        block_checksum: matches.is_present("block-checksum"),
+        content_checksum: !matches.is_present("no-frame-crc"),
+        content_size: matches.is_present("content-size"),
+    };
+    let literal_channel = matches.value_of("literal-channel").map(|max_length| {
+        max_length
+            .parse::<usize>()
+            .expect("Maximum length must be an integer")
+    });
 
     if verbose {
         init_logging().unwrap();
     }
 
     if decompress {
-        library_ctrl::decompress(input, output, hidden, prefer_hidden);
+        library_ctrl::decompress(
+            input,
+            output.unwrap(),
+            hidden,
+            prefer_hidden,
+            reference,
+            json,
+        );
     } else {
-        library_ctrl::compress(input, output, hidden, count, prefer_hidden);
+        let mode = if reference {
+            CompressionMode::Reference(acceleration)
+        } else if let Some(ratio) = trellis_ratio {
+            CompressionMode::Trellis(ratio)
+        } else if let Some(level) = hc_level {
+            CompressionMode::Hc(level)
+        } else if fit {
+            CompressionMode::Planned
+        } else if hidden.is_some()
+            || count
+            || dry_run
+            || literal_channel.is_some()
+            || match_truncation.is_some()
+            || block_channel.is_some()
+            || offset_distribution.is_some()
+        {
+            CompressionMode::Hidden(candidate_policy)
+        } else {
+            CompressionMode::Fast(acceleration)
+        };
+        let output = if dry_run { None } else { output };
+        let summary = if json {
+            Summary::Json
+        } else if count {
+            Summary::Count
+        } else {
+            Summary::None
+        };
+
+        library_ctrl::compress(
+            input,
+            output,
+            hidden,
+            summary,
+            mode,
+            HiddenChannels {
+                literal_channel,
+                match_truncation,
+                block_channel,
+                offset_distribution,
+            },
+            frame,
+        );
     }
 }
@@ -0,0 +1,27 @@
+#!/bin/sh
+# Measures REFERENCE_WEIGHTS of liblz4stego/src/offset_distribution.rs: the
+# recency ranks of the matches chosen by `lz4 -1` (v1.9.4) on the sources of
+# the libc 0.2.190 crate, scaled to 10000 choices. Needs curl, lz4, python3
+# and sha256sum; run from the root of the repository.
+set -eu
+
+CRATE_URL=https://static.crates.io/crates/libc/libc-0.2.190.crate
+CRATE_SHA256=ce5d3ddc6d3fa000eb1536d85e147bfe31aacaba692ed6a876f95cb7c855be78
+RANKS=24
+
+work_dir=$(mktemp -d)
+trap 'rm -rf "$work_dir"' EXIT
+
+curl -sSfL -o "$work_dir/libc.crate" "$CRATE_URL"
+echo "$CRATE_SHA256  $work_dir/libc.crate" | sha256sum -c --quiet
+gzip -dc "$work_dir/libc.crate" > "$work_dir/libc.tar"
+lz4 -q -1 "$work_dir/libc.tar" "$work_dir/libc.tar.lz4"
+
+cargo run -q --release -p lz4stego -- analyze --json "$work_dir/libc.tar.lz4" |
+    python3 -c '
+import json, sys
+ranks = int(sys.argv[1])
+histogram = json.load(sys.stdin)["rank_histogram"][:ranks]
+total = sum(histogram)
+print([max(1, round(count * 10000 / total)) for count in histogram])
+' "$RANKS"